@@ -0,0 +1,443 @@
+//! `AsyncInternalApi` implementations for the database and the virtual database.
+//! Every operation opens its own handle on the partition file, so concurrent
+//! requests never share a file cursor.
+use crate::{
+    compression,
+    db::Database,
+    namespace,
+    preamble::EncryptionLevel,
+    scrub,
+    utils::{timestamp, AsyncInternalApi, AsyncSharedApi},
+    virtual_db::{
+        fits, latest_keys, next_version, read_encoded, read_records, Partition, Record, RecordKind,
+        VirtualDatabase, VirtualItem, VirtualKey,
+    },
+    DatabaseError,
+};
+use std::{
+    io::{Cursor, Seek, SeekFrom},
+    sync::Arc,
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::RwLock,
+    task,
+};
+
+impl Partition {
+    /// Reads every record within the partition, without blocking.
+    /// The records are streamed by `read_records` on the blocking thread pool of the runtime,
+    /// so the values are skipped rather than read.
+    pub async fn records_async(&self) -> Result<Vec<Record>, DatabaseError> {
+        let (path, id, start, length) = (
+            self.path().to_path_buf(),
+            self.id,
+            self.start() as u64,
+            self.length as u64,
+        );
+        let (level, cipher) = (self.encryption(), self.cipher()?.cloned());
+        task::spawn_blocking(move || {
+            let mut file = std::fs::File::open(path)?;
+            file.seek(SeekFrom::Start(start))?;
+            let (records, _) = read_records(
+                &mut std::io::BufReader::new(file),
+                id,
+                length,
+                level,
+                cipher.as_ref(),
+            )?;
+            Ok(records)
+        })
+        .await
+        .map_err(|error| DatabaseError::Implementation(error.to_string()))?
+    }
+
+    /// Reads the value of the key, stored at its location, without blocking.
+    pub async fn read_at_async(&self, key: &VirtualKey) -> Result<Vec<u8>, DatabaseError> {
+        let mut file = File::open(self.path()).await?;
+        let position = self.start() as u64 + key.location.offset;
+        file.seek(SeekFrom::Start(position)).await?;
+        // the value is read as it is written, then opened the same way `read_at` does.
+        let (mut written, length) = match self.encryption() {
+            EncryptionLevel::Full => {
+                let length = file.read_u64().await?;
+                (length.to_be_bytes().to_vec(), length)
+            }
+            _ => {
+                let encoding = file.read_u8().await?;
+                let size = file.read_u128().await?;
+                let mut prefix = vec![encoding];
+                prefix.extend_from_slice(&size.to_be_bytes());
+                (prefix, size.try_into().unwrap_or(u64::MAX))
+            }
+        };
+        let prefix = written.len();
+        let remaining = self.end().saturating_sub(position + prefix as u64);
+        written.resize(prefix + fits(length, remaining)?, 0);
+        file.read_exact(&mut written[prefix..]).await?;
+        let remaining = written.len() as u64;
+        let (encoding, encoded, _) = read_encoded(
+            &mut Cursor::new(written),
+            remaining,
+            &key.name,
+            self.encryption(),
+            self.cipher()?,
+        )?;
+        compression::decode(encoding, encoded, &self.dictionaries)
+    }
+
+    /// Finds the current state of a key within the partition, without blocking.
+    pub async fn find_async(&self, key_name: &str) -> Result<Option<VirtualKey>, DatabaseError> {
+        if !self.may_contain(key_name) {
+            return Ok(None);
+        }
+        let records = self.records_async().await?;
+        Ok(latest_keys(records.into_iter().filter(|record| record.name == key_name)).pop())
+    }
+
+    /// Appends a record to the end of the partition, without blocking.
+    /// The value is compressed (and sealed) on the calling task, only the writes are awaited.
+    pub async fn append_async(
+        &mut self,
+        kind: RecordKind,
+        sequence: u64,
+        version: u64,
+        key_name: String,
+        value: &[u8],
+    ) -> Result<VirtualKey, DatabaseError> {
+        let pending = self.prepare_append(kind, sequence, version, key_name, value)?;
+        let mut file = OpenOptions::new().write(true).open(self.path()).await?;
+
+        // the filter is written before the record, so it never misses a key that was written.
+        for (position, byte) in pending.bloom_writes.iter() {
+            file.seek(SeekFrom::Start(*position)).await?;
+            file.write_all(&[*byte]).await?;
+        }
+        file.seek(SeekFrom::Start(pending.position)).await?;
+        file.write_all(&pending.record).await?;
+        file.flush().await?;
+
+        Ok(self.finish_append(pending))
+    }
+}
+
+impl AsyncInternalApi for Partition {
+    type KeyKind = VirtualKey;
+    type ValueKind = VirtualItem;
+
+    async fn get(&mut self, key_name: String) -> Result<Self::ValueKind, DatabaseError> {
+        let key = self
+            .find_async(&key_name)
+            .await?
+            .ok_or_else(|| DatabaseError::KeyNotFound(key_name.clone()))?;
+        let data = self.read_at_async(&key).await?;
+        Ok(VirtualItem {
+            key: key_name,
+            location: key.location,
+            length: data.len(),
+            version: key.version,
+            data,
+        })
+    }
+
+    async fn set(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<Self::KeyKind, DatabaseError> {
+        let sequence = self.sequence + 1;
+        let version = next_version(self.find_async(&key_name).await?.as_ref());
+        self.append_async(RecordKind::Set, sequence, version, key_name, &value)
+            .await
+    }
+
+    async fn add(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<Self::KeyKind, DatabaseError> {
+        if self.find_async(&key_name).await?.is_some() {
+            return Err(DatabaseError::KeyAlreadyExists(key_name));
+        }
+        AsyncInternalApi::set(self, key_name, value).await
+    }
+
+    async fn remove(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        let existing = match self.find_async(&key_name).await? {
+            Some(existing) => existing,
+            None => return Ok(false),
+        };
+        let sequence = self.sequence + 1;
+        let version = next_version(Some(&existing));
+        self.append_async(RecordKind::Remove, sequence, version, key_name, &[])
+            .await?;
+        Ok(true)
+    }
+
+    async fn fetch_keys(&mut self) -> Result<Vec<Self::KeyKind>, DatabaseError> {
+        Ok(latest_keys(self.records_async().await?))
+    }
+}
+
+impl VirtualDatabase {
+    /// Finds the key in the cache, or in the partitions, without blocking.
+    async fn locate_async(&mut self, key_name: &str) -> Result<Option<VirtualKey>, DatabaseError> {
+        let key = self.find_shared(key_name).await?;
+        if let Some(key) = key.as_ref() {
+            self.keys.insert(key.clone());
+        }
+        Ok(key)
+    }
+
+    /// Finds the key in the cache, or in the partitions, without caching what was found.
+    async fn find_shared(&self, key_name: &str) -> Result<Option<VirtualKey>, DatabaseError> {
+        if let Some(key) = self.cached(key_name) {
+            return Ok(Some(key));
+        }
+        for partition in self.parts.iter() {
+            if let Some(key) = partition.find_async(key_name).await? {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the value of a key that was found, without blocking.
+    async fn read_async(
+        &self,
+        key_name: String,
+        key: VirtualKey,
+    ) -> Result<VirtualItem, DatabaseError> {
+        let index = self.partition_index(key.location.id)?;
+        let data = self.parts[index].read_at_async(&key).await?;
+        Ok(VirtualItem {
+            key: key_name,
+            location: key.location,
+            length: data.len(),
+            version: key.version,
+            data,
+        })
+    }
+
+    /// Writes a record for the key and notifies the change feed, without blocking.
+    async fn write_async(
+        &mut self,
+        kind: RecordKind,
+        key_name: String,
+        value: &[u8],
+        existing: Option<&VirtualKey>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        let index = self.target_partition(existing)?;
+        let sequence = self.sequence + 1;
+        let key = self.parts[index]
+            .append_async(kind, sequence, next_version(existing), key_name, value)
+            .await?;
+        self.finish_write(kind, &key);
+        if kind == RecordKind::Remove && self.secure_delete {
+            self.scrub_removed_async(index, &key).await?;
+        }
+        Ok(key)
+    }
+
+    /// Overwrites the earlier records of a removed key, like `scrub_removed`, without blocking.
+    /// The records are overwritten on the blocking thread pool of the runtime.
+    async fn scrub_removed_async(
+        &mut self,
+        index: usize,
+        removed: &VirtualKey,
+    ) -> Result<u64, DatabaseError> {
+        let partition = &self.parts[index];
+        let records: Vec<Record> = partition
+            .records_async()
+            .await?
+            .into_iter()
+            .filter(|record| record.name == removed.name && record.sequence < removed.sequence)
+            .collect();
+        if records.is_empty() {
+            return Ok(0);
+        }
+        let (overwrites, scrubbed) = partition.overwrites(&records)?;
+        let path = partition.path().to_path_buf();
+        task::spawn_blocking(move || scrub::overwrite(&path, &overwrites))
+            .await
+            .map_err(|error| DatabaseError::Implementation(error.to_string()))??;
+        Ok(scrubbed)
+    }
+}
+
+impl AsyncInternalApi for VirtualDatabase {
+    type KeyKind = VirtualKey;
+    type ValueKind = VirtualItem;
+
+    async fn get(&mut self, key_name: String) -> Result<Self::ValueKind, DatabaseError> {
+        let key = match namespace::is_reserved(&key_name) {
+            true => None,
+            false => self.locate_async(&key_name).await?,
+        }
+        .ok_or_else(|| DatabaseError::KeyNotFound(key_name.clone()))?;
+        self.read_async(key_name, key).await
+    }
+
+    async fn set(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<Self::KeyKind, DatabaseError> {
+        if namespace::is_reserved(&key_name) {
+            return Err(DatabaseError::InvalidKey(key_name));
+        }
+        let existing = self.locate_async(&key_name).await?;
+        self.write_async(RecordKind::Set, key_name, &value, existing.as_ref())
+            .await
+    }
+
+    async fn add(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<Self::KeyKind, DatabaseError> {
+        if namespace::is_reserved(&key_name) {
+            return Err(DatabaseError::InvalidKey(key_name));
+        }
+        if self.locate_async(&key_name).await?.is_some() {
+            return Err(DatabaseError::KeyAlreadyExists(key_name));
+        }
+        self.write_async(RecordKind::Set, key_name, &value, None)
+            .await
+    }
+
+    async fn remove(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        if namespace::is_reserved(&key_name) {
+            return Ok(false);
+        }
+        match self.locate_async(&key_name).await? {
+            Some(existing) => {
+                self.write_async(RecordKind::Remove, key_name, &[], Some(&existing))
+                    .await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn fetch_keys(&mut self) -> Result<Vec<Self::KeyKind>, DatabaseError> {
+        self.fetch_keys_shared().await
+    }
+}
+
+impl AsyncSharedApi for VirtualDatabase {
+    async fn get_shared(&self, key_name: String) -> Result<Self::ValueKind, DatabaseError> {
+        let key = match namespace::is_reserved(&key_name) {
+            true => None,
+            false => self.find_shared(&key_name).await?,
+        }
+        .ok_or_else(|| DatabaseError::KeyNotFound(key_name.clone()))?;
+        self.read_async(key_name, key).await
+    }
+
+    async fn fetch_keys_shared(&self) -> Result<Vec<Self::KeyKind>, DatabaseError> {
+        let mut keys = Vec::new();
+        for partition in self.parts.iter() {
+            keys.extend(
+                latest_keys(partition.records_async().await?)
+                    .into_iter()
+                    .filter(|key| !namespace::is_reserved(&key.name)),
+            );
+        }
+        Ok(keys)
+    }
+}
+
+/// The async API of a database, with the same access checks as its `InternalApi`.
+impl AsyncInternalApi for Database {
+    type KeyKind = VirtualKey;
+    type ValueKind = VirtualItem;
+
+    async fn get(&mut self, key_name: String) -> Result<Self::ValueKind, DatabaseError> {
+        AsyncInternalApi::get(self.virtual_db()?, key_name).await
+    }
+
+    async fn set(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<Self::KeyKind, DatabaseError> {
+        let key = AsyncInternalApi::set(self.writable()?, key_name, value).await?;
+        self.header.last_write = timestamp();
+        Ok(key)
+    }
+
+    async fn add(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<Self::KeyKind, DatabaseError> {
+        let key = AsyncInternalApi::add(self.writable()?, key_name, value).await?;
+        self.header.last_write = timestamp();
+        Ok(key)
+    }
+
+    async fn remove(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        let removed = AsyncInternalApi::remove(self.writable()?, key_name).await?;
+        if removed {
+            self.header.last_write = timestamp();
+        }
+        Ok(removed)
+    }
+
+    async fn fetch_keys(&mut self) -> Result<Vec<Self::KeyKind>, DatabaseError> {
+        AsyncInternalApi::fetch_keys(self.virtual_db()?).await
+    }
+}
+
+impl AsyncSharedApi for Database {
+    async fn get_shared(&self, key_name: String) -> Result<Self::ValueKind, DatabaseError> {
+        self.shared_virtual_db()?.get_shared(key_name).await
+    }
+
+    async fn fetch_keys_shared(&self) -> Result<Vec<Self::KeyKind>, DatabaseError> {
+        self.shared_virtual_db()?.fetch_keys_shared().await
+    }
+}
+
+/// A database shared between many tasks.
+/// Every task keeps its own clone of the `Arc`. Reads share the lock, so they run at the
+/// same time, while writes hold it alone for the duration of the operation.
+impl<T> AsyncInternalApi for Arc<RwLock<T>>
+where
+    T: AsyncSharedApi + Send + Sync,
+    T::KeyKind: Send,
+    T::ValueKind: Send,
+{
+    type KeyKind = T::KeyKind;
+    type ValueKind = T::ValueKind;
+
+    async fn get(&mut self, key_name: String) -> Result<Self::ValueKind, DatabaseError> {
+        self.read().await.get_shared(key_name).await
+    }
+
+    async fn set(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<Self::KeyKind, DatabaseError> {
+        self.write().await.set(key_name, value).await
+    }
+
+    async fn add(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<Self::KeyKind, DatabaseError> {
+        self.write().await.add(key_name, value).await
+    }
+
+    async fn remove(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        self.write().await.remove(key_name).await
+    }
+
+    async fn fetch_keys(&mut self) -> Result<Vec<Self::KeyKind>, DatabaseError> {
+        self.read().await.fetch_keys_shared().await
+    }
+}
@@ -0,0 +1,343 @@
+use crate::{
+    db::Header,
+    encryption::{self, Cipher, KeyBlock, Secret, KEY_BLOCK_LEN},
+    preamble::Preamble,
+    snapshot::Snapshot,
+    utils::GetByteLength,
+    virtual_db::{latest_keys, read_partition_head, read_raw, read_records, Partition, Record},
+    DatabaseError, MAGIC_BYTES,
+};
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use sha3::{Digest, Sha3_256};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// The version of the backup archive format.
+pub const BACKUP_VERSION: u8 = 4;
+
+/// The length of the checksum at the end of an archive.
+const CHECKSUM_LEN: u64 = 32;
+
+/// A summary of what was written to (or read from) a backup archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupSummary {
+    /// The sequence number the backup was taken at.
+    pub sequence: u64,
+    /// The amount of records in the archive.
+    pub records: u64,
+    /// The amount of keys that were set when the backup was taken.
+    pub keys: u64,
+}
+
+/// The latest record of every key, by partition id and key name: its version, and the
+/// SHA3-256 of the record as it is stored.
+pub type RecordDigests = HashMap<(u8, String), (u64, [u8; 32])>;
+
+/// Records the version and hash of a record, in place of the earlier records of its key.
+/// Records are passed in the order they are stored.
+fn digest_record(digests: &mut RecordDigests, id: u8, record: Record, raw: &[u8]) {
+    let hash = Sha3_256::digest(raw).into();
+    digests.insert((id, record.name), (record.version, hash));
+}
+
+/// A writer that hashes everything written through it.
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha3_256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes a backup archive of the snapshot.
+/// The archive is a single file with the following layout:
+///
+/// | Name       | Type       | Description                                              |
+/// | ---------- | ---------- | -------------------------------------------------------- |
+/// | magic      | `[u8; 13]` | The One-Link magic.                                      |
+/// | version    | `u8`       | The archive version, currently `4`.                      |
+/// | name       | `str`      | The database name, prefixed by its `u16` length.         |
+/// | head       | `[u8]`     | The preamble and header of the database, as they are written. |
+/// | partitions | `u8`       | The amount of partitions that follow.                    |
+/// | partition  | -          | The id, head and records of each partition.              |
+/// | summary    | -          | The sequence, record and key counts as `u64`.            |
+/// | checksum   | `[u8; 32]` | The SHA3-256 of everything before it.                    |
+///
+/// The head of every partition, and every record, is archived as it is written in the
+/// partition, prefixed by its `u64` length.
+pub fn write_archive(
+    snapshot: &Snapshot,
+    name: &str,
+    head: &[u8],
+    archive: &Path,
+) -> Result<BackupSummary, DatabaseError> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(archive)?;
+    let mut writer = HashingWriter {
+        inner: BufWriter::new(file),
+        hasher: Sha3_256::new(),
+    };
+    let mut summary = BackupSummary {
+        sequence: snapshot.sequence,
+        records: 0,
+        keys: 0,
+    };
+
+    writer.write_all(&MAGIC_BYTES)?;
+    writer.write_u8(BACKUP_VERSION)?;
+    writer.write_u16::<BE>(name.len() as u16)?;
+    writer.write_all(name.as_bytes())?;
+    // an encrypted database keeps its key block and sealed header, so the archive
+    // stays encrypted.
+    writer.write_all(head)?;
+
+    let partitions = snapshot.partitions();
+    writer.write_u8(partitions.len() as u8)?;
+    for id in partitions {
+        let mut partition = snapshot.open_partition(id)?;
+        let head = partition.head()?;
+        writer.write_u8(id)?;
+        writer.write_u64::<BE>(head.len() as u64)?;
+        writer.write_all(&head)?;
+        writer.write_u64::<BE>(partition.records.len() as u64)?;
+
+        let records = std::mem::take(&mut partition.records);
+        for record in records.iter() {
+            // records are archived as they are written, so they stay compressed (and sealed).
+            let raw = partition.raw(record)?;
+            writer.write_u64::<BE>(raw.len() as u64)?;
+            writer.write_all(&raw)?;
+        }
+        summary.records += records.len() as u64;
+        summary.keys += latest_keys(records).len() as u64;
+    }
+
+    writer.write_u64::<BE>(summary.sequence)?;
+    writer.write_u64::<BE>(summary.records)?;
+    writer.write_u64::<BE>(summary.keys)?;
+
+    let checksum = writer.hasher.clone().finalize();
+    writer.inner.write_all(&checksum)?;
+    writer.inner.flush()?;
+    writer.inner.get_ref().sync_all()?;
+    Ok(summary)
+}
+
+/// Verifies the checksum of an archive without restoring it.
+pub fn verify_archive(archive: &Path) -> Result<(), DatabaseError> {
+    let mut file = File::open(archive)?;
+    let length = file.metadata()?.len();
+    if length < CHECKSUM_LEN {
+        return Err(DatabaseError::BackupInvalid("Archive is too short"));
+    }
+
+    let mut hasher = Sha3_256::new();
+    let mut reader = BufReader::new(&mut file).take(length - CHECKSUM_LEN);
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    let mut checksum = [0; CHECKSUM_LEN as usize];
+    file.seek(SeekFrom::Start(length - CHECKSUM_LEN))?;
+    file.read_exact(&mut checksum)?;
+    if hasher.finalize().as_slice() != checksum {
+        return Err(DatabaseError::BackupInvalid(
+            "Archive checksum does not match",
+        ));
+    }
+    Ok(())
+}
+
+/// Restores the archive into a new database at `path`.
+/// The checksum is verified before anything is written.
+/// The archive of an encrypted database needs a password or keyfile, to read the header.
+/// Returns the name, header and master key of the database, the summary stored in the
+/// archive, and the digests of the archived records, see `verify_restore`.
+pub fn restore_archive(
+    archive: &Path,
+    path: &Path,
+    secret: Option<Secret>,
+) -> Result<(String, Header, Option<Cipher>, BackupSummary, RecordDigests), DatabaseError> {
+    verify_archive(archive)?;
+
+    let file = File::open(archive)?;
+    let length = file.metadata()?.len() - CHECKSUM_LEN;
+    let mut reader = BufReader::new(file.take(length));
+
+    let mut magic = [0; MAGIC_BYTES.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC_BYTES {
+        return Err(DatabaseError::InvalidDatabase);
+    }
+    if reader.read_u8()? != BACKUP_VERSION {
+        return Err(DatabaseError::BackupInvalid("Unsupported archive version"));
+    }
+    let mut name = vec![0; reader.read_u16::<BE>()? as usize];
+    reader.read_exact(&mut name)?;
+    let name = String::from_utf8(name)
+        .map_err(|_| DatabaseError::BackupInvalid("Database name is not valid UTF-8"))?;
+
+    let mut head = vec![0; Preamble::new().byte_len()];
+    reader.read_exact(&mut head)?;
+    let preamble = Preamble::create(&head)?;
+    let (header, cipher) = match (preamble.is_encrypted(), secret) {
+        (true, Some(secret)) => {
+            let mut sealed = vec![0; KEY_BLOCK_LEN + 2];
+            reader.read_exact(&mut sealed)?;
+            let header_len = Cursor::new(&sealed[KEY_BLOCK_LEN..]).read_u16::<BE>()?;
+            sealed.resize(KEY_BLOCK_LEN + 2 + header_len as usize, 0);
+            reader.read_exact(&mut sealed[KEY_BLOCK_LEN + 2..])?;
+
+            let mut cursor = Cursor::new(&sealed);
+            let cipher = KeyBlock::read(&mut cursor)?
+                .ok_or(DatabaseError::InvalidDatabase)?
+                .unlock(secret)?;
+            let header = encryption::read_header(&mut cursor, &cipher)?;
+            head.extend_from_slice(&sealed);
+            (header, Some(cipher))
+        }
+        (true, None) => return Err(DatabaseError::IncorrectPassword),
+        (false, _) => {
+            let header = Header::read(&mut reader)?;
+            header.write(&mut head)?;
+            (header, None)
+        }
+    };
+
+    // an unpartitioned database keeps its records in the database file, directly
+    // after the head of partition `0`. Otherwise the database file only has a header.
+    if header.partitioned {
+        let mut db_file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        db_file.write_all(&head)?;
+        db_file.sync_all()?;
+    }
+
+    let mut digests = RecordDigests::new();
+    for _ in 0..reader.read_u8()? {
+        let id = reader.read_u8()?;
+        let mut head = Vec::new();
+        copy_record(&mut reader, &mut head)?;
+        let level = Preamble::create(&head[..head.len().min(preamble.byte_len())])?.encryption;
+        let records = reader.read_u64::<BE>()?;
+
+        let partition_path = if header.partitioned {
+            Partition::path_for(path, &name, id)
+        } else {
+            path.to_path_buf()
+        };
+        let mut partition_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(partition_path)?;
+        let mut writer = BufWriter::new(&mut partition_file);
+        writer.write_all(&head)?;
+        let mut raw = Vec::new();
+        for _ in 0..records {
+            raw.clear();
+            copy_record(&mut reader, &mut raw)?;
+            let length = raw.len() as u64;
+            let mut record = BufReader::new(Cursor::new(&raw));
+            let record = match read_records(&mut record, id, length, level, cipher.as_ref()) {
+                Ok((mut found, valid)) if found.len() == 1 && valid == length => found.remove(0),
+                _ => return Err(DatabaseError::BackupInvalid("Archived record is corrupted")),
+            };
+            digest_record(&mut digests, id, record, &raw);
+            writer.write_all(&raw)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        partition_file.sync_all()?;
+    }
+
+    let summary = BackupSummary {
+        sequence: reader.read_u64::<BE>()?,
+        records: reader.read_u64::<BE>()?,
+        keys: reader.read_u64::<BE>()?,
+    };
+    Ok((name, header, cipher, summary, digests))
+}
+
+/// Copies a single record (or partition head) from the archive into a partition.
+fn copy_record(reader: &mut impl Read, writer: &mut impl Write) -> Result<(), DatabaseError> {
+    let length = reader.read_u64::<BE>()?;
+    let copied = std::io::copy(&mut reader.take(length), writer)?;
+    if copied != length {
+        return Err(DatabaseError::BackupInvalid("Archive ends within a record"));
+    }
+    Ok(())
+}
+
+/// Verifies that the restored records match the archive: the counts of its summary, and
+/// the version and hash of the latest record of every key, from `restore_archive`.
+/// The `header` and `cipher` are those of the restored database, from `restore_archive`.
+pub fn verify_restore(
+    path: &Path,
+    name: &str,
+    header: &Header,
+    cipher: Option<&Cipher>,
+    summary: &BackupSummary,
+    archived: &RecordDigests,
+) -> Result<(), DatabaseError> {
+    let files = if header.partitioned {
+        (0..header.partitions.unwrap_or(0))
+            .map(|id| (id, Partition::path_for(path, name, id)))
+            .collect()
+    } else {
+        vec![(0, path.to_path_buf())]
+    };
+
+    let mut records = 0;
+    let mut keys = 0;
+    let mut restored = RecordDigests::new();
+    for (id, partition_path) in files {
+        let mut partition_file = File::open(partition_path)?;
+        let (preamble, header_len, bloom) = read_partition_head(&mut partition_file)?;
+        let start = preamble.byte_len() + header_len + bloom.byte_len();
+        let length = partition_file.metadata()?.len() - start as u64;
+        let mut reader = BufReader::new(partition_file);
+        let (found, valid) = read_records(&mut reader, id, length, preamble.encryption, cipher)?;
+        if valid != length {
+            return Err(DatabaseError::BackupInvalid(
+                "Restored partition is truncated",
+            ));
+        }
+        records += found.len() as u64;
+        for record in found.iter() {
+            let raw = read_raw(&mut reader, start as u64, record)?;
+            digest_record(&mut restored, id, record.clone(), &raw);
+        }
+        keys += latest_keys(found).len() as u64;
+    }
+
+    if records != summary.records || keys != summary.keys {
+        return Err(DatabaseError::BackupInvalid(
+            "Restored database does not match the archive",
+        ));
+    }
+    if &restored != archived {
+        return Err(DatabaseError::BackupInvalid(
+            "Restored keys do not match the archive",
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! Batched reads and writes.
+//! Requests are grouped by partition, so each partition is read (or written) once,
+//! and values are read in the order they are stored for sequential I/O.
+use crate::{
+    namespace,
+    virtual_db::{next_version, RecordKind, VirtualDatabase, VirtualItem, VirtualKey},
+    DatabaseError,
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// A record waiting to be written, with the position of its request.
+type BatchRecord<'a> = (usize, u64, String, &'a [u8]);
+
+impl VirtualDatabase {
+    /// Gets many keys at once.
+    /// Returns a result for every key, in the order they were requested.
+    pub fn get_many(
+        &mut self,
+        key_names: Vec<String>,
+    ) -> Result<Vec<Result<VirtualItem, DatabaseError>>, DatabaseError> {
+        let located = self.locate_many(&key_names)?;
+
+        // the requests for every partition, by the position of the partition.
+        let mut requests: HashMap<usize, Vec<(usize, VirtualKey)>> = HashMap::new();
+        for (index, key_name) in key_names.iter().enumerate() {
+            if namespace::is_reserved(key_name) {
+                continue;
+            }
+            if let Some(key) = located.get(key_name) {
+                requests
+                    .entry(self.partition_index(key.location.id)?)
+                    .or_default()
+                    .push((index, key.clone()));
+            }
+        }
+
+        let mut results: Vec<Option<Result<VirtualItem, DatabaseError>>> =
+            key_names.iter().map(|_| None).collect();
+        for (partition, requests) in requests {
+            let keys: Vec<VirtualKey> = requests.iter().map(|(_, key)| key.clone()).collect();
+            let values = self.parts[partition].read_many(&keys)?;
+            for ((index, key), data) in requests.into_iter().zip(values) {
+                results[index] = Some(Ok(VirtualItem {
+                    key: key_names[index].clone(),
+                    location: key.location,
+                    length: data.len(),
+                    version: key.version,
+                    data,
+                }));
+            }
+        }
+
+        Ok(results
+            .into_iter()
+            .zip(key_names)
+            .map(|(result, key_name)| result.unwrap_or(Err(DatabaseError::KeyNotFound(key_name))))
+            .collect())
+    }
+
+    /// Sets many keys at once, overwriting any existing keys.
+    /// The records for every partition are written with a single write.
+    /// Returns a result for every key, in the order they were given.
+    pub fn set_many(
+        &mut self,
+        pairs: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<Result<VirtualKey, DatabaseError>>, DatabaseError> {
+        self.write_many(pairs, false)
+    }
+
+    /// Sets many keys at once like `set_many`, where the names may be reserved,
+    /// such as the names of keys within namespaces.
+    pub(crate) fn set_many_raw(
+        &mut self,
+        pairs: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<Result<VirtualKey, DatabaseError>>, DatabaseError> {
+        self.write_many(pairs, true)
+    }
+
+    fn write_many(
+        &mut self,
+        pairs: Vec<(String, Vec<u8>)>,
+        reserved: bool,
+    ) -> Result<Vec<Result<VirtualKey, DatabaseError>>, DatabaseError> {
+        let key_names: Vec<String> = pairs.iter().map(|(name, _)| name.clone()).collect();
+        let located = self.locate_many(&key_names)?;
+
+        // a key repeated in the batch stays with the partition of its first record,
+        // and every record of it gets the next version.
+        let mut targets: HashMap<&str, (usize, u64)> = HashMap::new();
+        let mut writes: BTreeMap<usize, Vec<BatchRecord>> = BTreeMap::new();
+        let mut results: Vec<Option<Result<VirtualKey, DatabaseError>>> =
+            pairs.iter().map(|_| None).collect();
+
+        for (index, (key_name, value)) in pairs.iter().enumerate() {
+            if !reserved && namespace::is_reserved(key_name) {
+                results[index] = Some(Err(DatabaseError::InvalidKey(key_name.clone())));
+                continue;
+            }
+            let (partition, version) = match targets.get(key_name.as_str()) {
+                Some((partition, version)) => (*partition, version + 1),
+                None => {
+                    let existing = located.get(key_name);
+                    (self.target_partition(existing)?, next_version(existing))
+                }
+            };
+            // the name is checked as it is stored, so a name that is too long once sealed
+            // fails on its own instead of failing the whole write.
+            if !self.parts[partition].name_fits(key_name) {
+                results[index] = Some(Err(DatabaseError::InvalidKey(key_name.clone())));
+                continue;
+            }
+            targets.insert(key_name, (partition, version));
+            writes
+                .entry(partition)
+                .or_default()
+                .push((index, version, key_name.clone(), value));
+        }
+
+        // sequence numbers are given out partition by partition,
+        // so a failed write never leaves a gap before records that were written.
+        for (partition, records) in writes {
+            let first = self.sequence + 1;
+            let indexes: Vec<usize> = records.iter().map(|record| record.0).collect();
+            let keys = self.parts[partition].append_many(
+                records
+                    .into_iter()
+                    .enumerate()
+                    .map(|(offset, (_, version, name, value))| {
+                        (RecordKind::Set, first + offset as u64, version, name, value)
+                    })
+                    .collect(),
+            )?;
+            for (index, key) in indexes.into_iter().zip(keys) {
+                self.finish_write(RecordKind::Set, &key);
+                results[index] = Some(Ok(key));
+            }
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|result| {
+                result.unwrap_or(Err(DatabaseError::Implementation(
+                    "Key was not written".to_string(),
+                )))
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,191 @@
+//! Content-addressed, deduplicated values.
+//!
+//! Values set with `set_blob` are stored once for every unique content, addressed by
+//! their SHA3-256 hash. Keys only hold the hash of their value:
+//!
+//! | Name                       | Stored as                          |
+//! | -------------------------- | ---------------------------------- |
+//! | A blob                     | `\0blob\0{hash}` -> value          |
+//! | The references to a blob   | `\0refs\0{hash}` -> `u64`          |
+//! | A key pointing at a blob   | `\0link\0{key}` -> hash            |
+//!
+//! The reference count is raised before a key points at the blob, and lowered after the
+//! key stops pointing at it, so an interrupted write can leak a blob but never loses one.
+//! A blob is removed with its last reference, and its space is reclaimed by compaction.
+use crate::{
+    namespace::RESERVED_PREFIX,
+    virtual_db::{VirtualDatabase, VirtualItem},
+    DatabaseError,
+};
+use byteorder::{ReadBytesExt, BE};
+use sha3::{Digest, Sha3_256};
+use std::io::Cursor;
+
+/// The length of the hash that addresses a blob.
+pub const HASH_LEN: usize = 32;
+
+/// The SHA3-256 hash that addresses a blob.
+pub type BlobHash = [u8; HASH_LEN];
+
+/// The prefix of the names blobs are stored under.
+pub const BLOB_PREFIX: &str = "\0blob\0";
+const REFS_PREFIX: &str = "\0refs\0";
+const LINK_PREFIX: &str = "\0link\0";
+
+/// A key pointing at a blob.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlobKey {
+    /// The name of the key.
+    pub name: String,
+    /// The hash of the blob the key points at.
+    pub hash: BlobHash,
+    /// The amount of keys pointing at the blob.
+    pub references: u64,
+}
+
+/// Hashes a value into the address of its blob.
+pub fn hash(value: &[u8]) -> BlobHash {
+    Sha3_256::digest(value).into()
+}
+
+/// The hash as lowercase hexadecimal.
+pub fn to_hex(hash: &BlobHash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The name the blob with the given hash is stored under.
+pub fn blob_key(hash: &BlobHash) -> String {
+    format!("{}{}", BLOB_PREFIX, to_hex(hash))
+}
+
+/// The name the reference count of the blob is stored under.
+pub fn refs_key(hash: &BlobHash) -> String {
+    format!("{}{}", REFS_PREFIX, to_hex(hash))
+}
+
+/// The name a key pointing at a blob is stored under.
+pub fn link_key(key_name: &str) -> String {
+    format!("{}{}", LINK_PREFIX, key_name)
+}
+
+/// The name of the key, if the stored key points at a blob.
+pub fn link_name(key_name: &str) -> Option<&str> {
+    key_name.strip_prefix(LINK_PREFIX)
+}
+
+/// Reads the hash stored in a key pointing at a blob.
+pub fn read_hash(value: &[u8]) -> Result<BlobHash, DatabaseError> {
+    value
+        .try_into()
+        .map_err(|_| DatabaseError::Implementation("Blob hash is invalid".to_string()))
+}
+
+impl VirtualDatabase {
+    /// Sets the key to a value that is stored once, no matter how many keys hold it.
+    /// If the key already points at another blob, that blob loses a reference.
+    pub fn set_blob(&mut self, key_name: String, value: Vec<u8>) -> Result<BlobKey, DatabaseError> {
+        if key_name.starts_with(RESERVED_PREFIX) || link_key(&key_name).len() > u16::MAX as usize {
+            return Err(DatabaseError::InvalidKey(key_name));
+        }
+        let hash = hash(&value);
+        let previous = self.link_hash(&key_name)?;
+        if previous == Some(hash) {
+            let references = self.blob_references(&hash)?;
+            return Ok(BlobKey {
+                name: key_name,
+                hash,
+                references,
+            });
+        }
+
+        let references = self.blob_references(&hash)? + 1;
+        if references == 1 {
+            self.set_raw(blob_key(&hash), &value)?;
+        }
+        self.set_raw(refs_key(&hash), &references.to_be_bytes())?;
+        self.set_raw(link_key(&key_name), &hash)?;
+        if let Some(previous) = previous {
+            self.release_blob(&previous)?;
+        }
+        Ok(BlobKey {
+            name: key_name,
+            hash,
+            references,
+        })
+    }
+
+    /// Gets the value of a key that points at a blob.
+    /// The version of the item is the version of the key, not of the blob.
+    pub fn get_blob(&mut self, key_name: String) -> Result<VirtualItem, DatabaseError> {
+        let link = match key_name.starts_with(RESERVED_PREFIX) {
+            true => return Err(DatabaseError::KeyNotFound(key_name)),
+            false => self
+                .get_raw(link_key(&key_name))
+                .map_err(|error| match error {
+                    DatabaseError::KeyNotFound(_) => DatabaseError::KeyNotFound(key_name.clone()),
+                    error => error,
+                })?,
+        };
+        let mut item = self.get_raw(blob_key(&read_hash(&link.data)?))?;
+        item.key = key_name;
+        item.version = link.version;
+        Ok(item)
+    }
+
+    /// Removes a key that points at a blob.
+    /// The blob is removed with its last reference.
+    /// Returns whether or not the key existed.
+    pub fn remove_blob(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        let hash = match self.link_hash(&key_name)? {
+            Some(hash) => hash,
+            None => return Ok(false),
+        };
+        self.remove_raw(link_key(&key_name))?;
+        self.release_blob(&hash)?;
+        Ok(true)
+    }
+
+    /// The amount of keys pointing at the blob with the given hash.
+    pub fn blob_references(&mut self, hash: &BlobHash) -> Result<u64, DatabaseError> {
+        match self.get_raw(refs_key(hash)) {
+            Ok(item) => Ok(Cursor::new(item.data).read_u64::<BE>()?),
+            Err(DatabaseError::KeyNotFound(_)) => Ok(0),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The names of every key that points at a blob, sorted.
+    pub fn fetch_blob_keys(&mut self) -> Result<Vec<String>, DatabaseError> {
+        let mut names: Vec<String> = self
+            .fetch_raw_keys()?
+            .iter()
+            .filter_map(|key| link_name(&key.name).map(str::to_string))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// The hash of the blob the key points at.
+    fn link_hash(&mut self, key_name: &str) -> Result<Option<BlobHash>, DatabaseError> {
+        if key_name.starts_with(RESERVED_PREFIX) {
+            return Ok(None);
+        }
+        match self.get_raw(link_key(key_name)) {
+            Ok(item) => Ok(Some(read_hash(&item.data)?)),
+            Err(DatabaseError::KeyNotFound(_)) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Takes a reference away from the blob, and removes the blob with its last reference.
+    fn release_blob(&mut self, hash: &BlobHash) -> Result<(), DatabaseError> {
+        let references = self.blob_references(hash)?.saturating_sub(1);
+        if references == 0 {
+            self.remove_raw(refs_key(hash))?;
+            self.remove_raw(blob_key(hash))?;
+        } else {
+            self.set_raw(refs_key(hash), &references.to_be_bytes())?;
+        }
+        Ok(())
+    }
+}
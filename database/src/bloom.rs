@@ -0,0 +1,144 @@
+use crate::DatabaseError;
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use std::io::{Read, Write};
+
+/// The smallest size of a bloom filter in bytes.
+pub const MIN_BLOOM_BYTES: usize = 1024;
+
+/// The amount of bits a bloom filter is given for every key when it is sized.
+/// At 10 bits per key, with 7 hashes, roughly 1% of misses are false positives.
+pub const BLOOM_BITS_PER_KEY: usize = 10;
+
+/// The amount of hashes used for every key.
+pub const BLOOM_HASHES: u8 = 7;
+
+/// A bloom filter over the key names within a partition.
+/// The filter can tell for certain that a key is **not** in a partition,
+/// so lookups can skip reading partitions that can not hold the key.
+///
+/// | Name    | Type   | Byte Length | Description                          |
+/// | ------- | ------ | ----------- | ------------------------------------ |
+/// | hashes  | `u8`   | 1           | The amount of hashes per key.        |
+/// | length  | `u32`  | 4           | The length of the bits in bytes.     |
+/// | bits    | `[u8]` | length      | The bits of the filter.              |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    /// The amount of hashes per key.
+    pub hashes: u8,
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    /// The amount of bytes before the bits of the filter.
+    pub const PREFIX_LEN: usize = 1 + 4;
+
+    /// Creates an empty filter sized for the given amount of keys.
+    pub fn with_capacity(keys: usize) -> Self {
+        let bytes = (keys * BLOOM_BITS_PER_KEY)
+            .div_ceil(8)
+            .next_power_of_two()
+            .max(MIN_BLOOM_BYTES);
+        Self {
+            hashes: BLOOM_HASHES,
+            bits: vec![0; bytes],
+        }
+    }
+
+    /// The bit positions of a key.
+    /// The hashes are derived from two FNV-1a hashes, so they are stable across
+    /// platforms and versions of the library.
+    fn positions(&self, key: &[u8]) -> Vec<usize> {
+        let h1 = fnv1a(key, 0xcbf2_9ce4_8422_2325);
+        let h2 = fnv1a(key, 0x84222325_cbf29ce4) | 1;
+        let bits = (self.bits.len() * 8) as u64;
+        (0..self.hashes as u64)
+            .map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+            .collect()
+    }
+
+    /// Adds a key to the filter.
+    /// Returns the indexes of the bytes that changed, so they can be written back.
+    pub fn insert(&mut self, key: &str) -> Vec<usize> {
+        self.insert_bytes(key.as_bytes())
+    }
+
+    /// Adds a key that is not a name, such as the token of an encrypted name, see `insert`.
+    pub fn insert_bytes(&mut self, key: &[u8]) -> Vec<usize> {
+        let mut changed = Vec::new();
+        for position in self.positions(key) {
+            let (byte, bit) = (position / 8, 1 << (position % 8));
+            if self.bits[byte] & bit == 0 {
+                self.bits[byte] |= bit;
+                changed.push(byte);
+            }
+        }
+        changed
+    }
+
+    /// Whether or not the key may be in the filter.
+    /// `false` means the key was never added.
+    pub fn contains(&self, key: &str) -> bool {
+        self.contains_bytes(key.as_bytes())
+    }
+
+    /// Whether or not a key added by `insert_bytes` may be in the filter.
+    pub fn contains_bytes(&self, key: &[u8]) -> bool {
+        self.positions(key)
+            .into_iter()
+            .all(|position| self.bits[position / 8] & (1 << (position % 8)) != 0)
+    }
+
+    /// The value of a byte within the filter.
+    pub fn byte(&self, index: usize) -> u8 {
+        self.bits[index]
+    }
+
+    /// The ratio of bits that are set, between `0.0` and `1.0`.
+    /// The fuller the filter, the more false positives it gives.
+    pub fn saturation(&self) -> f64 {
+        let set: u32 = self.bits.iter().map(|byte| byte.count_ones()).sum();
+        set as f64 / (self.bits.len() * 8) as f64
+    }
+
+    /// Reads a filter from the given reader.
+    pub fn read(reader: &mut dyn Read) -> Result<Self, DatabaseError> {
+        let hashes = reader.read_u8()?;
+        let length = reader.read_u32::<BE>()? as usize;
+        if hashes == 0 || length == 0 {
+            return Err(DatabaseError::Implementation(
+                "Partition bloom filter is empty".to_string(),
+            ));
+        }
+        let mut bits = vec![0; length];
+        reader.read_exact(&mut bits)?;
+        Ok(Self { hashes, bits })
+    }
+
+    /// Writes the filter to the given writer.
+    pub fn write(&self, writer: &mut dyn Write) -> Result<(), DatabaseError> {
+        writer.write_u8(self.hashes)?;
+        writer.write_u32::<BE>(self.bits.len() as u32)?;
+        writer.write_all(&self.bits)?;
+        Ok(())
+    }
+
+    /// The length of the filter in bytes, once written.
+    pub fn byte_len(&self) -> usize {
+        Self::PREFIX_LEN + self.bits.len()
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+fn fnv1a(data: &[u8], seed: u64) -> u64 {
+    let mut hash = seed;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
@@ -0,0 +1,111 @@
+//! The cache of keys that were used since a database was opened.
+//!
+//! Keys are cached by name, so a lookup does not depend on how many keys are cached.
+//! The cache holds at most `KEY_CACHE_LEN` keys, and forgets the keys that were cached
+//! first once it is full. A forgotten key is found in the partitions again when it is used.
+use crate::virtual_db::VirtualKey;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The most keys the cache holds.
+pub const KEY_CACHE_LEN: usize = 64 * 1024;
+
+/// The keys that were used since the database was opened, by name.
+#[derive(Debug)]
+pub struct KeyCache {
+    keys: HashMap<String, VirtualKey>,
+    /// The names of the cached keys, in the order they were cached.
+    /// Names of keys that were removed from the cache stay until they are evicted.
+    order: VecDeque<String>,
+    capacity: usize,
+    /// The amount of lookups that were answered by the cache.
+    hits: AtomicU64,
+    /// The amount of lookups that had to read the partitions.
+    misses: AtomicU64,
+}
+
+impl Default for KeyCache {
+    fn default() -> Self {
+        Self::with_capacity(KEY_CACHE_LEN)
+    }
+}
+
+impl KeyCache {
+    /// Creates a cache that holds at most `capacity` keys.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            keys: HashMap::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Finds the key, counting whether or not it was cached.
+    pub fn get(&self, key_name: &str) -> Option<VirtualKey> {
+        let key = self.keys.get(key_name).cloned();
+        let counter = match key.is_some() {
+            true => &self.hits,
+            false => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        key
+    }
+
+    /// Caches the key, replacing the key with the same name.
+    pub fn insert(&mut self, key: VirtualKey) {
+        if self.keys.insert(key.name.clone(), key.clone()).is_none() {
+            self.order.push_back(key.name);
+        }
+        while self.keys.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(name) => {
+                    self.keys.remove(&name);
+                }
+                None => break,
+            }
+        }
+        // names of removed keys are dropped once they outnumber the cached keys.
+        if self.order.len() > self.capacity * 2 {
+            let keys = &self.keys;
+            self.order.retain(|name| keys.contains_key(name));
+        }
+    }
+
+    /// Forgets the key with the given name.
+    pub fn remove(&mut self, key_name: &str) {
+        self.keys.remove(key_name);
+    }
+
+    /// Forgets every key whose name starts with the prefix.
+    pub fn remove_prefix(&mut self, prefix: &str) {
+        self.keys.retain(|name, _| !name.starts_with(prefix));
+    }
+
+    /// Forgets every key.
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.order.clear();
+    }
+
+    /// The amount of keys that are cached.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether or not no key is cached.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The amount of lookups that were answered by the cache, and that were not.
+    pub fn lookups(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// The kind of mutation that produced a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The key was created or overwritten.
+    Set,
+    /// The key was removed.
+    Remove,
+}
+
+/// A single mutation of the virtual database.
+/// Every mutation is given a monotonic sequence number, so a client that
+/// remembers the last sequence it has seen can ask for everything after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The sequence number of the mutation.
+    pub sequence: u64,
+    /// What happened to the key.
+    pub kind: ChangeKind,
+    /// The name of the key that changed.
+    pub key: String,
+    /// The length of the value that was written (`0` for removals).
+    pub length: usize,
+}
+
+/// The subscribers listening for changes on a virtual database.
+/// Subscribers are dropped once their receiving end has been dropped.
+#[derive(Default)]
+pub struct Subscribers {
+    senders: Vec<(String, Sender<Change>)>,
+}
+
+impl Subscribers {
+    /// Creates an empty set of subscribers.
+    pub fn new() -> Self {
+        Self {
+            senders: Vec::new(),
+        }
+    }
+
+    /// Subscribes to every change on a key starting with `prefix`.
+    /// An empty prefix subscribes to every change.
+    pub fn subscribe(&mut self, prefix: String) -> Receiver<Change> {
+        let (sender, receiver) = channel();
+        self.senders.push((prefix, sender));
+        receiver
+    }
+
+    /// Sends the change to every subscriber whose prefix matches the key.
+    pub fn notify(&mut self, change: &Change) {
+        self.senders.retain(|(prefix, sender)| {
+            !change.key.starts_with(prefix.as_str()) || sender.send(change.clone()).is_ok()
+        });
+    }
+
+    /// The amount of subscribers that are still listening.
+    pub fn len(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Whether or not there are no subscribers.
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+}
@@ -1,25 +1,107 @@
+use crate::{
+    preamble::CompressionMode,
+    tiering::{Tier, TieringPolicy},
+};
+
 /// Database commands from external sources.
 /// These are commands that are relative to an `OPEN` database.
 pub enum OpenDatabaseCommand {
-     /// A command to add a new entry to the database.
-     /// Appends the entry to the database if it does not already exist
-     /// and returns its Virtual Location.
-     New(String, Vec<u8>),
-     /// A command to get an item from the database.
-     /// Returns the item if it exists, or None if it does not.
-     Get(String),
-     /// A command to remove an item from the database.
-     /// Returns the item if it exists, or None if it does not.
-     Remove(String),
-     /// A command to update an item in the database.
-     /// If it does not exist, it is created.
-     /// However if you want to only create it, use `New`.
-     Update(String, Vec<u8>)
+    /// A command to add a new entry to the database.
+    /// Appends the entry to the database if it does not already exist
+    /// and returns its Virtual Location.
+    New(String, Vec<u8>),
+    /// A command to get an item from the database.
+    /// Returns the item if it exists, or None if it does not.
+    Get(String),
+    /// A command to get part of an item from the database.
+    /// Where the numbers are the offset and length of the part within the value.
+    GetRange(String, u64, u64),
+    /// A command to remove an item from the database.
+    /// Returns the item if it exists, or None if it does not.
+    Remove(String),
+    /// A command to update an item in the database.
+    /// If it does not exist, it is created.
+    /// However if you want to only create it, use `New`.
+    Update(String, Vec<u8>),
+    /// A command to create a namespace, where the encapsulated string is its name.
+    CreateNamespace(String),
+    /// A command to drop a namespace and every key within it.
+    DropNamespace(String),
+    /// A command to list the keys within a namespace.
+    ListNamespace(String),
+    /// The same as `New`, within the namespace named by the first string.
+    NewIn(String, String, Vec<u8>),
+    /// The same as `Get`, within the namespace named by the first string.
+    GetIn(String, String),
+    /// The same as `Remove`, within the namespace named by the first string.
+    RemoveIn(String, String),
+    /// The same as `Update`, within the namespace named by the first string.
+    UpdateIn(String, String, Vec<u8>),
+    /// A command to get many items from the database at once.
+    /// Returns a result for every key, in the order requested.
+    GetMany(Vec<String>),
+    /// A command to update many items in the database at once.
+    /// Returns a result for every key, in the order given.
+    SetMany(Vec<(String, Vec<u8>)>),
+    /// A command to update an item only if it is at the expected version.
+    /// Where `0` expects the item to not exist.
+    CompareAndSet(String, u64, Vec<u8>),
+    /// A command to remove an item only if it is at the expected version.
+    CompareAndRemove(String, u64),
+    /// A command to update an item with a deduplicated value.
+    /// Items with the same content share the stored value.
+    UpdateBlob(String, Vec<u8>),
+    /// The same as `Get`, for an item set with `UpdateBlob`.
+    GetBlob(String),
+    /// The same as `Remove`, for an item set with `UpdateBlob`.
+    RemoveBlob(String),
+    /// A command to gather statistics about the database.
+    Stats,
+    /// A command to set the mode new values are compressed with, in every partition.
+    SetCompression(CompressionMode),
+    /// A command to set the mode new values written to a partition are compressed with.
+    SetPartitionCompression(u8, CompressionMode),
+    /// A command to set the zstd level new values are compressed at.
+    SetCompressionLevel(i32),
+    /// A command to train a new dictionary for small values, from the values stored now.
+    TrainDictionary,
+    /// A command to move a partition, by its id, into the given storage tier.
+    MovePartition(u8, Tier),
+    /// A command to move the partitions that the policy says belong in another tier.
+    ApplyTiering(TieringPolicy),
+    /// A command to add a key slot that unlocks an encrypted database with another password.
+    AddPassword(String),
+    /// A command to add a key slot that unlocks an encrypted database with a keyfile.
+    /// Where the encapsulated string is the path to the keyfile.
+    AddKeyfile(String),
+    /// A command to remove a key slot, by its index.
+    RemoveKeySlot(u8),
+    /// A command to list the key slots that are used.
+    ListKeySlots,
+    /// A command to start rotating the master key of an encrypted database.
+    /// Every key slot is kept, and wraps the new key.
+    RotateKey,
+    /// A command to encrypt up to the given amount of partitions with the new master key.
+    RotatePartitions(u16),
+    /// A command to set whether or not removing an item overwrites its earlier values.
+    SetSecureDelete(bool),
+    /// A command to overwrite the values that are no longer visible, without compacting.
+    ScrubFreeSpace,
 }
 
 pub enum DatabaseCommand {
     /// A command to open a database.
     /// Where the encapuslated string is the path to the database.
     Open(String),
+    /// A command to open a database only for reading.
+    /// Where the encapuslated string is the path to the database.
+    OpenReadOnly(String),
+    /// A command to open an encrypted database.
+    /// Where the encapsulated strings are the path to the database and its password.
+    OpenWithPassword(String, String),
+    /// A command to open an encrypted database with a keyfile.
+    /// Where the encapsulated strings are the path to the database and to the keyfile.
+    OpenWithKeyfile(String, String),
     /// A command to close a database.
-}
\ No newline at end of file
+    Close,
+}
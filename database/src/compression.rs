@@ -0,0 +1,441 @@
+//! Compression of the values stored within partitions.
+//!
+//! Every value is stored with an `Encoding`, so values within a partition do not have to
+//! be compressed the same way. The preamble of a partition decides how new values are
+//! compressed, and a value that does not get smaller is stored raw. Changing the mode of
+//! a partition only affects new values, until compaction recompresses the older ones.
+//!
+//! A compressed value is prefixed by its length once decompressed, and a value compressed
+//! with a dictionary also names the version of the dictionary:
+//!
+//! | Name       | Type   | Byte Length                  |
+//! | ---------- | ------ | ---------------------------- |
+//! | size       | `u64`  | 8                            |
+//! | dictionary | `u32`  | 4 (only with a dictionary)   |
+//! | data       | `[u8]` | remaining                    |
+//!
+//! Values longer than `FRAME_LEN` are compressed by zstd as a sequence of independent
+//! frames of `FRAME_LEN` bytes each, so part of the value can be read without
+//! decompressing all of it. The frames follow a seek table (see `SeekTable`) after the size.
+use crate::{
+    dictionary::{Dictionaries, DICTIONARY_VALUE_LEN},
+    preamble::CompressionMode,
+    virtual_db::VirtualDatabase,
+    DatabaseError,
+};
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use std::{
+    borrow::Cow,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+};
+
+/// The zstd level values are compressed at, unless configured otherwise.
+pub const DEFAULT_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+/// The brotli quality values are compressed at.
+pub const BROTLI_QUALITY: u32 = 11;
+
+/// The base 2 logarithm of the brotli window size.
+pub const BROTLI_WINDOW: u32 = 22;
+
+/// The xz preset values are compressed with.
+pub const XZ_PRESET: u32 = 6;
+
+/// The length of every frame of a seekable value, once decompressed.
+/// Longer values are compressed as seekable frames by the zstd mode.
+pub const FRAME_LEN: usize = 1024 * 1024;
+
+/// The amount of bytes before the data of a compressed value.
+pub const SIZE_LEN: usize = 8;
+
+/// The most an lz4 block can grow by once decompressed, where a byte of a match length
+/// stands for 255 bytes of the value.
+const LZ4_MAX_RATIO: usize = 255;
+
+/// How a value is stored within a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Encoding {
+    /// The value is stored as it was given.
+    Raw,
+    /// The value is compressed as a single zstd frame.
+    Zstd,
+    /// The value is compressed as a single zstd frame, with a dictionary.
+    ZstdDictionary,
+    /// The value is compressed as a single lz4 block.
+    Lz4,
+    /// The value is compressed as a brotli stream.
+    Brotli,
+    /// The value is compressed as a single xz stream.
+    Xz,
+    /// The value is compressed as independent zstd frames, after a seek table.
+    ZstdSeekable,
+}
+
+impl Encoding {
+    pub fn from_u8(encoding: u8) -> Result<Self, DatabaseError> {
+        match encoding {
+            0 => Ok(Encoding::Raw),
+            1 => Ok(Encoding::Zstd),
+            2 => Ok(Encoding::ZstdDictionary),
+            3 => Ok(Encoding::Lz4),
+            4 => Ok(Encoding::Brotli),
+            5 => Ok(Encoding::Xz),
+            6 => Ok(Encoding::ZstdSeekable),
+            _ => Err(DatabaseError::Implementation(format!(
+                "Unknown value encoding: {}",
+                encoding
+            ))),
+        }
+    }
+
+    /// Whether or not the stored value is prefixed by its decompressed size.
+    pub fn is_compressed(self) -> bool {
+        self != Encoding::Raw
+    }
+}
+
+/// How the values written to a partition are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    /// The compression mode of the partition, from its preamble.
+    pub mode: CompressionMode,
+    /// The zstd compression level, from its preamble.
+    /// The other modes compress at a fixed level.
+    pub level: i32,
+}
+
+impl Compression {
+    /// Checks that the level is accepted by zstd.
+    pub fn validate_level(level: i32) -> Result<(), DatabaseError> {
+        match zstd::compression_level_range().contains(&level) {
+            true => Ok(()),
+            false => Err(DatabaseError::Implementation(format!(
+                "Compression level {} is not supported",
+                level
+            ))),
+        }
+    }
+
+    /// Whether or not a value stored with the encoding is kept as is by compaction.
+    /// Values compressed by another mode are recompressed, while raw values stay raw,
+    /// as they are usually raw because they did not get smaller.
+    pub fn keeps(&self, encoding: Encoding) -> bool {
+        match encoding {
+            Encoding::Raw => true,
+            Encoding::Zstd | Encoding::ZstdDictionary | Encoding::ZstdSeekable => {
+                self.mode == CompressionMode::Zstd
+            }
+            Encoding::Lz4 => self.mode == CompressionMode::Lz4,
+            Encoding::Brotli => self.mode == CompressionMode::Brotli,
+            Encoding::Xz => self.mode == CompressionMode::Xz,
+        }
+    }
+
+    /// Encodes a value to be stored.
+    /// Small values are compressed with the newest of the `dictionaries` in the zstd mode,
+    /// if there is one. The value is stored raw if compressing it does not make it smaller.
+    pub fn encode<'a>(
+        &self,
+        value: &'a [u8],
+        dictionaries: Option<&Dictionaries>,
+    ) -> Result<(Encoding, Cow<'a, [u8]>), DatabaseError> {
+        if value.is_empty() {
+            return Ok((Encoding::Raw, Cow::Borrowed(value)));
+        }
+
+        let mut stored = Vec::new();
+        stored.write_u64::<BE>(value.len() as u64)?;
+        let encoding = match self.mode {
+            CompressionMode::None => return Ok((Encoding::Raw, Cow::Borrowed(value))),
+            CompressionMode::Zstd => self.encode_zstd(value, dictionaries, &mut stored)?,
+            CompressionMode::Lz4 => {
+                stored.extend_from_slice(&lz4_flex::block::compress(value));
+                Encoding::Lz4
+            }
+            CompressionMode::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(&mut stored, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer.write_all(value)?;
+                writer.flush()?;
+                drop(writer);
+                Encoding::Brotli
+            }
+            CompressionMode::Xz => {
+                let mut writer = xz2::write::XzEncoder::new(&mut stored, XZ_PRESET);
+                writer.write_all(value)?;
+                writer.finish()?;
+                Encoding::Xz
+            }
+        };
+        match stored.len() < value.len() {
+            true => Ok((encoding, Cow::Owned(stored))),
+            false => Ok((Encoding::Raw, Cow::Borrowed(value))),
+        }
+    }
+}
+
+impl Compression {
+    /// Compresses a value with zstd, after its size.
+    fn encode_zstd(
+        &self,
+        value: &[u8],
+        dictionaries: Option<&Dictionaries>,
+        stored: &mut Vec<u8>,
+    ) -> Result<Encoding, DatabaseError> {
+        if value.len() > FRAME_LEN {
+            let frames = value
+                .chunks(FRAME_LEN)
+                .map(|chunk| zstd::block::compress(chunk, self.level))
+                .collect::<Result<Vec<_>, _>>()?;
+            SeekTable {
+                frame_len: FRAME_LEN as u32,
+                frames: frames.iter().map(|frame| frame.len() as u32).collect(),
+            }
+            .write(stored)?;
+            for frame in frames {
+                stored.extend_from_slice(&frame);
+            }
+            return Ok(Encoding::ZstdSeekable);
+        }
+        let dictionary = match value.len() <= DICTIONARY_VALUE_LEN {
+            true => dictionaries.and_then(Dictionaries::latest),
+            false => None,
+        };
+        match dictionary {
+            Some((version, dictionary)) => {
+                stored.write_u32::<BE>(version)?;
+                let mut compressor = zstd::block::Compressor::with_dict(dictionary.to_vec());
+                stored.extend_from_slice(&compressor.compress(value, self.level)?);
+                Ok(Encoding::ZstdDictionary)
+            }
+            None => {
+                stored.extend_from_slice(&zstd::block::compress(value, self.level)?);
+                Ok(Encoding::Zstd)
+            }
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            mode: CompressionMode::Zstd,
+            level: DEFAULT_LEVEL,
+        }
+    }
+}
+
+impl VirtualDatabase {
+    /// Sets the mode new values are compressed with, in every partition.
+    /// Values that are already stored are recompressed when their partition is compacted.
+    pub fn set_compression(&mut self, mode: CompressionMode) -> Result<(), DatabaseError> {
+        for partition in self.parts.iter_mut() {
+            partition.set_compression_mode(mode)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the mode new values written to one partition are compressed with.
+    /// Values that are already stored are recompressed when the partition is compacted.
+    pub fn set_partition_compression(
+        &mut self,
+        id: u8,
+        mode: CompressionMode,
+    ) -> Result<(), DatabaseError> {
+        let index = self.partition_index(id as u64)?;
+        self.parts[index].set_compression_mode(mode)
+    }
+
+    /// Sets the zstd level new values are compressed at, in every partition.
+    /// Values that are already stored keep the level they were compressed at.
+    pub fn set_compression_level(&mut self, level: i32) -> Result<(), DatabaseError> {
+        Compression::validate_level(level)?;
+        for partition in self.parts.iter_mut() {
+            partition.set_compression_level(level)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where every frame of a seekable value is stored.
+///
+/// | Name       | Type       | Byte Length     |
+/// | ---------- | ---------- | --------------- |
+/// | frame_len  | `u32`      | 4               |
+/// | count      | `u32`      | 4               |
+/// | frames     | `[u32]`    | 4 * count       |
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeekTable {
+    /// The length of every frame once decompressed, except for the last one.
+    pub frame_len: u32,
+    /// The length of every frame as it is stored, in order.
+    pub frames: Vec<u32>,
+}
+
+impl SeekTable {
+    /// Reads a seek table from the given reader.
+    pub fn read(reader: &mut impl Read) -> Result<Self, DatabaseError> {
+        let frame_len = reader.read_u32::<BE>()?;
+        let count = reader.read_u32::<BE>()? as u64;
+        let table = read_exactly(reader, count * 4)?;
+        let frames = table
+            .chunks(4)
+            .map(|entry| u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]))
+            .collect();
+        if frame_len == 0 {
+            return Err(corrupted());
+        }
+        Ok(Self { frame_len, frames })
+    }
+
+    /// Writes the seek table to the given writer.
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), DatabaseError> {
+        writer.write_u32::<BE>(self.frame_len)?;
+        writer.write_u32::<BE>(self.frames.len() as u32)?;
+        for frame in self.frames.iter() {
+            writer.write_u32::<BE>(*frame)?;
+        }
+        Ok(())
+    }
+
+    /// Decompresses the frames `first` to `last` from the reader, which is positioned at
+    /// the start of frame `first`. The whole value is `size` bytes long.
+    fn decompress(
+        &self,
+        reader: &mut impl Read,
+        first: usize,
+        last: usize,
+        size: u64,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let mut value = Vec::new();
+        for index in first..=last {
+            let frame_start = index as u64 * self.frame_len as u64;
+            let capacity = size.saturating_sub(frame_start).min(self.frame_len as u64);
+            let frame = read_exactly(
+                reader,
+                *self.frames.get(index).ok_or_else(corrupted)? as u64,
+            )?;
+            let decompressed = read_limited(
+                zstd::stream::read::Decoder::new(&frame[..])?,
+                capacity as usize,
+            )?;
+            if decompressed.len() as u64 != capacity {
+                return Err(corrupted());
+            }
+            value.extend_from_slice(&decompressed);
+        }
+        Ok(value)
+    }
+}
+
+/// Reads `length` bytes of a seekable value, starting at `offset`, decompressing only the
+/// frames they are within. The reader is positioned at the size of the stored value.
+/// Returns the bytes, and the length of the whole value.
+pub fn read_seekable_range<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    length: u64,
+) -> Result<(Vec<u8>, u64), DatabaseError> {
+    let size = reader.read_u64::<BE>()?;
+    let table = SeekTable::read(reader)?;
+    let start = offset.min(size);
+    let end = start.saturating_add(length).min(size);
+    if start == end {
+        return Ok((Vec::new(), size));
+    }
+
+    let frame_len = table.frame_len as u64;
+    let (first, last) = (
+        (start / frame_len) as usize,
+        ((end - 1) / frame_len) as usize,
+    );
+    let skipped: u64 = table
+        .frames
+        .iter()
+        .take(first)
+        .map(|frame| *frame as u64)
+        .sum();
+    reader.seek(SeekFrom::Current(skipped as i64))?;
+    let frames = table.decompress(reader, first, last, size)?;
+    let from = (start - first as u64 * frame_len) as usize;
+    Ok((frames[from..from + (end - start) as usize].to_vec(), size))
+}
+
+/// The length of a value once decompressed, from the stored value.
+/// Only the first `SIZE_LEN` bytes are needed for a compressed value.
+pub fn decoded_len(encoding: Encoding, stored: &[u8]) -> Result<u64, DatabaseError> {
+    match encoding.is_compressed() {
+        true => Ok(Cursor::new(stored).read_u64::<BE>()?),
+        false => Ok(stored.len() as u64),
+    }
+}
+
+/// Decodes a stored value back into the value that was written.
+pub fn decode(
+    encoding: Encoding,
+    stored: Vec<u8>,
+    dictionaries: &Dictionaries,
+) -> Result<Vec<u8>, DatabaseError> {
+    let size = decoded_len(encoding, &stored)? as usize;
+    let data = stored.get(SIZE_LEN..).unwrap_or_default();
+    let value = match encoding {
+        Encoding::Raw => return Ok(stored),
+        Encoding::Zstd => read_limited(zstd::stream::read::Decoder::new(data)?, size)?,
+        Encoding::ZstdDictionary => {
+            let mut reader = Cursor::new(data);
+            let dictionary = dictionaries.get(reader.read_u32::<BE>()?)?;
+            let decoder =
+                zstd::stream::read::Decoder::with_dictionary(&data[4..], dictionary.as_slice())?;
+            read_limited(decoder, size)?
+        }
+        Encoding::Lz4 => {
+            // lz4 blocks are decompressed at once, so the size is checked against the most
+            // a block of this length can hold before it is allocated.
+            if size > data.len().saturating_mul(LZ4_MAX_RATIO) {
+                return Err(corrupted());
+            }
+            lz4_flex::block::decompress(data, size).map_err(|_| corrupted())?
+        }
+        Encoding::Brotli => read_limited(brotli::Decompressor::new(data, 4096), size)?,
+        Encoding::Xz => read_limited(xz2::read::XzDecoder::new(data), size)?,
+        Encoding::ZstdSeekable => {
+            let mut reader = Cursor::new(data);
+            let table = SeekTable::read(&mut reader)?;
+            match table.frames.len() {
+                0 => Vec::new(),
+                count => table.decompress(&mut reader, 0, count - 1, size as u64)?,
+            }
+        }
+    };
+    if value.len() != size {
+        return Err(corrupted());
+    }
+    Ok(value)
+}
+
+/// Reads a decompressing stream, reading at most one byte more than the value should have.
+/// The size is read from the partition, so the value grows as it is read instead of being
+/// allocated up front.
+fn read_limited(reader: impl Read, size: usize) -> Result<Vec<u8>, DatabaseError> {
+    let mut value = Vec::new();
+    reader
+        .take(size as u64 + 1)
+        .read_to_end(&mut value)
+        .map_err(|_| corrupted())?;
+    Ok(value)
+}
+
+/// Reads exactly `length` bytes, which grow as they are read, like `read_limited`.
+fn read_exactly(reader: &mut impl Read, length: u64) -> Result<Vec<u8>, DatabaseError> {
+    let mut data = Vec::new();
+    reader.take(length).read_to_end(&mut data)?;
+    if data.len() as u64 != length {
+        return Err(corrupted());
+    }
+    Ok(data)
+}
+
+fn corrupted() -> DatabaseError {
+    DatabaseError::Implementation("Compressed value is corrupted".to_string())
+}
@@ -1,15 +1,75 @@
-use byteorder::{ReadBytesExt, BE};
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
 use std::any::Any;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::Cursor;
-use std::path::Path;
-
-use crate::preamble::Preamble;
-use crate::utils::GetByteLength;
-use crate::virtual_db::VirtualDatabase;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{BufRead, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use zeroize::Zeroizing;
+
+use crate::backup::{self, BackupSummary};
+use crate::blob::{BlobHash, BlobKey};
+use crate::changes::Change;
+use crate::compression::{Compression, Encoding};
+use crate::encryption::{
+    self, Cipher, KdfParams, KeyBlock, KeyRotation, Secret, SlotKind, KEY_BLOCK_LEN,
+};
+use crate::export;
+use crate::preamble::{CompressionMode, EncryptionLevel, Preamble};
+use crate::scrub::ScrubSummary;
+use crate::snapshot::{Snapshot, SnapshotPin};
+use crate::stats::DatabaseStats;
+use crate::tiering::{Placement, StorageTiers, Tier, TierCatalog, TieringPolicy};
+use crate::utils::{timestamp, GetByteLength, InternalApi};
+use crate::virtual_db::{Partition, RecordKind, VirtualDatabase, VirtualItem, VirtualKey};
 use crate::DatabaseError;
 
+/// The path of the file that is locked while the database is open, next to the database.
+fn lock_path(db_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.lock", db_path.display()))
+}
+
+/// Locks the database, shared with other readers if `read_only`, and exclusively otherwise.
+/// Fails with `DatabaseError::Locked` if the lock is held by someone else.
+///
+/// The lock is held on a file of its own (see `lock_path`), rather than the database file,
+/// because compaction replaces the database file of an unpartitioned database, and a lock
+/// on the file it replaced would no longer lock anything.
+fn lock(db_path: &Path, read_only: bool) -> Result<File, DatabaseError> {
+    let path = lock_path(db_path);
+    let file = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+    {
+        Ok(file) => file,
+        // a reader may not be allowed to create the lock file, but may still share it.
+        Err(_) if read_only && path.exists() => File::open(&path)?,
+        Err(error) => return Err(error.into()),
+    };
+    let locked = match read_only {
+        true => file.try_lock_shared(),
+        false => file.try_lock(),
+    };
+    match locked {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(DatabaseError::Locked),
+        Err(TryLockError::Error(error)) => Err(error.into()),
+    }
+}
+
+/// What a database is opened for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// Reading and writing, by a single opener.
+    Write,
+    /// Only reading, shared with other readers.
+    Read,
+    /// Reading, and applying the records of a primary, by a single opener.
+    Replica,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DbDeviceOs {
     Linux,
@@ -18,6 +78,21 @@ pub enum DbDeviceOs {
     Unknown,
 }
 
+impl DbDeviceOs {
+    /// The operating system this library was compiled for.
+    pub fn current() -> Self {
+        if cfg!(target_os = "linux") {
+            DbDeviceOs::Linux
+        } else if cfg!(target_os = "windows") {
+            DbDeviceOs::Windows
+        } else if cfg!(target_os = "macos") {
+            DbDeviceOs::Mac
+        } else {
+            DbDeviceOs::Unknown
+        }
+    }
+}
+
 /// The One-Link database mode.
 /// This will not effect the api, however it will change
 /// the behaviour of the database.
@@ -50,34 +125,58 @@ pub struct Header {
     /// This needs to be `true` for partitions.
     /// However it is recommended for all instances.
     pub virtualization: bool,
+    /// Whether or not removing a key overwrites its earlier values, see `crate::scrub`.
+    pub secure_delete: bool,
 }
 
 impl Header {
+    /// Creates a new virtualized header for a database with the given amount of partitions.
+    /// A database with `0` partitions stores its data within the database file.
+    pub fn new(partitions: u8) -> Header {
+        let partitioned = partitions > 0;
+        Header {
+            partitioned,
+            partition_index: if partitioned { Some(0) } else { None },
+            partitions: if partitioned { Some(partitions) } else { None },
+            created_on: DbDeviceOs::current(),
+            last_open: 0,
+            last_close: 0,
+            last_write: 0,
+            virtualization: true,
+            secure_delete: false,
+        }
+    }
+
     /// Creates a header from the given data.
     /// This is used when opening a database.
     pub fn create(data: &[u8]) -> Result<Header, DatabaseError> {
-        let mut cursor = Cursor::new(data);
-        let partitioned = cursor.read_u8()? != 0;
+        Self::read(&mut Cursor::new(data))
+    }
+
+    /// Reads a header from the given reader.
+    pub fn read(reader: &mut dyn Read) -> Result<Header, DatabaseError> {
+        let partitioned = reader.read_u8()? != 0;
         let partition_index = if partitioned {
-            Some(cursor.read_u8()?)
+            Some(reader.read_u8()?)
         } else {
             None
         };
         let partitions = if partitioned {
-            Some(cursor.read_u8()?)
+            Some(reader.read_u8()?)
         } else {
             None
         };
-        let created_on = match cursor.read_u8()? {
+        let created_on = match reader.read_u8()? {
             0 => DbDeviceOs::Linux,
             1 => DbDeviceOs::Windows,
             2 => DbDeviceOs::Mac,
             _ => DbDeviceOs::Unknown,
         };
-        let last_open = cursor.read_u128::<BE>()?;
-        let last_close = cursor.read_u128::<BE>()?;
-        let last_write = cursor.read_u128::<BE>()?;
-        let virtualization = cursor.read_u8()? != 0;
+        let last_open = reader.read_u128::<BE>()?;
+        let last_close = reader.read_u128::<BE>()?;
+        let last_write = reader.read_u128::<BE>()?;
+        let virtualization = reader.read_u8()? != 0;
+        let secure_delete = reader.read_u8()? != 0;
 
         Ok(Self {
             partitioned,
@@ -88,9 +187,26 @@ impl Header {
             last_close,
             last_write,
             virtualization,
+            secure_delete,
         })
     }
 
+    /// Writes the header to the given writer.
+    pub fn write(&self, writer: &mut dyn Write) -> Result<(), DatabaseError> {
+        writer.write_u8(self.partitioned as u8)?;
+        if self.partitioned {
+            writer.write_u8(self.partition_index.unwrap_or(0))?;
+            writer.write_u8(self.partitions.unwrap_or(0))?;
+        }
+        writer.write_u8(self.created_on as u8)?;
+        writer.write_u128::<BE>(self.last_open)?;
+        writer.write_u128::<BE>(self.last_close)?;
+        writer.write_u128::<BE>(self.last_write)?;
+        writer.write_u8(self.virtualization as u8)?;
+        writer.write_u8(self.secure_delete as u8)?;
+        Ok(())
+    }
+
     pub fn byte_len(&self) -> usize {
         let mut current_byte_size: usize = 1;
         if self.partitioned {
@@ -98,10 +214,28 @@ impl Header {
             current_byte_size += 2;
         }
         // db os is 1 byte
-        // last open is 8 bytes, last close is 8 bytes, last write is 8 bytes
-        // virtualization is 1 byte
-        current_byte_size += 26;
-        return current_byte_size;
+        // last open is 16 bytes, last close is 16 bytes, last write is 16 bytes
+        // virtualization is 1 byte, secure delete is 1 byte
+        current_byte_size += 51;
+        current_byte_size
+    }
+}
+
+/// Writes the preamble and header at the start of a database file.
+/// An encrypted database has its key block before the header, which is sealed.
+fn write_head(
+    writer: &mut dyn Write,
+    preamble: &Preamble,
+    header: &Header,
+    encryption: Option<(&KeyBlock, &Cipher)>,
+) -> Result<(), DatabaseError> {
+    preamble.write(writer)?;
+    match encryption {
+        Some((key_block, cipher)) => {
+            KeyBlock::write(Some(key_block), writer)?;
+            encryption::write_header(header, cipher, writer)
+        }
+        None => header.write(writer),
     }
 }
 
@@ -116,14 +250,14 @@ pub struct Key {
 
 pub enum InternalDatabase {
     Single(File),
-    Virtual(VirtualDatabase),
+    Virtual(Box<VirtualDatabase>),
 }
 
 impl InternalDatabase {
     pub fn inner(&self) -> &dyn Any {
         match self {
             InternalDatabase::Single(file) => file,
-            InternalDatabase::Virtual(virtual_db) => virtual_db,
+            InternalDatabase::Virtual(virtual_db) => virtual_db.as_ref(),
         }
     }
 }
@@ -135,47 +269,946 @@ pub struct Database {
     pub header: Header,
     /// The mode of the database.
     pub mode: DatabaseMode,
+    /// The name of the database, partitions are named after it.
+    name: String,
+    /// The path to the database.
+    path: PathBuf,
     /// The virtual database.
     internal: InternalDatabase,
+    /// What the database was opened for.
+    access: Access,
+    /// The wrapped master key, if the database is encrypted.
+    key_block: Option<KeyBlock>,
+    /// The handle that holds the lock on the database, until the database is dropped.
+    /// See `lock`.
+    _lock: File,
 }
 
 impl Database {
+    /// Creates a new One-Link Database and opens it.
+    /// A database with `0` partitions stores its data within the database file,
+    /// otherwise the data is split into `{name}-{id}.bin` files next to the database.
+    pub fn create(
+        name: String,
+        path: String,
+        preamble: Preamble,
+        partitions: u8,
+    ) -> Result<Database, DatabaseError> {
+        if preamble.is_encrypted() {
+            return Err(DatabaseError::Implementation(
+                "An encrypted database needs a password, see `Database::create_with_password`"
+                    .to_string(),
+            ));
+        }
+        Self::create_with(&name, &path, preamble, partitions, None, None)?;
+        Self::open(name, path)
+    }
+
+    /// Creates a new encrypted One-Link Database and opens it.
+    /// A random master key encrypts the database, which is stored wrapped by a key derived
+    /// from the password, see `crate::encryption`.
+    /// The encryption level is taken from the preamble, an unencrypted preamble encrypts values.
+    pub fn create_with_password(
+        name: String,
+        path: String,
+        mut preamble: Preamble,
+        partitions: u8,
+        password: &str,
+    ) -> Result<Database, DatabaseError> {
+        if !preamble.is_encrypted() {
+            preamble.encryption = EncryptionLevel::Values;
+        }
+        let cipher = Cipher::generate()?;
+        let key_block = KeyBlock::new(Secret::Password(password), &cipher, KdfParams::default())?;
+        Self::create_with(
+            &name,
+            &path,
+            preamble,
+            partitions,
+            None,
+            Some((&key_block, &cipher)),
+        )?;
+        Self::open_with(name, path, Access::Write, Some(Secret::Password(password)))
+    }
+
+    /// Creates a new One-Link Database with its partitions in the hot storage tier, and opens it.
+    /// Partitions are spread over the hot directories by their id, see `StorageTiers`.
+    pub fn create_tiered(
+        name: String,
+        path: String,
+        preamble: Preamble,
+        partitions: u8,
+        tiers: StorageTiers,
+    ) -> Result<Database, DatabaseError> {
+        if partitions == 0 {
+            return Err(DatabaseError::Implementation(
+                "Tiered storage needs a partitioned database".to_string(),
+            ));
+        }
+        if preamble.is_encrypted() {
+            return Err(DatabaseError::Implementation(
+                "An encrypted database needs a password, see `Database::create_with_password`"
+                    .to_string(),
+            ));
+        }
+        Self::create_with(&name, &path, preamble, partitions, Some(tiers), None)?;
+        Self::open(name, path)
+    }
+
+    /// Writes the database file and partitions of a new database.
+    fn create_with(
+        name: &str,
+        path: &str,
+        preamble: Preamble,
+        partitions: u8,
+        tiers: Option<StorageTiers>,
+        encryption: Option<(&KeyBlock, &Cipher)>,
+    ) -> Result<(), DatabaseError> {
+        Compression::validate_level(preamble.level)?;
+        let header = Header::new(partitions);
+        let cipher = encryption.map(|(_, cipher)| cipher);
+        if partitions == 0 {
+            // the database file is the only partition, with the key block of the database.
+            Partition::create(PathBuf::from(path), 0, &preamble, &header, cipher)?;
+            if let Some((key_block, _)) = encryption {
+                let mut db_file = OpenOptions::new().write(true).open(path)?;
+                db_file.seek(SeekFrom::Start(preamble.byte_len() as u64))?;
+                KeyBlock::write(Some(key_block), &mut db_file)?;
+                db_file.sync_all()?;
+            }
+            return Ok(());
+        }
+
+        let mut db_file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        write_head(&mut db_file, &preamble, &header, encryption)?;
+        db_file.sync_all()?;
+
+        let mut catalog = tiers.map(|tiers| TierCatalog {
+            tiers,
+            placements: Vec::new(),
+        });
+        for i in 0..partitions {
+            let mut partition_path = Partition::path_for(Path::new(path), name, i);
+            if let Some(catalog) = catalog.as_mut() {
+                let directory =
+                    catalog
+                        .tiers
+                        .directory(Tier::Hot, i)
+                        .cloned()
+                        .ok_or_else(|| {
+                            DatabaseError::Implementation(
+                                "No directories for the Hot tier".to_string(),
+                            )
+                        })?;
+                std::fs::create_dir_all(&directory)?;
+                if let Some(file_name) = partition_path.file_name() {
+                    partition_path = directory.join(file_name);
+                }
+                catalog.placements.push(Placement {
+                    id: i,
+                    tier: Tier::Hot,
+                    directory,
+                    last_access: timestamp(),
+                });
+            }
+
+            let mut partition_header = header.clone();
+            partition_header.partition_index = Some(i);
+            Partition::create(partition_path, i, &preamble, &partition_header, cipher)?;
+        }
+        if let Some(catalog) = catalog {
+            catalog.save(Path::new(path))?;
+        }
+        Ok(())
+    }
+
     /// Opens a One-Link Database.
     /// This will open the database and read the headers.
+    /// The database is locked, so it can not be opened by anyone else until it is dropped.
+    /// An encrypted database fails to open with `DatabaseError::IncorrectPassword`,
+    /// it is opened with `Database::open_with_password`.
     pub fn open(name: String, path: String) -> Result<Database, DatabaseError> {
-        let mut db_file = File::open(&path)?;
-        let mut buffer = BufReader::new(db_file);
-        let preamble = Preamble::create(&mut buffer.buffer())?;
-        let mut mode = DatabaseMode::Single;
+        Self::open_with(name, path, Access::Write, None)
+    }
 
-        buffer.seek_relative(preamble.byte_len() as i64)?;
+    /// Opens an encrypted One-Link Database.
+    /// Fails with `DatabaseError::IncorrectPassword` if the password does not unlock it.
+    pub fn open_with_password(
+        name: String,
+        path: String,
+        password: &str,
+    ) -> Result<Database, DatabaseError> {
+        Self::open_with(name, path, Access::Write, Some(Secret::Password(password)))
+    }
 
-        if preamble.encryption != 0 {
-            // The database is NOT encrypted.
-            let header = Header::create(&mut buffer.buffer())?;
+    /// Opens an encrypted One-Link Database with a keyfile, which was added to
+    /// it with `Database::add_keyfile`.
+    /// Fails with `DatabaseError::IncorrectPassword` if the keyfile does not unlock it.
+    pub fn open_with_keyfile(
+        name: String,
+        path: String,
+        keyfile: String,
+    ) -> Result<Database, DatabaseError> {
+        let keyfile = Zeroizing::new(fs::read(keyfile)?);
+        Self::open_with(name, path, Access::Write, Some(Secret::Keyfile(&keyfile)))
+    }
 
-            if header.virtualization {
-                // The database is virtualized.
-                mode = DatabaseMode::Virtual;
-            } else {
+    /// Opens a One-Link Database only for reading.
+    /// Any number of read-only openers can share the database, but not with an opener
+    /// that writes. Every write fails with `DatabaseError::ReadOnly`, and the header is
+    /// never written, not even on open and close.
+    pub fn open_read_only(name: String, path: String) -> Result<Database, DatabaseError> {
+        Self::open_with(name, path, Access::Read, None)
+    }
+
+    /// Opens the database of a replica, see `crate::replication`.
+    /// The database can not be shared, and every write fails with `DatabaseError::ReadOnly`,
+    /// except for the records applied from the primary.
+    pub(crate) fn open_replica(name: String, path: String) -> Result<Database, DatabaseError> {
+        Self::open_with(name, path, Access::Replica, None)
+    }
+
+    fn open_with(
+        name: String,
+        path: String,
+        access: Access,
+        secret: Option<Secret>,
+    ) -> Result<Database, DatabaseError> {
+        let read_only = access == Access::Read;
+        let mut db_file = File::open(&path)?;
+        let lock_file = lock(Path::new(&path), read_only)?;
+        let mut preamble_bytes = vec![0; Preamble::new().byte_len()];
+        db_file.read_exact(&mut preamble_bytes)?;
+        let preamble = Preamble::create(&preamble_bytes)?;
+
+        let (header, key_block, cipher) = match (preamble.is_encrypted(), secret) {
+            (true, Some(secret)) => {
+                let key_block =
+                    KeyBlock::read(&mut db_file)?.ok_or(DatabaseError::InvalidDatabase)?;
+                let cipher = key_block.unlock(secret)?;
+                let header = encryption::read_header(&mut db_file, &cipher)?;
+                (header, Some(key_block), Some(cipher))
+            }
+            (true, None) => return Err(DatabaseError::IncorrectPassword),
+            (false, Some(_)) => {
                 return Err(DatabaseError::Implementation(
-                    "Non-Virtualized databases are not supported yet".to_string(),
-                ));
+                    "The database is not encrypted".to_string(),
+                ))
             }
-            Ok(Database {
-                preamble,
-                header: header.clone(),
-                mode,
-                internal: InternalDatabase::Virtual(VirtualDatabase::new(
-                    header,
-                    name,
-                    Path::new(&path),
-                )),
-            })
-        } else {
-            Err(DatabaseError::Implementation(
-                "Non-encrypted databases are not supported yet".to_string(),
-            ))
+            (false, None) => (Header::read(&mut db_file)?, None, None),
+        };
+
+        if !header.virtualization {
+            return Err(unsupported());
+        }
+        // The database is virtualized.
+        let internal = VirtualDatabase::open(
+            header.clone(),
+            name.clone(),
+            Path::new(&path),
+            !read_only,
+            cipher,
+        )?;
+
+        let mut db = Database {
+            preamble,
+            header,
+            mode: DatabaseMode::Virtual,
+            name,
+            path: PathBuf::from(path),
+            internal: InternalDatabase::Virtual(Box::new(internal)),
+            access,
+            key_block,
+            _lock: lock_file,
+        };
+        if !read_only {
+            db.header.last_open = timestamp();
+            db.write_header()?;
+        }
+        Ok(db)
+    }
+
+    /// Closes the database, recording the time it was closed.
+    /// A read-only database is closed without writing anything.
+    pub fn close(mut self) -> Result<(), DatabaseError> {
+        if self.access == Access::Read {
+            return Ok(());
+        }
+        self.virtual_db()?.save_tiers()?;
+        self.header.last_close = timestamp();
+        self.write_header()
+    }
+
+    /// The path of the database file.
+    pub fn get_path(&self) -> String {
+        self.path.to_str().unwrap_or("").to_string()
+    }
+
+    /// The name of the database.
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Writes the in-memory header back to the database file.
+    fn write_header(&mut self) -> Result<(), DatabaseError> {
+        let mut db_file = OpenOptions::new().write(true).open(&self.path)?;
+        match self.cipher()? {
+            Some(cipher) => {
+                db_file.seek(SeekFrom::Start(
+                    (self.preamble.byte_len() + KEY_BLOCK_LEN) as u64,
+                ))?;
+                encryption::write_header(&self.header, &cipher, &mut db_file)?;
+            }
+            None => {
+                db_file.seek(SeekFrom::Start(self.preamble.byte_len() as u64))?;
+                self.header.write(&mut db_file)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The preamble and header of the database file as they are written,
+    /// with the key block and sealed header if the database is encrypted.
+    fn head(&mut self) -> Result<Vec<u8>, DatabaseError> {
+        let cipher = self.cipher()?;
+        let encryption = self.key_block.as_ref().zip(cipher.as_ref());
+        let mut head = Vec::new();
+        write_head(&mut head, &self.preamble, &self.header, encryption)?;
+        Ok(head)
+    }
+
+    /// The master key, if the database is encrypted.
+    fn cipher(&mut self) -> Result<Option<Cipher>, DatabaseError> {
+        Ok(self.virtual_db()?.cipher.clone())
+    }
+
+    /// Whether or not the database is encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.preamble.is_encrypted()
+    }
+
+    /// How much of the database is encrypted.
+    pub fn encryption_level(&self) -> EncryptionLevel {
+        self.preamble.encryption
+    }
+
+    /// The zstd level new values are compressed at.
+    pub fn compression_level(&self) -> i32 {
+        self.preamble.level
+    }
+
+    /// Adds a key slot that unlocks the database with another password.
+    /// Returns the index of the slot.
+    pub fn add_password(&mut self, password: &str) -> Result<u8, DatabaseError> {
+        self.add_key_slot(Secret::Password(password))
+    }
+
+    /// Adds a key slot that unlocks the database with the contents of a keyfile,
+    /// see `Database::open_with_keyfile`. Returns the index of the slot.
+    pub fn add_keyfile(&mut self, keyfile: String) -> Result<u8, DatabaseError> {
+        let keyfile = Zeroizing::new(fs::read(keyfile)?);
+        self.add_key_slot(Secret::Keyfile(&keyfile))
+    }
+
+    fn add_key_slot(&mut self, secret: Secret) -> Result<u8, DatabaseError> {
+        self.writable()?;
+        let cipher = self.cipher()?.ok_or_else(|| {
+            DatabaseError::Implementation("The database is not encrypted".to_string())
+        })?;
+        let key_block = self
+            .key_block
+            .as_mut()
+            .ok_or(DatabaseError::IncorrectPassword)?;
+        let index = key_block.add(secret, &cipher, KdfParams::default())?;
+        self.write_key_block()?;
+        Ok(index)
+    }
+
+    /// Removes a key slot, so its password or keyfile no longer unlocks the database.
+    /// The slot is zeroed within the database file. The last slot can not be removed.
+    pub fn remove_key_slot(&mut self, index: u8) -> Result<(), DatabaseError> {
+        self.writable()?;
+        self.key_block
+            .as_mut()
+            .ok_or_else(|| {
+                DatabaseError::Implementation("The database is not encrypted".to_string())
+            })?
+            .remove(index)?;
+        self.write_key_block()
+    }
+
+    /// The index and kind of every key slot that is used.
+    /// Empty if the database is not encrypted.
+    pub fn key_slots(&self) -> Vec<(u8, SlotKind)> {
+        self.key_block
+            .as_ref()
+            .map(KeyBlock::slots)
+            .unwrap_or_default()
+    }
+
+    /// Rotates the master key, encrypting every partition again with a new one.
+    /// Every key slot wraps the new key, so every password and keyfile keeps unlocking the
+    /// database. To stop a password from unlocking the database, remove its slot with
+    /// `Database::remove_key_slot` first.
+    pub fn rotate_key(&mut self) -> Result<KeyRotation, DatabaseError> {
+        let rotation = self.begin_key_rotation()?;
+        self.rotate_partitions(rotation.partitions)
+    }
+
+    /// Starts rotating the master key like `Database::rotate_key`, without encrypting any
+    /// partition again yet. New values are sealed with the new key straight away, and the
+    /// partitions are encrypted again a few at a time by `Database::rotate_partitions`,
+    /// or in the background by `encryption::rotate_in_background`.
+    /// Fails while snapshots are alive, as they only know the previous key.
+    pub fn begin_key_rotation(&mut self) -> Result<KeyRotation, DatabaseError> {
+        let virtual_db = self.writable()?;
+        if !virtual_db.snapshots.is_empty() {
+            return Err(DatabaseError::Implementation(
+                "The master key can not be rotated while snapshots are alive".to_string(),
+            ));
+        }
+        let cipher = virtual_db.cipher.clone().ok_or_else(|| {
+            DatabaseError::Implementation("The database is not encrypted".to_string())
+        })?;
+        let key_block = self
+            .key_block
+            .as_mut()
+            .ok_or(DatabaseError::IncorrectPassword)?;
+        let next = key_block.begin_rotation(&cipher)?;
+        self.write_key_block()?;
+        self.virtual_db()?.set_cipher(Some(next));
+        self.write_header()?;
+        self.key_rotation().ok_or(DatabaseError::IncorrectPassword)
+    }
+
+    /// Encrypts up to `count` more partitions with the new master key, continuing the
+    /// rotation started by `Database::begin_key_rotation`, also after the database was
+    /// closed or crashed. Progress is recorded after every partition, and the previous
+    /// key is forgotten once every partition is encrypted with the new one.
+    pub fn rotate_partitions(&mut self, count: u16) -> Result<KeyRotation, DatabaseError> {
+        let mut rotation = self.key_rotation().ok_or_else(|| {
+            DatabaseError::Implementation("The master key is not being rotated".to_string())
+        })?;
+        let end = rotation
+            .rotated
+            .saturating_add(count)
+            .min(rotation.partitions);
+        for index in rotation.rotated..end {
+            self.writable()?.rotate_partition(index as usize)?;
+            rotation.rotated = index + 1;
+            if let Some(key_block) = self.key_block.as_mut() {
+                key_block.set_rotated(rotation.rotated);
+            }
+            self.write_key_block()?;
+        }
+        if rotation.is_done() {
+            if let Some(key_block) = self.key_block.as_mut() {
+                key_block.finish_rotation();
+            }
+            self.write_key_block()?;
+            let cipher = self.cipher()?.map(|cipher| cipher.current());
+            self.virtual_db()?.set_cipher(cipher);
+        }
+        Ok(rotation)
+    }
+
+    /// How far the rotation of the master key has come, if it is being rotated.
+    pub fn key_rotation(&self) -> Option<KeyRotation> {
+        let rotated = self.key_block.as_ref()?.rotated()?;
+        let partitions = match self.header.partitioned {
+            true => self.header.partitions.unwrap_or(0) as u16,
+            false => 1,
+        };
+        Some(KeyRotation {
+            rotated,
+            partitions,
+        })
+    }
+
+    /// Writes the in-memory key block back to the database file.
+    fn write_key_block(&mut self) -> Result<(), DatabaseError> {
+        let mut db_file = OpenOptions::new().write(true).open(&self.path)?;
+        db_file.seek(SeekFrom::Start(self.preamble.byte_len() as u64))?;
+        KeyBlock::write(self.key_block.as_ref(), &mut db_file)?;
+        db_file.sync_all()?;
+        Ok(())
+    }
+
+    /// The virtual database behind this database, for operations that write to it.
+    pub(crate) fn writable(&mut self) -> Result<&mut VirtualDatabase, DatabaseError> {
+        if self.access != Access::Write {
+            return Err(DatabaseError::ReadOnly);
         }
+        self.virtual_db()
     }
+
+    /// Whether or not the database was opened with `Database::open_read_only`,
+    /// or is the database of a replica.
+    pub fn is_read_only(&self) -> bool {
+        self.access != Access::Write
+    }
+
+    /// The virtual database behind this database.
+    pub(crate) fn virtual_db(&mut self) -> Result<&mut VirtualDatabase, DatabaseError> {
+        match &mut self.internal {
+            InternalDatabase::Virtual(virtual_db) => Ok(virtual_db),
+            InternalDatabase::Single(_) => Err(unsupported()),
+        }
+    }
+
+    /// The virtual database behind this database, for reads that share it.
+    #[cfg(feature = "async")]
+    pub(crate) fn shared_virtual_db(&self) -> Result<&VirtualDatabase, DatabaseError> {
+        match &self.internal {
+            InternalDatabase::Virtual(virtual_db) => Ok(virtual_db),
+            InternalDatabase::Single(_) => Err(unsupported()),
+        }
+    }
+
+    /// The sequence number of the latest mutation.
+    pub fn sequence(&mut self) -> Result<u64, DatabaseError> {
+        Ok(self.virtual_db()?.sequence)
+    }
+
+    /// Every change made after the given sequence number, in order.
+    /// Clients can remember the last sequence they have seen and resume from it.
+    pub fn changes_since(
+        &mut self,
+        sequence: u64,
+    ) -> Result<impl Iterator<Item = Change>, DatabaseError> {
+        self.virtual_db()?.changes_since(sequence)
+    }
+
+    /// Takes a read-only, point-in-time view of the database.
+    /// Writes made after this call are not visible through the snapshot.
+    pub fn snapshot(&mut self) -> Result<Snapshot, DatabaseError> {
+        Ok(self.virtual_db()?.snapshot())
+    }
+
+    /// Retains a sequence number, so compaction keeps every record after it until the pin is
+    /// dropped. Unlike a snapshot, it does not stop the master key from being rotated.
+    pub(crate) fn retain(&mut self, sequence: u64) -> Result<SnapshotPin, DatabaseError> {
+        Ok(self.virtual_db()?.retain(sequence))
+    }
+
+    /// The highest sequence number of a record that compaction dropped.
+    pub(crate) fn compacted(&mut self) -> Result<u64, DatabaseError> {
+        Ok(self.virtual_db()?.compacted)
+    }
+
+    /// Appends a record shipped from another database, keeping its sequence and version.
+    /// This is the only write to the database of a replica.
+    pub(crate) fn apply_record(
+        &mut self,
+        kind: RecordKind,
+        sequence: u64,
+        version: u64,
+        key_name: String,
+        encoding: Encoding,
+        stored: Vec<u8>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        if self.access == Access::Read {
+            return Err(DatabaseError::ReadOnly);
+        }
+        let key = self
+            .virtual_db()?
+            .apply_record(kind, sequence, version, key_name, encoding, stored)?;
+        self.header.last_write = timestamp();
+        Ok(key)
+    }
+
+    /// Gathers statistics about what is stored within the database.
+    /// This reads every partition, so it should not be called for every request.
+    pub fn stats(&mut self) -> Result<DatabaseStats, DatabaseError> {
+        let mut stats = self.virtual_db()?.stats()?;
+        stats.created_on = self.header.created_on;
+        stats.last_open = self.header.last_open;
+        stats.last_close = self.header.last_close;
+        stats.last_write = self.header.last_write;
+        Ok(stats)
+    }
+
+    /// Compacts the database, reclaiming the space of overwritten and removed values
+    /// that are no longer visible to any snapshot.
+    /// Returns the amount of bytes reclaimed.
+    pub fn compact(&mut self) -> Result<usize, DatabaseError> {
+        self.writable()?.compact()
+    }
+
+    /// Sets whether or not removing a key overwrites its earlier values, see `crate::scrub`.
+    /// The option is stored in the header, so the database is opened with it.
+    pub fn set_secure_delete(&mut self, enabled: bool) -> Result<(), DatabaseError> {
+        self.writable()?.set_secure_delete(enabled);
+        self.header.secure_delete = enabled;
+        self.write_header()
+    }
+
+    /// Whether or not removing a key overwrites its earlier values.
+    pub fn secure_delete(&self) -> bool {
+        self.header.secure_delete
+    }
+
+    /// Overwrites the overwritten and removed values that are no longer visible to any
+    /// snapshot, without compacting the database. See `VirtualDatabase::scrub_free_space`.
+    pub fn scrub_free_space(&mut self) -> Result<ScrubSummary, DatabaseError> {
+        self.writable()?.scrub_free_space()
+    }
+
+    /// Writes a consistent backup of the database into a single archive.
+    /// The backup is taken from a snapshot, so the database stays usable while it is written.
+    pub fn backup_to(&mut self, archive: String) -> Result<BackupSummary, DatabaseError> {
+        let snapshot = self.snapshot()?;
+        let head = self.head()?;
+        backup::write_archive(&snapshot, &self.name, &head, Path::new(&archive))
+    }
+
+    /// Restores a backup archive into a new database at `path` and opens it.
+    /// The archive checksum is verified before restoring, and the restored
+    /// partitions are verified against the archive afterwards, by the version and hash of
+    /// the latest record of every key.
+    pub fn restore_from(archive: String, path: String) -> Result<Database, DatabaseError> {
+        let (name, header, _, summary, digests) =
+            backup::restore_archive(Path::new(&archive), Path::new(&path), None)?;
+        backup::verify_restore(Path::new(&path), &name, &header, None, &summary, &digests)?;
+        Self::open(name, path)
+    }
+
+    /// Restores a backup archive of an encrypted database, see `Database::restore_from`.
+    /// The restored database stays encrypted, and opens with the same password.
+    pub fn restore_from_with_password(
+        archive: String,
+        path: String,
+        password: &str,
+    ) -> Result<Database, DatabaseError> {
+        let (name, header, cipher, summary, digests) = backup::restore_archive(
+            Path::new(&archive),
+            Path::new(&path),
+            Some(Secret::Password(password)),
+        )?;
+        backup::verify_restore(
+            Path::new(&path),
+            &name,
+            &header,
+            cipher.as_ref(),
+            &summary,
+            &digests,
+        )?;
+        Self::open_with_password(name, path, password)
+    }
+
+    /// Exports every key and value as JSON-lines, with base64 encoded values.
+    /// The export is taken from a snapshot, so the database stays usable while it is written.
+    /// Returns the amount of keys exported.
+    pub fn export_to(&mut self, writer: &mut dyn Write) -> Result<u64, DatabaseError> {
+        let snapshot = self.snapshot()?;
+        export::export_snapshot(&snapshot, writer)
+    }
+
+    /// Creates a new database from a JSON-lines export.
+    /// The preamble chooses the compression mode of the new database, which is not
+    /// encrypted, see `Database::import_from_with_password`.
+    pub fn import_from(
+        reader: &mut dyn BufRead,
+        name: String,
+        path: String,
+        preamble: Preamble,
+        partitions: u8,
+    ) -> Result<Database, DatabaseError> {
+        let mut db = Self::create(name, path, preamble, partitions)?;
+        export::import_lines(reader, &mut db)?;
+        Ok(db)
+    }
+
+    /// Creates a new encrypted database from a JSON-lines export, like
+    /// `Database::create_with_password`, and loads the export into it.
+    pub fn import_from_with_password(
+        reader: &mut dyn BufRead,
+        name: String,
+        path: String,
+        preamble: Preamble,
+        partitions: u8,
+        password: &str,
+    ) -> Result<Database, DatabaseError> {
+        let mut db = Self::create_with_password(name, path, preamble, partitions, password)?;
+        export::import_lines(reader, &mut db)?;
+        Ok(db)
+    }
+
+    /// Creates a named namespace, a separate key space within the database.
+    /// Returns the id of the namespace.
+    pub fn create_namespace(&mut self, name: String) -> Result<u64, DatabaseError> {
+        let id = self.writable()?.create_namespace(name)?;
+        self.header.last_write = timestamp();
+        Ok(id)
+    }
+
+    /// Drops a namespace and every key within it.
+    /// Returns whether or not the namespace existed.
+    pub fn drop_namespace(&mut self, name: String) -> Result<bool, DatabaseError> {
+        let dropped = self.writable()?.drop_namespace(name)?;
+        if dropped {
+            self.header.last_write = timestamp();
+        }
+        Ok(dropped)
+    }
+
+    /// The names of every namespace within the database.
+    pub fn namespaces(&mut self) -> Result<Vec<String>, DatabaseError> {
+        Ok(self.virtual_db()?.namespaces())
+    }
+
+    /// Gets a key within a namespace.
+    pub fn get_in(
+        &mut self,
+        namespace: &str,
+        key_name: String,
+    ) -> Result<VirtualItem, DatabaseError> {
+        self.virtual_db()?.get_in(namespace, key_name)
+    }
+
+    /// Sets a key within a namespace.
+    pub fn set_in(
+        &mut self,
+        namespace: &str,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        let key = self.writable()?.set_in(namespace, key_name, value)?;
+        self.header.last_write = timestamp();
+        Ok(key)
+    }
+
+    /// Adds a key within a namespace, if it does not already exist.
+    pub fn add_in(
+        &mut self,
+        namespace: &str,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        let key = self.writable()?.add_in(namespace, key_name, value)?;
+        self.header.last_write = timestamp();
+        Ok(key)
+    }
+
+    /// Removes a key within a namespace.
+    pub fn remove_in(&mut self, namespace: &str, key_name: String) -> Result<bool, DatabaseError> {
+        let removed = self.writable()?.remove_in(namespace, key_name)?;
+        if removed {
+            self.header.last_write = timestamp();
+        }
+        Ok(removed)
+    }
+
+    /// Every key within a namespace.
+    pub fn fetch_keys_in(&mut self, namespace: &str) -> Result<Vec<VirtualKey>, DatabaseError> {
+        self.virtual_db()?.fetch_keys_in(namespace)
+    }
+
+    /// Subscribes to every future set and remove on keys starting with `prefix`.
+    pub fn subscribe(&mut self, prefix: String) -> Result<Receiver<Change>, DatabaseError> {
+        Ok(self.virtual_db()?.subscribe(prefix))
+    }
+
+    /// Sets the key only if it is at `expected_version`.
+    /// See `VirtualDatabase::compare_and_set`.
+    pub fn compare_and_set(
+        &mut self,
+        key_name: String,
+        expected_version: u64,
+        value: Vec<u8>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        let key = self
+            .writable()?
+            .compare_and_set(key_name, expected_version, value)?;
+        self.header.last_write = timestamp();
+        Ok(key)
+    }
+
+    /// Removes the key only if it is at `expected_version`.
+    /// See `VirtualDatabase::compare_and_remove`.
+    pub fn compare_and_remove(
+        &mut self,
+        key_name: String,
+        expected_version: u64,
+    ) -> Result<bool, DatabaseError> {
+        let removed = self
+            .writable()?
+            .compare_and_remove(key_name, expected_version)?;
+        if removed {
+            self.header.last_write = timestamp();
+        }
+        Ok(removed)
+    }
+
+    /// Sets the key to a deduplicated value.
+    /// See `VirtualDatabase::set_blob`.
+    pub fn set_blob(&mut self, key_name: String, value: Vec<u8>) -> Result<BlobKey, DatabaseError> {
+        let key = self.writable()?.set_blob(key_name, value)?;
+        self.header.last_write = timestamp();
+        Ok(key)
+    }
+
+    /// Gets the value of a key that points at a blob.
+    pub fn get_blob(&mut self, key_name: String) -> Result<VirtualItem, DatabaseError> {
+        self.virtual_db()?.get_blob(key_name)
+    }
+
+    /// Removes a key that points at a blob, and the blob with its last reference.
+    pub fn remove_blob(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        let removed = self.writable()?.remove_blob(key_name)?;
+        if removed {
+            self.header.last_write = timestamp();
+        }
+        Ok(removed)
+    }
+
+    /// The amount of keys pointing at the blob with the given hash.
+    pub fn blob_references(&mut self, hash: &BlobHash) -> Result<u64, DatabaseError> {
+        self.virtual_db()?.blob_references(hash)
+    }
+
+    /// The names of every key that points at a blob, sorted.
+    pub fn fetch_blob_keys(&mut self) -> Result<Vec<String>, DatabaseError> {
+        self.virtual_db()?.fetch_blob_keys()
+    }
+
+    /// Gets many keys at once, with a result for every key in the order requested.
+    /// See `VirtualDatabase::get_many`.
+    pub fn get_many(
+        &mut self,
+        key_names: Vec<String>,
+    ) -> Result<Vec<Result<VirtualItem, DatabaseError>>, DatabaseError> {
+        self.virtual_db()?.get_many(key_names)
+    }
+
+    /// Sets many keys at once, with a result for every key in the order given.
+    /// See `VirtualDatabase::set_many`.
+    pub fn set_many(
+        &mut self,
+        pairs: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<Result<VirtualKey, DatabaseError>>, DatabaseError> {
+        let results = self.writable()?.set_many(pairs)?;
+        if results.iter().any(|result| result.is_ok()) {
+            self.header.last_write = timestamp();
+        }
+        Ok(results)
+    }
+
+    /// Sets the mode new values are compressed with, in the database preamble and every partition.
+    /// See `VirtualDatabase::set_compression`.
+    pub fn set_compression(&mut self, mode: CompressionMode) -> Result<(), DatabaseError> {
+        self.writable()?.set_compression(mode)?;
+        self.preamble.compression = mode;
+        let mut db_file = OpenOptions::new().write(true).open(&self.path)?;
+        self.preamble.write(&mut db_file)?;
+        db_file.sync_data()?;
+        Ok(())
+    }
+
+    /// Sets the mode new values written to one partition are compressed with.
+    /// See `VirtualDatabase::set_partition_compression`.
+    pub fn set_partition_compression(
+        &mut self,
+        id: u8,
+        mode: CompressionMode,
+    ) -> Result<(), DatabaseError> {
+        self.writable()?.set_partition_compression(id, mode)
+    }
+
+    /// Sets the zstd level new values are compressed at, in the database preamble and every
+    /// partition. See `VirtualDatabase::set_compression_level`.
+    pub fn set_compression_level(&mut self, level: i32) -> Result<(), DatabaseError> {
+        self.writable()?.set_compression_level(level)?;
+        self.preamble.level = level;
+        let mut db_file = OpenOptions::new().write(true).open(&self.path)?;
+        self.preamble.write(&mut db_file)?;
+        db_file.sync_data()?;
+        Ok(())
+    }
+
+    /// Trains a new zstd dictionary from the small values within the database.
+    /// Returns the version of the dictionary, see `VirtualDatabase::train_dictionary`.
+    pub fn train_dictionary(&mut self) -> Result<u32, DatabaseError> {
+        let version = self.writable()?.train_dictionary()?;
+        self.header.last_write = timestamp();
+        Ok(version)
+    }
+
+    /// The versions of every dictionary, oldest first.
+    pub fn dictionary_versions(&mut self) -> Result<Vec<u32>, DatabaseError> {
+        Ok(self.virtual_db()?.dictionary_versions())
+    }
+
+    /// Configures the storage tiers of the database.
+    /// See `VirtualDatabase::set_tiers`.
+    pub fn set_tiers(&mut self, tiers: StorageTiers) -> Result<(), DatabaseError> {
+        self.writable()?.set_tiers(tiers)
+    }
+
+    /// Where every partition is stored.
+    pub fn placements(&mut self) -> Result<Vec<Placement>, DatabaseError> {
+        Ok(self.virtual_db()?.placements())
+    }
+
+    /// Moves a partition into the given tier, returning its new path.
+    /// See `VirtualDatabase::move_partition`.
+    pub fn move_partition(&mut self, id: u8, tier: Tier) -> Result<PathBuf, DatabaseError> {
+        self.writable()?.move_partition(id, tier)
+    }
+
+    /// Moves the partitions that the policy says belong in another tier.
+    /// Returns the partitions that moved, with their new tier.
+    pub fn apply_tiering(
+        &mut self,
+        policy: &TieringPolicy,
+    ) -> Result<Vec<(u8, Tier)>, DatabaseError> {
+        self.writable()?.apply_tiering(policy)
+    }
+}
+
+impl InternalApi for Database {
+    type KeyKind = VirtualKey;
+    type ValueKind = VirtualItem;
+
+    fn get(&mut self, key_name: String) -> Result<Self::ValueKind, DatabaseError> {
+        self.virtual_db()?.get(key_name)
+    }
+
+    fn get_range(
+        &mut self,
+        key_name: String,
+        offset: u64,
+        length: u64,
+    ) -> Result<Self::ValueKind, DatabaseError> {
+        self.virtual_db()?.get_range(key_name, offset, length)
+    }
+
+    fn set(&mut self, key_name: String, value: Vec<u8>) -> Result<Self::KeyKind, DatabaseError> {
+        let key = self.writable()?.set(key_name, value)?;
+        self.header.last_write = timestamp();
+        Ok(key)
+    }
+
+    fn add(&mut self, key_name: String, value: Vec<u8>) -> Result<Self::KeyKind, DatabaseError> {
+        let key = self.writable()?.add(key_name, value)?;
+        self.header.last_write = timestamp();
+        Ok(key)
+    }
+
+    fn remove(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        let removed = self.writable()?.remove(key_name)?;
+        if removed {
+            self.header.last_write = timestamp();
+        }
+        Ok(removed)
+    }
+
+    fn fetch_keys(&mut self) -> Result<Vec<Self::KeyKind>, DatabaseError> {
+        self.virtual_db()?.fetch_keys()
+    }
+}
+
+/// The error for databases that are not virtualized.
+fn unsupported() -> DatabaseError {
+    DatabaseError::Implementation("Non-Virtualized databases are not supported yet".to_string())
 }
@@ -0,0 +1,146 @@
+//! Zstd dictionaries for compressing small values.
+//!
+//! Small values (such as JSON metadata) compress poorly on their own, because there is
+//! little within a single value to refer back to. A dictionary is trained from a sample of
+//! the values within the database, and small values are compressed against it.
+//!
+//! Dictionaries are versioned, and stored within the database under `\0dict\0{version}`.
+//! Retraining adds a new version, which new values are compressed with, while values
+//! compressed with an older version keep reading it.
+use crate::{
+    blob, namespace,
+    utils::InternalApi,
+    virtual_db::{VirtualDatabase, VirtualKey},
+    DatabaseError,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+/// The prefix of the names dictionaries are stored under.
+const DICTIONARY_PREFIX: &str = "\0dict\0";
+
+/// The largest size of a trained dictionary.
+pub const DICTIONARY_SIZE: usize = 16 * 1024;
+
+/// Values up to this length are sampled for training, and compressed with the dictionary.
+pub const DICTIONARY_VALUE_LEN: usize = 4 * 1024;
+
+/// The largest amount of values sampled for training.
+pub const MAX_SAMPLES: usize = 4096;
+
+/// The name the dictionary with the given version is stored under.
+pub fn dictionary_key(version: u32) -> String {
+    format!("{}{}", DICTIONARY_PREFIX, version)
+}
+
+/// The version of the dictionary, if the stored key is a dictionary.
+pub fn dictionary_version(key_name: &str) -> Option<u32> {
+    key_name.strip_prefix(DICTIONARY_PREFIX)?.parse().ok()
+}
+
+/// Whether or not the value of the stored key is written by users, and so is sampled.
+fn is_sampled(key_name: &str) -> bool {
+    !namespace::is_reserved(key_name)
+        || namespace::split_key(key_name).is_some()
+        || key_name.starts_with(blob::BLOB_PREFIX)
+}
+
+/// The dictionaries of a database, by version.
+/// Clones share the same dictionaries, so the partitions and snapshots of a database
+/// see a dictionary as soon as it is trained.
+#[derive(Clone, Debug, Default)]
+pub struct Dictionaries {
+    versions: Arc<RwLock<BTreeMap<u32, Arc<Vec<u8>>>>>,
+}
+
+impl Dictionaries {
+    /// Adds a dictionary.
+    pub fn insert(&self, version: u32, dictionary: Vec<u8>) {
+        self.versions
+            .write()
+            .unwrap()
+            .insert(version, Arc::new(dictionary));
+    }
+
+    /// The dictionary with the given version.
+    pub fn get(&self, version: u32) -> Result<Arc<Vec<u8>>, DatabaseError> {
+        self.versions
+            .read()
+            .unwrap()
+            .get(&version)
+            .cloned()
+            .ok_or_else(|| {
+                DatabaseError::Implementation(format!("Dictionary {} is missing", version))
+            })
+    }
+
+    /// The newest dictionary, with its version.
+    pub fn latest(&self) -> Option<(u32, Arc<Vec<u8>>)> {
+        self.versions
+            .read()
+            .unwrap()
+            .iter()
+            .next_back()
+            .map(|(version, dictionary)| (*version, dictionary.clone()))
+    }
+}
+
+impl VirtualDatabase {
+    /// Trains a new dictionary from the small values within the database.
+    /// Values written after training are compressed with it, if they are small.
+    /// Returns the version of the new dictionary.
+    pub fn train_dictionary(&mut self) -> Result<u32, DatabaseError> {
+        // the values are sampled from every partition, reading each partition once.
+        let mut keys: HashMap<u64, Vec<VirtualKey>> = HashMap::new();
+        for key in self
+            .fetch_raw_keys()?
+            .into_iter()
+            .filter(|key| key.length <= DICTIONARY_VALUE_LEN && is_sampled(&key.name))
+            .take(MAX_SAMPLES)
+        {
+            keys.entry(key.location.id).or_default().push(key);
+        }
+        let mut samples: Vec<Vec<u8>> = Vec::new();
+        for (id, keys) in keys {
+            let index = self.partition_index(id)?;
+            samples.extend(self.parts[index].read_many(&keys)?);
+        }
+
+        let dictionary = zstd::dict::from_samples(&samples, DICTIONARY_SIZE).map_err(|error| {
+            DatabaseError::Implementation(format!("Could not train a dictionary: {}", error))
+        })?;
+        let version = self
+            .dictionaries
+            .latest()
+            .map_or(1, |(latest, _)| latest + 1);
+        self.set_raw(dictionary_key(version), &dictionary)?;
+        self.dictionaries.insert(version, dictionary);
+        Ok(version)
+    }
+
+    /// The versions of every dictionary, oldest first.
+    pub fn dictionary_versions(&self) -> Vec<u32> {
+        self.dictionaries
+            .versions
+            .read()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    /// Reads the dictionaries from the partitions.
+    pub(crate) fn load_dictionaries(&mut self) -> Result<(), DatabaseError> {
+        for partition in self.parts.iter_mut() {
+            for key in partition.fetch_keys()? {
+                if let Some(version) = dictionary_version(&key.name) {
+                    let dictionary = partition.read_at(&key)?;
+                    self.dictionaries.insert(version, dictionary);
+                }
+            }
+        }
+        Ok(())
+    }
+}
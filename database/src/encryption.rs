@@ -0,0 +1,806 @@
+//! At-rest encryption of databases.
+//!
+//! An encrypted database has a random master key, which seals the header and the values of
+//! every partition with XChaCha20-Poly1305. The master key is stored within the database
+//! file in key slots, each unlocked by a key that is derived from a password or keyfile with
+//! Argon2id, so a wrong password is noticed before any data is read. A slot wraps the master
+//! key for its own X25519 key pair, so a new master key is wrapped for every slot when the
+//! master key is rotated, without the passwords or keyfiles of the slots.
+//!
+//! Encrypted files have a key block and a sealed header after the preamble:
+//!
+//! | Name      | Type   | Byte Length     | Description                                          |
+//! | --------- | ------ | --------------- | ---------------------------------------------------- |
+//! | key block | -      | `KEY_BLOCK_LEN` | The key slots and rotation. Zeroed in partition files. |
+//! | length    | `u16`  | 2               | The length of the sealed header.                     |
+//! | header    | `[u8]` | length          | The header, sealed with the master key.              |
+//!
+//! How the records are sealed depends on the `EncryptionLevel` of the preamble.
+//! From `EncryptionLevel::Values`, values are sealed after their length once decoded,
+//! which stays readable so records can be read without decrypting every value:
+//!
+//! | Name  | Type       | Byte Length | Description                                        |
+//! | ----- | ---------- | ----------- | -------------------------------------------------- |
+//! | size  | `u64`      | 8           | The length of the value once decrypted and decoded. |
+//! | nonce | `[u8; 24]` | 24          | The random nonce the value was sealed with.        |
+//! | data  | `[u8]`     | remaining   | The value as compressed, with the tag after it.    |
+//!
+//! A sealed value is bound to its encoding, its size and the name of its key, so it can not
+//! be copied onto another key without being noticed.
+//!
+//! From `EncryptionLevel::Names`, the name of the key is sealed as well, and the record
+//! holds the nonce and sealed name in its place. Names are sealed deterministically: their
+//! nonce is the token of the name, a keyed SHA3-256 of it, so a key is found by the token
+//! of its plaintext name without opening the name of every record. The bloom filter of a
+//! partition holds the tokens instead of the names. The same name always seals the same
+//! way under a master key, so records of the same key can be told apart from others.
+//!
+//! With `EncryptionLevel::Full`, every record is sealed as a whole instead, so nothing but
+//! the length of the sealed record is readable:
+//!
+//! | Name   | Type   | Byte Length | Description                                            |
+//! | ------ | ------ | ----------- | ------------------------------------------------------ |
+//! | length | `u64`  | 8           | The length of the sealed record.                       |
+//! | record | `[u8]` | length      | The nonce, and the record with its value as compressed. |
+use crate::{
+    compression::Encoding,
+    db::{Database, Header},
+    DatabaseError,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use sha3::{Digest, Sha3_256};
+use std::{
+    fmt,
+    io::{Cursor, Read, Write},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// The length of the master key, and of keys derived from passwords.
+pub const KEY_LEN: usize = 32;
+
+/// The length of the random nonce before every sealed message.
+pub const NONCE_LEN: usize = 24;
+
+/// The length of the authentication tag after every sealed message.
+pub const TAG_LEN: usize = 16;
+
+/// The amount of bytes sealing adds to a message.
+pub const SEAL_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+/// The length of the salt passwords are derived with.
+pub const SALT_LEN: usize = 16;
+
+/// The amount of key slots in the key block.
+pub const KEY_SLOTS: usize = 8;
+
+/// The length of a single key slot.
+pub const KEY_SLOT_LEN: usize = 1 + SALT_LEN + 12 + 2 * (KEY_LEN + KEY_LEN + SEAL_OVERHEAD);
+
+/// The length of the rotation after the key slots, which is zeroed unless the
+/// master key is being rotated.
+pub const ROTATION_LEN: usize = 2 + KEY_LEN + SEAL_OVERHEAD;
+
+/// The length of the key block after the preamble of an encrypted file.
+pub const KEY_BLOCK_LEN: usize = KEY_SLOTS * KEY_SLOT_LEN + ROTATION_LEN;
+
+/// Messages are bound to what they are, so one can not be swapped for another.
+const KEY_AAD: &[u8] = b"onelink master key";
+const PRIVATE_AAD: &[u8] = b"onelink slot key";
+const AGREED_CONTEXT: &[u8] = b"onelink slot agreement";
+const HEADER_AAD: &[u8] = b"onelink header";
+const PREVIOUS_AAD: &[u8] = b"onelink previous key";
+const RECORD_AAD: &[u8] = b"onelink record";
+const NAME_AAD: &[u8] = b"onelink key name";
+const TOKEN_CONTEXT: &[u8] = b"onelink name token";
+
+/// The length of the token of a key name, which is also the nonce its name is sealed with.
+pub const TOKEN_LEN: usize = NONCE_LEN;
+
+/// The length of the length before a record sealed as a whole.
+pub const RECORD_PREFIX_LEN: usize = 8;
+
+/// Fills a buffer with random bytes from the operating system.
+pub fn random_bytes<const N: usize>() -> Result<[u8; N], DatabaseError> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).map_err(|error| {
+        DatabaseError::Implementation(format!("Could not generate random bytes: {}", error))
+    })?;
+    Ok(bytes)
+}
+
+/// A key that seals (encrypts and authenticates) messages.
+/// Clones share the same key, which is zeroed once the last clone is dropped.
+/// While the master key is rotated, the cipher also opens messages sealed with the
+/// previous key, but only seals with the new one.
+#[derive(Clone)]
+pub struct Cipher {
+    key: Arc<Zeroizing<[u8; KEY_LEN]>>,
+    previous: Option<Arc<Zeroizing<[u8; KEY_LEN]>>>,
+}
+
+impl Cipher {
+    /// Generates a new random key.
+    pub fn generate() -> Result<Self, DatabaseError> {
+        Ok(Self::from_key(random_bytes()?))
+    }
+
+    fn from_key(key: [u8; KEY_LEN]) -> Self {
+        Self {
+            key: Arc::new(Zeroizing::new(key)),
+            previous: None,
+        }
+    }
+
+    /// The same key, which also opens messages sealed with the `previous` key.
+    pub fn with_previous(&self, previous: &Cipher) -> Self {
+        Self {
+            key: self.key.clone(),
+            previous: Some(previous.key.clone()),
+        }
+    }
+
+    /// The same key, without the previous key.
+    pub fn current(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            previous: None,
+        }
+    }
+
+    /// Whether or not the cipher also opens messages sealed with a previous key.
+    pub fn has_previous(&self) -> bool {
+        self.previous.is_some()
+    }
+
+    fn aead(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(self.key.as_slice().into())
+    }
+
+    /// Seals a message, returning the nonce followed by the encrypted message and its tag.
+    /// The `aad` is authenticated, but not stored.
+    pub fn seal(&self, message: &[u8], aad: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+        self.seal_with(&random_bytes()?, message, aad)
+    }
+
+    /// Seals a message with the given nonce, see `Cipher::seal`.
+    /// A nonce must never be used for two different messages.
+    fn seal_with(
+        &self,
+        nonce: &[u8; NONCE_LEN],
+        message: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let sealed = self
+            .aead()
+            .encrypt(XNonce::from_slice(nonce), Payload { msg: message, aad })
+            .map_err(|_| DatabaseError::Implementation("Could not encrypt".to_string()))?;
+        let mut data = Vec::with_capacity(NONCE_LEN + sealed.len());
+        data.extend_from_slice(nonce);
+        data.extend_from_slice(&sealed);
+        Ok(data)
+    }
+
+    /// Opens a sealed message, which fails if it was sealed with another key or changed.
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+        if sealed.len() < SEAL_OVERHEAD {
+            return Err(corrupted());
+        }
+        let (nonce, message) = sealed.split_at(NONCE_LEN);
+        let payload = || Payload { msg: message, aad };
+        self.aead()
+            .decrypt(XNonce::from_slice(nonce), payload())
+            .or_else(|error| match self.previous.as_ref() {
+                Some(previous) => XChaCha20Poly1305::new(previous.as_slice().into())
+                    .decrypt(XNonce::from_slice(nonce), payload()),
+                None => Err(error),
+            })
+            .map_err(|_| corrupted())
+    }
+
+    /// The token of a key name, which is the same for every record of the key, but can not
+    /// be worked out without the master key.
+    pub fn token(&self, name: &str) -> [u8; TOKEN_LEN] {
+        token(&self.key, name)
+    }
+
+    /// The tokens a key name may have on disk, under the key and the previous key.
+    pub fn tokens(&self, name: &str) -> Vec<[u8; TOKEN_LEN]> {
+        let mut tokens = vec![self.token(name)];
+        if let Some(previous) = self.previous.as_ref() {
+            tokens.push(token(previous, name));
+        }
+        tokens
+    }
+}
+
+fn token(key: &[u8; KEY_LEN], name: &str) -> [u8; TOKEN_LEN] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(TOKEN_CONTEXT);
+    hasher.update(key);
+    hasher.update(name.as_bytes());
+    let mut token = [0; TOKEN_LEN];
+    token.copy_from_slice(&hasher.finalize()[..TOKEN_LEN]);
+    token
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Cipher(..)")
+    }
+}
+
+/// How much work deriving a key from a password takes, see Argon2id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    /// The memory used, in KiB.
+    pub memory_kib: u32,
+    /// The amount of passes over the memory.
+    pub iterations: u32,
+    /// The amount of lanes.
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// Derives a key from the password.
+    pub fn derive(&self, password: &[u8], salt: &[u8]) -> Result<Cipher, DatabaseError> {
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LEN),
+        )
+        .map_err(|error| DatabaseError::Implementation(format!("Invalid KDF: {}", error)))?;
+        let mut key = Zeroizing::new([0; KEY_LEN]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password, salt, key.as_mut())
+            .map_err(|error| DatabaseError::Implementation(format!("Invalid KDF: {}", error)))?;
+        Ok(Cipher::from_key(*key))
+    }
+}
+
+impl Default for KdfParams {
+    /// The minimum recommended by OWASP for Argon2id.
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// What unlocks a key slot.
+#[derive(Clone, Copy)]
+pub enum Secret<'a> {
+    /// A password, typed by someone.
+    Password(&'a str),
+    /// The contents of a keyfile.
+    Keyfile(&'a [u8]),
+}
+
+impl Secret<'_> {
+    /// The kind of slot the secret unlocks.
+    pub fn kind(&self) -> SlotKind {
+        match self {
+            Secret::Password(_) => SlotKind::Password,
+            Secret::Keyfile(_) => SlotKind::Keyfile,
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Secret::Password(password) => password.as_bytes(),
+            Secret::Keyfile(keyfile) => keyfile,
+        }
+    }
+}
+
+/// The kind of secret a key slot is unlocked by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SlotKind {
+    Password = 1,
+    Keyfile = 2,
+}
+
+impl SlotKind {
+    pub fn from_u8(kind: u8) -> Result<Self, DatabaseError> {
+        match kind {
+            1 => Ok(SlotKind::Password),
+            2 => Ok(SlotKind::Keyfile),
+            _ => Err(corrupted()),
+        }
+    }
+}
+
+/// The master key, wrapped for a password or keyfile.
+///
+/// Every slot has an X25519 key pair. The private key is wrapped by a key derived from the
+/// secret, and the master key is sealed with a key agreed between the public key and a
+/// one-off ephemeral key, so the master key can be wrapped for the slot again without
+/// its secret, see `KeySlot::wrap`.
+///
+/// | Name        | Type       | Byte Length |
+/// | ----------- | ---------- | ----------- |
+/// | kind        | `u8`       | 1           |
+/// | salt        | `[u8; 16]` | 16          |
+/// | memory      | `u32`      | 4           |
+/// | iterations  | `u32`      | 4           |
+/// | parallelism | `u32`      | 4           |
+/// | public      | `[u8; 32]` | 32          |
+/// | private     | `[u8]`     | 72          |
+/// | ephemeral   | `[u8; 32]` | 32          |
+/// | wrapped     | `[u8]`     | 72          |
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeySlot {
+    pub kind: SlotKind,
+    pub salt: [u8; SALT_LEN],
+    pub kdf: KdfParams,
+    public: [u8; KEY_LEN],
+    private: Vec<u8>,
+    ephemeral: [u8; KEY_LEN],
+    wrapped: Vec<u8>,
+}
+
+impl KeySlot {
+    /// Wraps the master key for a new key pair, whose private key is wrapped by a key
+    /// derived from the secret.
+    pub fn new(secret: Secret, master: &Cipher, kdf: KdfParams) -> Result<Self, DatabaseError> {
+        let salt = random_bytes()?;
+        let private = StaticSecret::from(random_bytes::<KEY_LEN>()?);
+        let mut slot = Self {
+            kind: secret.kind(),
+            salt,
+            kdf,
+            public: PublicKey::from(&private).to_bytes(),
+            private: kdf
+                .derive(secret.bytes(), &salt)?
+                .seal(private.as_bytes(), PRIVATE_AAD)?,
+            ephemeral: [0; KEY_LEN],
+            wrapped: Vec::new(),
+        };
+        slot.wrap(master)?;
+        Ok(slot)
+    }
+
+    /// Wraps another master key for the slot, with a new ephemeral key.
+    /// Only the public key of the slot is used, so the secret is not needed.
+    pub fn wrap(&mut self, master: &Cipher) -> Result<(), DatabaseError> {
+        let ephemeral = StaticSecret::from(random_bytes::<KEY_LEN>()?);
+        let public = PublicKey::from(&ephemeral).to_bytes();
+        let shared = ephemeral.diffie_hellman(&PublicKey::from(self.public));
+        if !shared.was_contributory() {
+            return Err(corrupted());
+        }
+        self.wrapped = agreed_key(shared.as_bytes(), &public, &self.public)
+            .seal(master.key.as_ref().as_ref(), KEY_AAD)?;
+        self.ephemeral = public;
+        Ok(())
+    }
+
+    /// Unwraps the master key, or fails with `DatabaseError::IncorrectPassword`.
+    pub fn unlock(&self, secret: Secret) -> Result<Cipher, DatabaseError> {
+        if secret.kind() != self.kind {
+            return Err(DatabaseError::IncorrectPassword);
+        }
+        let private = Zeroizing::new(
+            self.kdf
+                .derive(secret.bytes(), &self.salt)?
+                .open(&self.private, PRIVATE_AAD)
+                .map_err(|_| DatabaseError::IncorrectPassword)?,
+        );
+        let private: [u8; KEY_LEN] = private.as_slice().try_into().map_err(|_| corrupted())?;
+        let shared = StaticSecret::from(private).diffie_hellman(&PublicKey::from(self.ephemeral));
+        if !shared.was_contributory() {
+            return Err(corrupted());
+        }
+        let key = Zeroizing::new(
+            agreed_key(shared.as_bytes(), &self.ephemeral, &self.public)
+                .open(&self.wrapped, KEY_AAD)?,
+        );
+        let key: [u8; KEY_LEN] = key.as_slice().try_into().map_err(|_| corrupted())?;
+        Ok(Cipher::from_key(key))
+    }
+
+    fn read(slot: &[u8]) -> Result<Option<Self>, DatabaseError> {
+        if slot.iter().all(|byte| *byte == 0) {
+            return Ok(None);
+        }
+        let mut cursor = Cursor::new(slot);
+        let kind = SlotKind::from_u8(cursor.read_u8()?)?;
+        let mut salt = [0; SALT_LEN];
+        cursor.read_exact(&mut salt)?;
+        let kdf = KdfParams {
+            memory_kib: cursor.read_u32::<BE>()?,
+            iterations: cursor.read_u32::<BE>()?,
+            parallelism: cursor.read_u32::<BE>()?,
+        };
+        let mut public = [0; KEY_LEN];
+        cursor.read_exact(&mut public)?;
+        let mut private = vec![0; KEY_LEN + SEAL_OVERHEAD];
+        cursor.read_exact(&mut private)?;
+        let mut ephemeral = [0; KEY_LEN];
+        cursor.read_exact(&mut ephemeral)?;
+        let wrapped = slot[cursor.position() as usize..].to_vec();
+        Ok(Some(Self {
+            kind,
+            salt,
+            kdf,
+            public,
+            private,
+            ephemeral,
+            wrapped,
+        }))
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<(), DatabaseError> {
+        writer.write_u8(self.kind as u8)?;
+        writer.write_all(&self.salt)?;
+        writer.write_u32::<BE>(self.kdf.memory_kib)?;
+        writer.write_u32::<BE>(self.kdf.iterations)?;
+        writer.write_u32::<BE>(self.kdf.parallelism)?;
+        writer.write_all(&self.public)?;
+        writer.write_all(&self.private)?;
+        writer.write_all(&self.ephemeral)?;
+        writer.write_all(&self.wrapped)?;
+        Ok(())
+    }
+}
+
+/// The key the master key of a slot is sealed with, from the secret agreed between the
+/// ephemeral key and the key pair of the slot, bound to both public keys.
+fn agreed_key(shared: &[u8; KEY_LEN], ephemeral: &[u8; KEY_LEN], public: &[u8; KEY_LEN]) -> Cipher {
+    let mut hasher = Sha3_256::new();
+    hasher.update(AGREED_CONTEXT);
+    hasher.update(shared);
+    hasher.update(ephemeral);
+    hasher.update(public);
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    key.copy_from_slice(&hasher.finalize());
+    Cipher::from_key(*key)
+}
+
+/// How far the rotation of the master key has come.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyRotation {
+    /// The amount of partitions that are encrypted with the new key.
+    pub rotated: u16,
+    /// The amount of partitions in the database.
+    pub partitions: u16,
+}
+
+impl KeyRotation {
+    /// Whether or not every partition is encrypted with the new key.
+    pub fn is_done(&self) -> bool {
+        self.rotated >= self.partitions
+    }
+}
+
+/// Encrypts the partitions again on a thread of its own, continuing the rotation started by
+/// `Database::begin_key_rotation`. The database is locked for `step` partitions at a time,
+/// and unlocked for `pause` in between, so it keeps serving requests while it is rotated.
+/// The thread returns once every partition is encrypted with the new key, or with the first
+/// error, after which the rotation can be continued again.
+pub fn rotate_in_background(
+    db: Arc<Mutex<Database>>,
+    step: u16,
+    pause: Duration,
+) -> JoinHandle<Result<KeyRotation, DatabaseError>> {
+    thread::spawn(move || loop {
+        let rotation = db
+            .lock()
+            .map_err(|_| DatabaseError::Implementation("Database lock is poisoned".into()))?
+            .rotate_partitions(step.max(1))?;
+        if rotation.is_done() {
+            return Ok(rotation);
+        }
+        thread::sleep(pause);
+    })
+}
+
+/// The previous master key while the master key is rotated.
+///
+/// | Name     | Type   | Byte Length | Description                                         |
+/// | -------- | ------ | ----------- | --------------------------------------------------- |
+/// | rotated  | `u16`  | 2           | The amount of partitions encrypted with the new key. |
+/// | previous | `[u8]` | 72          | The previous master key, sealed with the new one.   |
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rotation {
+    rotated: u16,
+    previous: Vec<u8>,
+}
+
+/// The key slots of a database, `KEY_SLOTS` of which fit in the key block.
+/// Every slot wraps the same master key, so slots are added and removed
+/// without encrypting the data again. Free slots are zeroed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyBlock {
+    slots: Vec<Option<KeySlot>>,
+    rotation: Option<Rotation>,
+}
+
+impl KeyBlock {
+    /// Creates a key block with the master key in the first slot.
+    pub fn new(secret: Secret, master: &Cipher, kdf: KdfParams) -> Result<Self, DatabaseError> {
+        let mut slots = vec![None; KEY_SLOTS];
+        slots[0] = Some(KeySlot::new(secret, master, kdf)?);
+        Ok(Self {
+            slots,
+            rotation: None,
+        })
+    }
+
+    /// Unwraps the master key with the first slot the secret unlocks,
+    /// or fails with `DatabaseError::IncorrectPassword`.
+    /// Only the slots of the same kind as the secret are tried.
+    /// While the master key is rotated, the cipher also opens what the previous key sealed.
+    pub fn unlock(&self, secret: Secret) -> Result<Cipher, DatabaseError> {
+        let master = self
+            .slots
+            .iter()
+            .flatten()
+            .filter(|slot| slot.kind == secret.kind())
+            .find_map(|slot| slot.unlock(secret).ok())
+            .ok_or(DatabaseError::IncorrectPassword)?;
+        match self.rotation.as_ref() {
+            Some(rotation) => {
+                let previous = Zeroizing::new(master.open(&rotation.previous, PREVIOUS_AAD)?);
+                let previous: [u8; KEY_LEN] =
+                    previous.as_slice().try_into().map_err(|_| corrupted())?;
+                Ok(master.with_previous(&Cipher::from_key(previous)))
+            }
+            None => Ok(master),
+        }
+    }
+
+    /// Starts rotating the master key, returning the new master key.
+    /// Every slot wraps the new key in place of the previous one, so every password and
+    /// keyfile keeps unlocking the database.
+    /// The previous key is kept, sealed with the new one, until `finish_rotation`.
+    pub fn begin_rotation(&mut self, master: &Cipher) -> Result<Cipher, DatabaseError> {
+        if self.rotation.is_some() {
+            return Err(DatabaseError::Implementation(
+                "The master key is already being rotated".to_string(),
+            ));
+        }
+        let next = Cipher::generate()?;
+        let mut slots = self.slots.clone();
+        for slot in slots.iter_mut().flatten() {
+            slot.wrap(&next)?;
+        }
+        self.slots = slots;
+        self.rotation = Some(Rotation {
+            rotated: 0,
+            previous: next.seal(master.key.as_ref().as_ref(), PREVIOUS_AAD)?,
+        });
+        Ok(next.with_previous(master))
+    }
+
+    /// The amount of partitions encrypted with the new key, if the master key is being rotated.
+    pub fn rotated(&self) -> Option<u16> {
+        self.rotation.as_ref().map(|rotation| rotation.rotated)
+    }
+
+    /// Records that the first `rotated` partitions are encrypted with the new key.
+    pub fn set_rotated(&mut self, rotated: u16) {
+        if let Some(rotation) = self.rotation.as_mut() {
+            rotation.rotated = rotated;
+        }
+    }
+
+    /// Forgets the previous key, once nothing is sealed with it anymore.
+    pub fn finish_rotation(&mut self) {
+        self.rotation = None;
+    }
+
+    /// Wraps the master key with the secret in the first free slot, returning its index.
+    pub fn add(
+        &mut self,
+        secret: Secret,
+        master: &Cipher,
+        kdf: KdfParams,
+    ) -> Result<u8, DatabaseError> {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| slot.is_none())
+            .ok_or_else(|| DatabaseError::Implementation("Every key slot is used".to_string()))?;
+        self.slots[index] = Some(KeySlot::new(secret, master, kdf)?);
+        Ok(index as u8)
+    }
+
+    /// Frees a slot. The last slot can not be removed, or the database could not be unlocked.
+    pub fn remove(&mut self, index: u8) -> Result<KeySlot, DatabaseError> {
+        if self.slots.iter().flatten().count() == 1 {
+            return Err(DatabaseError::Implementation(
+                "The last key slot can not be removed".to_string(),
+            ));
+        }
+        self.slots
+            .get_mut(index as usize)
+            .and_then(Option::take)
+            .ok_or_else(|| DatabaseError::Implementation(format!("Key slot {} is free", index)))
+    }
+
+    /// The index and kind of every slot that is used.
+    pub fn slots(&self) -> Vec<(u8, SlotKind)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| slot.as_ref().map(|slot| (index as u8, slot.kind)))
+            .collect()
+    }
+
+    /// Reads a key block, which is `None` if it is zeroed.
+    pub fn read(reader: &mut dyn Read) -> Result<Option<Self>, DatabaseError> {
+        let mut block = vec![0; KEY_BLOCK_LEN];
+        reader.read_exact(&mut block)?;
+        let (slots, rotation) = block.split_at(KEY_SLOTS * KEY_SLOT_LEN);
+        let slots = slots
+            .chunks(KEY_SLOT_LEN)
+            .map(KeySlot::read)
+            .collect::<Result<Vec<_>, _>>()?;
+        let rotation = match rotation.iter().all(|byte| *byte == 0) {
+            true => None,
+            false => Some(Rotation {
+                rotated: Cursor::new(rotation).read_u16::<BE>()?,
+                previous: rotation[2..].to_vec(),
+            }),
+        };
+        match slots.iter().any(Option::is_some) {
+            true => Ok(Some(Self { slots, rotation })),
+            false => Ok(None),
+        }
+    }
+
+    /// Writes the key block, or a zeroed one if there is none.
+    pub fn write(block: Option<&Self>, writer: &mut dyn Write) -> Result<(), DatabaseError> {
+        match block {
+            Some(block) => {
+                for slot in block.slots.iter() {
+                    match slot {
+                        Some(slot) => slot.write(writer)?,
+                        None => writer.write_all(&[0; KEY_SLOT_LEN])?,
+                    }
+                }
+                match block.rotation.as_ref() {
+                    Some(rotation) => {
+                        writer.write_u16::<BE>(rotation.rotated)?;
+                        writer.write_all(&rotation.previous)?;
+                    }
+                    None => writer.write_all(&[0; ROTATION_LEN])?,
+                }
+            }
+            None => writer.write_all(&[0; KEY_BLOCK_LEN])?,
+        }
+        Ok(())
+    }
+}
+
+/// Writes the header of an encrypted file, sealed with the (current) master key.
+pub fn write_header(
+    header: &Header,
+    cipher: &Cipher,
+    writer: &mut dyn Write,
+) -> Result<(), DatabaseError> {
+    let mut plain = Vec::with_capacity(header.byte_len());
+    header.write(&mut plain)?;
+    let sealed = cipher.seal(&plain, HEADER_AAD)?;
+    writer.write_u16::<BE>(sealed.len() as u16)?;
+    writer.write_all(&sealed)?;
+    Ok(())
+}
+
+/// Reads the header of an encrypted file, opening it with the master key.
+pub fn read_header(reader: &mut dyn Read, cipher: &Cipher) -> Result<Header, DatabaseError> {
+    let mut sealed = vec![0; reader.read_u16::<BE>()? as usize];
+    reader.read_exact(&mut sealed)?;
+    let plain = cipher
+        .open(&sealed, HEADER_AAD)
+        .map_err(|_| DatabaseError::IncorrectPassword)?;
+    Header::read(&mut Cursor::new(plain))
+}
+
+/// Copies the key block and sealed header of an encrypted file, sealing the header
+/// again with the current master key of the `cipher`.
+pub fn reseal_head(
+    reader: &mut dyn Read,
+    cipher: &Cipher,
+    writer: &mut dyn Write,
+) -> Result<(), DatabaseError> {
+    let mut block = vec![0; KEY_BLOCK_LEN];
+    reader.read_exact(&mut block)?;
+    writer.write_all(&block)?;
+    let header = read_header(reader, cipher)?;
+    write_header(&header, cipher, writer)
+}
+
+/// Skips the key block and sealed header of an encrypted file, without opening them.
+/// Returns the amount of bytes skipped.
+pub fn skip_head(reader: &mut dyn Read) -> Result<usize, DatabaseError> {
+    let mut block = vec![0; KEY_BLOCK_LEN];
+    reader.read_exact(&mut block)?;
+    let mut sealed = vec![0; reader.read_u16::<BE>()? as usize];
+    reader.read_exact(&mut sealed)?;
+    Ok(KEY_BLOCK_LEN + 2 + sealed.len())
+}
+
+/// Seals the value of a key as it is stored by its encoding, after the length of the
+/// decoded value.
+pub fn seal_value(
+    cipher: &Cipher,
+    key_name: &str,
+    encoding: Encoding,
+    size: u64,
+    stored: &[u8],
+) -> Result<Vec<u8>, DatabaseError> {
+    let mut sealed = Vec::with_capacity(8 + SEAL_OVERHEAD + stored.len());
+    sealed.write_u64::<BE>(size)?;
+    sealed.extend_from_slice(&cipher.seal(stored, &value_aad(key_name, encoding, size))?);
+    Ok(sealed)
+}
+
+/// Opens the sealed value of a key, returning it as it is stored by its encoding.
+/// Fails if the value was sealed for another key.
+pub fn open_value(
+    cipher: &Cipher,
+    key_name: &str,
+    encoding: Encoding,
+    sealed: &[u8],
+) -> Result<Vec<u8>, DatabaseError> {
+    let size = Cursor::new(sealed).read_u64::<BE>()?;
+    cipher.open(&sealed[8..], &value_aad(key_name, encoding, size))
+}
+
+/// Seals the name of a key, with its token as the nonce.
+/// The nonce only repeats for the same name, so the name always seals the same way.
+pub fn seal_name(cipher: &Cipher, name: &str) -> Result<Vec<u8>, DatabaseError> {
+    cipher.seal_with(&cipher.token(name), name.as_bytes(), NAME_AAD)
+}
+
+/// Opens the name of a key, sealed by `seal_name`.
+pub fn open_name(cipher: &Cipher, sealed: &[u8]) -> Result<String, DatabaseError> {
+    String::from_utf8(cipher.open(sealed, NAME_AAD)?).map_err(|_| corrupted())
+}
+
+/// The token of a name sealed by `seal_name`, without opening it.
+pub fn sealed_token(sealed: &[u8]) -> &[u8] {
+    &sealed[..TOKEN_LEN.min(sealed.len())]
+}
+
+/// Seals a whole record, prefixed by the length of the sealed record.
+pub fn seal_record(cipher: &Cipher, record: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+    let sealed = cipher.seal(record, RECORD_AAD)?;
+    let mut data = Vec::with_capacity(RECORD_PREFIX_LEN + sealed.len());
+    data.write_u64::<BE>(sealed.len() as u64)?;
+    data.extend_from_slice(&sealed);
+    Ok(data)
+}
+
+/// Opens a record sealed by `seal_record`, without its length.
+pub fn open_record(cipher: &Cipher, sealed: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+    cipher.open(sealed, RECORD_AAD)
+}
+
+/// Values are bound to their encoding, length and key, so none of them can be changed on
+/// disk, and a value can not be copied onto another key.
+fn value_aad(key_name: &str, encoding: Encoding, size: u64) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(9 + key_name.len());
+    aad.push(encoding as u8);
+    aad.extend_from_slice(&size.to_be_bytes());
+    aad.extend_from_slice(key_name.as_bytes());
+    aad
+}
+
+fn corrupted() -> DatabaseError {
+    DatabaseError::Implementation("Encrypted data is corrupted".to_string())
+}
@@ -0,0 +1,178 @@
+use crate::{
+    blob,
+    db::Database,
+    namespace,
+    snapshot::{Snapshot, SnapshotPartitionReader},
+    utils::timestamp,
+    virtual_db::{latest_keys, Record},
+    DatabaseError,
+};
+use byteorder::{ReadBytesExt, BE};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, Cursor, Write};
+
+/// The most keys an import writes at once.
+pub const IMPORT_CHUNK_KEYS: usize = 1024;
+
+/// The most bytes of values an import writes at once, unless a single value is larger.
+pub const IMPORT_CHUNK_BYTES: usize = 16 * 1024 * 1024;
+
+/// A single line of a JSON-lines export.
+/// Values are base64 encoded, so any value can be exported.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExportLine {
+    /// The namespace the key is in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// The name of the key.
+    pub key: String,
+    /// Whether or not the key was set with a deduplicated value.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deduplicated: bool,
+    /// The base64 encoded value of the key.
+    pub value: String,
+}
+
+/// Writes every key that is set in the snapshot as a line of JSON.
+/// Keys within namespaces are written with the name of their namespace.
+/// Returns the amount of keys written.
+pub fn export_snapshot(snapshot: &Snapshot, writer: &mut dyn Write) -> Result<u64, DatabaseError> {
+    // every partition is read once, and kept open for the values of deduplicated keys.
+    let mut partitions: HashMap<u8, SnapshotPartitionReader> = HashMap::new();
+    for id in snapshot.partitions() {
+        partitions.insert(id, snapshot.open_partition(id)?);
+    }
+    let ids = snapshot.partitions();
+
+    // the namespace names, by their ids, and the partitions of the blobs.
+    let mut namespaces: HashMap<u64, String> = HashMap::new();
+    let mut blobs: HashMap<String, (u8, Record)> = HashMap::new();
+    for id in ids.iter() {
+        let partition = partitions.get_mut(id).ok_or_else(missing)?;
+        for key in latest_keys(partition.records.iter().cloned()) {
+            if let Some(name) = namespace::catalog_name(&key.name) {
+                let value = partition.value(&Record::from_key(&key))?;
+                namespaces.insert(Cursor::new(value).read_u64::<BE>()?, name.to_string());
+            } else if key.name.starts_with(blob::BLOB_PREFIX) {
+                blobs.insert(key.name.clone(), (*id, Record::from_key(&key)));
+            }
+        }
+    }
+
+    let mut exported = 0;
+    for id in ids.iter() {
+        let keys = latest_keys(
+            partitions
+                .get(id)
+                .ok_or_else(missing)?
+                .records
+                .iter()
+                .cloned(),
+        );
+        for key in keys {
+            let link = blob::link_name(&key.name);
+            let (namespace, name) = match (namespace::split_key(&key.name), link) {
+                // keys of dropped namespaces are not exported.
+                (Some((id, name)), _) => match namespaces.get(&id) {
+                    Some(namespace) => (Some(namespace.clone()), name.to_string()),
+                    None => continue,
+                },
+                (None, Some(name)) => (None, name.to_string()),
+                (None, None) if namespace::is_reserved(&key.name) => continue,
+                (None, None) => (None, key.name.clone()),
+            };
+            let partition = partitions.get_mut(id).ok_or_else(missing)?;
+            let mut value = partition.value(&Record::from_key(&key))?;
+            let deduplicated = link.is_some();
+            if deduplicated {
+                let (id, record) = blobs
+                    .get(&blob::blob_key(&blob::read_hash(&value)?))
+                    .ok_or_else(|| DatabaseError::KeyNotFound(name.clone()))?;
+                value = partitions.get_mut(id).ok_or_else(missing)?.value(record)?;
+            }
+            let line = ExportLine {
+                namespace,
+                key: name,
+                deduplicated,
+                value: base64::encode(value),
+            };
+            serde_json::to_writer(&mut *writer, &line).map_err(json_error)?;
+            writer.write_all(b"\n")?;
+            exported += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(exported)
+}
+
+/// Bulk loads every line of the export into the database.
+/// The compression and encryption of the database are kept as they are.
+/// Keys are written in chunks of up to `IMPORT_CHUNK_KEYS` keys, or `IMPORT_CHUNK_BYTES`
+/// bytes of values, with a single write for every partition.
+pub fn import_lines(reader: &mut dyn BufRead, db: &mut Database) -> Result<(), DatabaseError> {
+    let mut namespaces: HashMap<String, u64> = HashMap::new();
+    let mut chunk: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut chunk_bytes = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line: ExportLine = serde_json::from_str(&line).map_err(json_error)?;
+        let value = base64::decode(&line.value)
+            .map_err(|error| DatabaseError::ImportInvalid(format!("{}: {}", line.key, error)))?;
+        let name = match line.namespace {
+            Some(namespace) => {
+                let id = match namespaces.get(&namespace) {
+                    Some(id) => *id,
+                    None => {
+                        let id = db.create_namespace(namespace.clone())?;
+                        namespaces.insert(namespace, id);
+                        id
+                    }
+                };
+                namespace::namespaced_key(id, &line.key)
+            }
+            None if line.deduplicated => {
+                db.set_blob(line.key, value)?;
+                continue;
+            }
+            None if namespace::is_reserved(&line.key) => {
+                return Err(DatabaseError::InvalidKey(line.key));
+            }
+            None => line.key,
+        };
+        chunk_bytes += value.len();
+        chunk.push((name, value));
+        if chunk.len() >= IMPORT_CHUNK_KEYS || chunk_bytes >= IMPORT_CHUNK_BYTES {
+            write_chunk(db, std::mem::take(&mut chunk))?;
+            chunk_bytes = 0;
+        }
+    }
+    write_chunk(db, chunk)
+}
+
+/// Writes a chunk of an import, failing with the error of the first key that failed.
+fn write_chunk(db: &mut Database, chunk: Vec<(String, Vec<u8>)>) -> Result<(), DatabaseError> {
+    if chunk.is_empty() {
+        return Ok(());
+    }
+    for result in db.writable()?.set_many_raw(chunk)? {
+        result?;
+    }
+    db.header.last_write = timestamp();
+    Ok(())
+}
+
+fn missing() -> DatabaseError {
+    DatabaseError::Implementation("Partition is missing".to_string())
+}
+
+fn json_error(error: serde_json::Error) -> DatabaseError {
+    if error.is_io() {
+        DatabaseError::IoError(error.into())
+    } else {
+        DatabaseError::ImportInvalid(error.to_string())
+    }
+}
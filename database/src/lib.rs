@@ -1,6 +1,25 @@
+#[cfg(feature = "async")]
+pub mod async_db;
+pub mod backup;
+pub mod batch;
+pub mod blob;
+pub mod bloom;
+pub mod cache;
+pub mod changes;
+pub mod command;
+pub mod compression;
 pub mod db;
+pub mod dictionary;
+pub mod encryption;
+pub mod export;
+pub mod namespace;
 pub mod preamble;
+pub mod replication;
+pub mod scrub;
 pub mod single_db;
+pub mod snapshot;
+pub mod stats;
+pub mod tiering;
 pub mod utils;
 pub mod virtual_db;
 
@@ -27,10 +46,13 @@ pub enum DatabaseError {
     PreambleInvalid(&'static str),
 
     /// The preamble contains an invalid compression mode.
-    /// Currently only `0` and `1` are supported.
-    /// Where `0` is `CompressionMode::None` and `1` is `CompressionMode::Zstd`
+    /// Currently `0` to `4` are supported, see `CompressionMode`.
     PreambleCompressionInvalid,
 
+    /// The preamble contains an invalid encryption level.
+    /// Currently `0` to `3` are supported, see `EncryptionLevel`.
+    PreambleEncryptionInvalid,
+
     /// The database is not a valid One-Link Database.
     /// This happens when the magic bytes are incorrect.
     InvalidDatabase,
@@ -50,6 +72,41 @@ pub enum DatabaseError {
     /// The following is related to the database read/write operations.
     /// When the database key is not found. The encapsulated key is the key that was not found.
     KeyNotFound(String),
+
+    /// The key already exists, and the operation only creates new keys.
+    /// The encapsulated key is the key that already exists.
+    KeyAlreadyExists(String),
+
+    /// The key can not be stored in the database. (For example, the name is too long)
+    /// The encapsulated key is the key that was rejected.
+    InvalidKey(String),
+
+    /// The backup archive is invalid, or the database restored from it does not match it.
+    /// This error is generic, and encapsulates the specific error.
+    BackupInvalid(&'static str),
+
+    /// A line of an import could not be read.
+    /// The encapsulated string describes the line and the issue.
+    ImportInvalid(String),
+
+    /// The namespace does not exist.
+    /// The encapsulated string is the name of the namespace.
+    NamespaceNotFound(String),
+
+    /// The key is not at the version the conditional operation expected.
+    /// The encapsulated key is the key, with its current version (`0` when it is not set).
+    VersionConflict(String, u64),
+
+    /// The database was opened with `Database::open_read_only`, and the operation writes.
+    ReadOnly,
+
+    /// The database is already opened by an opener that does not allow this open.
+    /// A database opened for writing can not be shared, read-only openers can share it.
+    Locked,
+
+    /// The primary compacted records the replica did not pull yet, so the replica can no
+    /// longer follow it. The replica has to be created again.
+    ResyncRequired,
 }
 
 impl From<std::io::Error> for DatabaseError {
@@ -0,0 +1,58 @@
+//! Named namespaces (buckets) within a virtual database.
+//!
+//! A namespace is a separate key space inside the same partitions. Every namespace is
+//! given an id when it is created (the sequence number of its creation), and its keys
+//! are stored under a name prefixed by that id:
+//!
+//! | Name                 | Stored as                |
+//! | -------------------- | ------------------------ |
+//! | The namespace `name` | `\0ns\0{name}` -> `id`   |
+//! | A key within it      | `\0{id}\0{key}`          |
+//!
+//! Dropping a namespace only removes its catalog entry, so it takes a single record.
+//! Its keys are no longer reachable and are reclaimed by the next compaction.
+//! Key names starting with `\0` are reserved, and rejected by the flat key space.
+
+/// Key names starting with this character are reserved for the database.
+pub const RESERVED_PREFIX: char = '\0';
+
+/// The prefix of the catalog entries of namespaces.
+const CATALOG_PREFIX: &str = "\0ns\0";
+
+/// Whether or not the key name is reserved for the database.
+pub fn is_reserved(key_name: &str) -> bool {
+    key_name.starts_with(RESERVED_PREFIX)
+}
+
+/// Whether or not the name can be used for a namespace.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(RESERVED_PREFIX)
+}
+
+/// The name of the catalog entry of a namespace.
+pub fn catalog_key(name: &str) -> String {
+    format!("{}{}", CATALOG_PREFIX, name)
+}
+
+/// The namespace the catalog entry belongs to, if the key is a catalog entry.
+pub fn catalog_name(key_name: &str) -> Option<&str> {
+    key_name.strip_prefix(CATALOG_PREFIX)
+}
+
+/// The prefix of every key within the namespace.
+pub fn key_prefix(id: u64) -> String {
+    format!("{}{}{}", RESERVED_PREFIX, id, RESERVED_PREFIX)
+}
+
+/// The name a key within the namespace is stored under.
+pub fn namespaced_key(id: u64, key_name: &str) -> String {
+    format!("{}{}", key_prefix(id), key_name)
+}
+
+/// Splits a stored key name into its namespace id and key name.
+/// Returns `None` for keys that are not within a namespace.
+pub fn split_key(key_name: &str) -> Option<(u64, &str)> {
+    let rest = key_name.strip_prefix(RESERVED_PREFIX)?;
+    let (id, key) = rest.split_once(RESERVED_PREFIX)?;
+    Some((id.parse().ok()?, key))
+}
@@ -1,3 +1,4 @@
+use crate::compression::{self, Compression};
 use crate::utils::GetByteLength;
 use crate::DatabaseError;
 use crate::MAGIC_BYTES;
@@ -9,6 +10,51 @@ use std::io::{Cursor, Write};
 pub enum CompressionMode {
     None,
     Zstd,
+    /// Fast compression, for data that is read often and needs to be read quickly.
+    Lz4,
+    /// Slow, high ratio compression, for archival partitions.
+    Brotli,
+    /// Slow, high ratio compression, for archival partitions.
+    Xz,
+}
+
+impl CompressionMode {
+    pub fn from_u8(mode: u8) -> Result<Self, DatabaseError> {
+        match mode {
+            0 => Ok(CompressionMode::None),
+            1 => Ok(CompressionMode::Zstd),
+            2 => Ok(CompressionMode::Lz4),
+            3 => Ok(CompressionMode::Brotli),
+            4 => Ok(CompressionMode::Xz),
+            _ => Err(DatabaseError::PreambleCompressionInvalid),
+        }
+    }
+}
+
+/// How much of a database is encrypted, see `crate::encryption`.
+/// Every level encrypts everything the levels before it do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum EncryptionLevel {
+    None,
+    /// Values are sealed. Key names and the lengths of values stay readable.
+    Values,
+    /// Values and key names are sealed. The lengths of values stay readable.
+    Names,
+    /// Every record is sealed as a whole, so only the length of each sealed record is readable.
+    Full,
+}
+
+impl EncryptionLevel {
+    pub fn from_u8(level: u8) -> Result<Self, DatabaseError> {
+        match level {
+            0 => Ok(EncryptionLevel::None),
+            1 => Ok(EncryptionLevel::Values),
+            2 => Ok(EncryptionLevel::Names),
+            3 => Ok(EncryptionLevel::Full),
+            _ => Err(DatabaseError::PreambleEncryptionInvalid),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -18,24 +64,39 @@ pub struct Preamble {
     pub version: u16,
     /// The compression algorithm used to compress the database.
     pub compression: CompressionMode,
-    /// Whether or not the One-Link Database is encrypted.
-    /// This is a `u8` to allow extended support for reading encrypted databases.
-    /// However currently, this is treated as a boolean.
-    pub encryption: u8,
+    /// How much of the One-Link Database is encrypted, see `crate::encryption`.
+    pub encryption: EncryptionLevel,
+    /// The zstd level new values are compressed at, see `crate::compression::Compression`.
+    pub level: i32,
     // /// The checksum of the database.
     // checksum: u32,
 }
 
 impl Preamble {
+    /// The offset of the compression mode, from the start of the preamble.
+    pub const COMPRESSION_OFFSET: u64 = MAGIC_BYTES.len() as u64 + 2;
+    /// The offset of the zstd compression level, from the start of the preamble.
+    pub const LEVEL_OFFSET: u64 = MAGIC_BYTES.len() as u64 + 4;
+
     /// Checks whether the database is encrypted.
-    pub fn is_encrypted(self) -> bool {
-        self.encryption != 0
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption != EncryptionLevel::None
+    }
+
+    /// Checks whether the key names of the database are encrypted.
+    pub fn encrypts_names(&self) -> bool {
+        self.encryption >= EncryptionLevel::Names
+    }
+
+    /// Checks whether the lengths of the values of the database are encrypted.
+    pub fn encrypts_lengths(&self) -> bool {
+        self.encryption >= EncryptionLevel::Full
     }
 
     /// Validates that the database is a valid One-Link Database.
     /// This is not reliable for data validation, and is used for header validation.
     pub fn validate_magic(data: &[u8]) -> bool {
-        data[0..16] == MAGIC_BYTES
+        data.len() >= MAGIC_BYTES.len() && data[0..MAGIC_BYTES.len()] == MAGIC_BYTES
     }
 
     /// Validates whether the current version of the library supports
@@ -44,20 +105,18 @@ impl Preamble {
     /// where the hundreds represents the major and and the ten's represent
     /// the minor version.
     pub fn validate_version(version: u16) -> bool {
-        match version {
-            100 => true,
-            _ => false,
-        }
+        matches!(version, 100)
     }
 
     /// Creates a new preamble with the default values.
-    /// This assumes that you will encrypt the database.
+    /// This assumes that you will encrypt the database, with `Database::create_with_password`.
     /// If you do not encrypt the database, you should use `Preamble::new_unsafe`.
     pub fn new() -> Preamble {
         Preamble {
             version: 100,
             compression: CompressionMode::Zstd,
-            encryption: 1,
+            encryption: EncryptionLevel::Values,
+            level: compression::DEFAULT_LEVEL,
         }
     }
 
@@ -68,7 +127,8 @@ impl Preamble {
         Preamble {
             version: 100,
             compression: CompressionMode::Zstd,
-            encryption: 0,
+            encryption: EncryptionLevel::None,
+            level: compression::DEFAULT_LEVEL,
         }
     }
 
@@ -76,33 +136,36 @@ impl Preamble {
     /// This is used when reading a database or to validate a database
     /// before writing it.
     pub fn create(data: &[u8]) -> Result<Self, DatabaseError> {
-        if data.len() != MAGIC_BYTES.len() + 4 {
+        if data.len() != MAGIC_BYTES.len() + 8 {
             return Err(DatabaseError::PreambleInvalid("Preamble is too short"));
         }
 
-        if !Self::validate_magic(&data[0..16]) {
+        if !Self::validate_magic(data) {
             return Err(DatabaseError::InvalidDatabase);
         }
 
         let mut cursor = Cursor::new(data);
-        cursor.set_position(16);
+        cursor.set_position(MAGIC_BYTES.len() as u64);
 
         let version = cursor.read_u16::<BE>()?;
-        let compression = match cursor.read_u8()? {
-            0 => CompressionMode::None,
-            1 => CompressionMode::Zstd,
-            _ => return Err(DatabaseError::PreambleCompressionInvalid),
-        };
-        let encryption = cursor.read_u8()?;
+        let compression = CompressionMode::from_u8(cursor.read_u8()?)?;
+        let encryption = EncryptionLevel::from_u8(cursor.read_u8()?)?;
+        let level = cursor.read_i32::<BE>()?;
+        if Compression::validate_level(level).is_err() {
+            return Err(DatabaseError::PreambleInvalid(
+                "Compression level is not supported",
+            ));
+        }
 
         if !Self::validate_version(version) {
-            return Err(DatabaseError::InvalidVersion(version));
+            Err(DatabaseError::InvalidVersion(version))
         } else {
-            return Ok(Self {
+            Ok(Self {
                 version,
                 compression,
                 encryption,
-            });
+                level,
+            })
         }
     }
 
@@ -111,17 +174,25 @@ impl Preamble {
         writer.write_all(&MAGIC_BYTES)?;
         writer.write_u16::<BE>(self.version)?;
         writer.write_u8(self.compression as u8)?;
-        writer.write_u8(self.encryption)?;
+        writer.write_u8(self.encryption as u8)?;
+        writer.write_i32::<BE>(self.level)?;
         Ok(())
     }
 }
 
+impl Default for Preamble {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GetByteLength for Preamble {
     fn byte_len(&self) -> usize {
-        // Magic is 16 bytes
+        // Magic is 13 bytes
         // Version is 2 bytes
         // Compression is 1 byte
         // Encryption is 1 byte
-        return MAGIC_BYTES.len() + 4;
+        // Compression level is 4 bytes
+        MAGIC_BYTES.len() + 8
     }
 }
@@ -0,0 +1,525 @@
+//! Log shipping replication.
+//!
+//! A replica pulls the records written after its latest sequence number from a primary,
+//! and appends them in order with the same sequence numbers and versions. The replica
+//! only serves reads, and reports how far it is behind the primary.
+//!
+//! Frames are exchanged over a [`Transport`], which can be anything that carries bytes:
+//!
+//! | Frame   | Layout                                                                             |
+//! | ------- | ---------------------------------------------------------------------------------- |
+//! | Pull    | `0`, after `u64`, base `u64`                                                       |
+//! | Records | `1`, primary sequence `u64`, sent on `u128`, compacted `u64`, count `u32`, records |
+//! | Resync  | `2`, compacted `u64`                                                               |
+//!
+//! Records are encoded as they are stored in a partition. While a `Primary` is serving,
+//! it keeps every record after the latest sequence it shipped from compaction, so a replica
+//! that pulls again never misses a removal.
+//!
+//! Compaction may still drop records a replica has not pulled, when the primary was
+//! restarted or nothing was serving the replica. So the primary records the highest
+//! sequence number compaction ever dropped next to the database file (`{path}.compacted`),
+//! and answers a pull from before it with a resync frame, on which `Replica::sync` fails
+//! with `DatabaseError::ResyncRequired`. Such a replica has to be created again.
+//!
+//! A replica that starts out empty is not behind the records that were dropped before
+//! its first pull, as it only receives what compaction kept. It sends the compacted
+//! sequence of its first answer as its base, so it is not told to resync while it catches
+//! up with them (unless the replica was reopened in the meantime).
+use crate::{
+    compression::Encoding,
+    db::Database,
+    preamble::Preamble,
+    snapshot::SnapshotPin,
+    utils::{timestamp, InternalApi},
+    virtual_db::{encode_record, RecordKind, VirtualItem, VirtualKey},
+    DatabaseError,
+};
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Cursor, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+};
+
+/// The most records sent in a single frame.
+pub const MAX_FRAME_RECORDS: usize = 1024;
+
+/// The longest frame a `StreamTransport` sends or receives, in bytes.
+/// A frame of records stays under it, unless it holds a single record that is longer.
+pub const MAX_FRAME_LEN: usize = 1024 * 1024 * 1024;
+
+/// The length of a records frame before its records.
+const RECORDS_HEAD_LEN: usize = 1 + 8 + 16 + 8 + 4;
+
+const PULL: u8 = 0;
+const RECORDS: u8 = 1;
+const RESYNC: u8 = 2;
+
+/// The path of the file that records how far compaction dropped records.
+fn compacted_path(db_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.compacted", db_path.display()))
+}
+
+/// Reads the highest sequence number compaction dropped a record at, see the module docs.
+/// A database that was never compacted has dropped nothing.
+pub(crate) fn load_compacted(db_path: &Path) -> Result<u64, DatabaseError> {
+    match fs::read(compacted_path(db_path)) {
+        Ok(data) => Ok(Cursor::new(data).read_u64::<BE>()?),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(0),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Records the highest sequence number compaction dropped a record at,
+/// replacing the previous one at once.
+pub(crate) fn save_compacted(db_path: &Path, sequence: u64) -> Result<(), DatabaseError> {
+    let path = compacted_path(db_path);
+    let temporary = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&temporary, sequence.to_be_bytes())?;
+    File::open(&temporary)?.sync_all()?;
+    fs::rename(&temporary, &path)?;
+    Ok(())
+}
+
+/// Carries frames between a primary and a replica.
+pub trait Transport {
+    /// Sends a frame to the other side.
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), DatabaseError>;
+
+    /// Waits for the next frame from the other side.
+    /// Returns `None` once the other side has closed the transport.
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, DatabaseError>;
+}
+
+/// A transport over a byte stream, such as a pipe or a loopback socket.
+/// Frames are prefixed with their length as a `u32`, and are at most `MAX_FRAME_LEN` long.
+pub struct StreamTransport<S: Read + Write> {
+    stream: S,
+}
+
+impl<S: Read + Write> StreamTransport<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+}
+
+impl<S: Read + Write> Transport for StreamTransport<S> {
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), DatabaseError> {
+        check_frame_len(frame.len() as u64)?;
+        self.stream.write_u32::<BE>(frame.len() as u32)?;
+        self.stream.write_all(&frame)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let length = match self.stream.read_u32::<BE>() {
+            Ok(length) => length,
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        check_frame_len(length as u64)?;
+        // the frame grows as it is received, so a length that was never sent is not allocated.
+        let mut frame = Vec::new();
+        (&mut self.stream)
+            .take(length as u64)
+            .read_to_end(&mut frame)?;
+        if frame.len() != length as usize {
+            return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+        }
+        Ok(Some(frame))
+    }
+}
+
+/// Fails if a frame is longer than `MAX_FRAME_LEN`.
+fn check_frame_len(length: u64) -> Result<(), DatabaseError> {
+    match length <= MAX_FRAME_LEN as u64 {
+        true => Ok(()),
+        false => Err(DatabaseError::Implementation(format!(
+            "Frame of {} bytes is longer than {} bytes",
+            length, MAX_FRAME_LEN
+        ))),
+    }
+}
+
+/// A transport between two threads of the same process.
+pub struct ChannelTransport {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl ChannelTransport {
+    /// Creates both ends of the transport.
+    pub fn pair() -> (Self, Self) {
+        let (left_sender, right_receiver) = channel();
+        let (right_sender, left_receiver) = channel();
+        (
+            Self {
+                sender: left_sender,
+                receiver: left_receiver,
+            },
+            Self {
+                sender: right_sender,
+                receiver: right_receiver,
+            },
+        )
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), DatabaseError> {
+        self.sender
+            .send(frame)
+            .map_err(|_| std::io::Error::from(ErrorKind::BrokenPipe).into())
+    }
+
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, DatabaseError> {
+        Ok(self.receiver.recv().ok())
+    }
+}
+
+/// A record shipped from the primary.
+struct ShippedRecord {
+    kind: RecordKind,
+    sequence: u64,
+    version: u64,
+    /// The name the key is stored under, including reserved names.
+    name: String,
+    /// The value as it is stored by the primary.
+    encoding: Encoding,
+    stored: Vec<u8>,
+}
+
+impl ShippedRecord {
+    fn read(reader: &mut Cursor<Vec<u8>>) -> Result<Self, DatabaseError> {
+        let kind = RecordKind::from_u8(reader.read_u8()?)?;
+        let sequence = reader.read_u64::<BE>()?;
+        let version = reader.read_u64::<BE>()?;
+        let mut name = vec![0; reader.read_u16::<BE>()? as usize];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name)
+            .map_err(|_| DatabaseError::Implementation("Key is not valid UTF-8".to_string()))?;
+        let encoding = Encoding::from_u8(reader.read_u8()?)?;
+        let length = reader.read_u128::<BE>()?;
+        let remaining = reader.get_ref().len() as u64 - reader.position();
+        if length > remaining as u128 {
+            return Err(DatabaseError::Implementation(format!(
+                "Shipped record of {} bytes is longer than the {} bytes left in the frame",
+                length, remaining
+            )));
+        }
+        let mut stored = vec![0; length as usize];
+        reader.read_exact(&mut stored)?;
+        Ok(Self {
+            kind,
+            sequence,
+            version,
+            name,
+            encoding,
+            stored,
+        })
+    }
+}
+
+/// How far a replica is behind its primary.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReplicationLag {
+    /// The amount of sequence numbers the primary was ahead when it last answered.
+    pub sequences: u64,
+    /// The milliseconds since the primary last answered.
+    pub millis: u128,
+}
+
+/// Answers the pulls of a replica.
+pub struct Primary<T: Transport> {
+    transport: T,
+    /// Keeps the records after the latest shipped sequence from being compacted.
+    /// It is not a snapshot, so it does not stop the master key from being rotated.
+    pin: Option<SnapshotPin>,
+}
+
+impl<T: Transport> Primary<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            pin: None,
+        }
+    }
+
+    /// Answers pulls until the replica closes the transport.
+    pub fn serve(&mut self, db: &Mutex<Database>) -> Result<(), DatabaseError> {
+        while self.serve_one(db)? {}
+        Ok(())
+    }
+
+    /// Waits for a single pull and answers it.
+    /// The database is only locked while the answer is read from it.
+    /// Returns `false` once the replica has closed the transport.
+    pub fn serve_one(&mut self, db: &Mutex<Database>) -> Result<bool, DatabaseError> {
+        let frame = match self.transport.receive()? {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+        let mut reader = Cursor::new(frame);
+        if reader.read_u8()? != PULL {
+            return Err(DatabaseError::Implementation(
+                "Expected a pull from the replica".to_string(),
+            ));
+        }
+        let after = reader.read_u64::<BE>()?;
+        let base = reader.read_u64::<BE>()?;
+
+        let frame = {
+            let mut db = db
+                .lock()
+                .map_err(|_| DatabaseError::Implementation("Database lock is poisoned".into()))?;
+            let compacted = db.compacted()?;
+            match after > 0 && after < compacted && base < compacted {
+                true => {
+                    self.pin = None;
+                    let mut frame = vec![RESYNC];
+                    frame.write_u64::<BE>(compacted)?;
+                    frame
+                }
+                false => {
+                    let (frame, shipped) = Self::records_after(&mut db, after, compacted)?;
+                    self.pin = Some(db.retain(shipped)?);
+                    frame
+                }
+            }
+        };
+        self.transport.send(frame)?;
+        Ok(true)
+    }
+
+    /// Encodes the records after `after` into a frame.
+    /// Returns the frame, and the latest sequence number within it.
+    fn records_after(
+        db: &mut Database,
+        after: u64,
+        compacted: u64,
+    ) -> Result<(Vec<u8>, u64), DatabaseError> {
+        let snapshot = db.snapshot()?;
+        let mut partitions = HashMap::new();
+        let mut records = Vec::new();
+        for id in snapshot.partitions() {
+            let mut partition = snapshot.open_partition(id)?;
+            for record in std::mem::take(&mut partition.records) {
+                if record.sequence > after {
+                    records.push((id, record));
+                }
+            }
+            partitions.insert(id, partition);
+        }
+        records.sort_by_key(|(_, record)| record.sequence);
+        records.truncate(MAX_FRAME_RECORDS);
+
+        let (mut body, mut count, mut shipped) = (Vec::new(), 0u32, after);
+        for (id, record) in records {
+            // values are shipped compressed, but not sealed, so a replica can store them
+            // with its own key (or none).
+            let (encoding, stored) = match partitions.get_mut(&id) {
+                Some(partition) => partition.encoded(&record)?,
+                None => {
+                    shipped = record.sequence;
+                    continue;
+                }
+            };
+            let encoded = encode_record(
+                record.kind,
+                record.sequence,
+                record.version,
+                record.name.as_bytes(),
+                encoding,
+                &stored,
+            )?;
+            // the remaining records are sent in the next frame.
+            if count > 0 && RECORDS_HEAD_LEN + body.len() + encoded.len() > MAX_FRAME_LEN {
+                break;
+            }
+            body.extend_from_slice(&encoded);
+            count += 1;
+            shipped = record.sequence;
+        }
+        let mut frame = Vec::with_capacity(RECORDS_HEAD_LEN + body.len());
+        frame.write_u8(RECORDS)?;
+        frame.write_u64::<BE>(snapshot.sequence)?;
+        frame.write_u128::<BE>(timestamp())?;
+        frame.write_u64::<BE>(compacted)?;
+        frame.write_u32::<BE>(count)?;
+        frame.write_all(&body)?;
+        Ok((frame, shipped))
+    }
+}
+
+/// A read-only copy of a primary database, kept up to date by pulling its records.
+pub struct Replica<T: Transport> {
+    db: Database,
+    transport: T,
+    /// The sequence number of the primary when it last answered.
+    primary_sequence: u64,
+    /// When the primary last answered.
+    synced_on: u128,
+    /// The compacted sequence of the primary when the replica was still empty,
+    /// see the module documentation.
+    base: u64,
+}
+
+impl<T: Transport> Replica<T> {
+    /// Creates a new, empty replica.
+    /// The partitions of the replica do not have to match the primary.
+    pub fn create(
+        name: String,
+        path: String,
+        preamble: Preamble,
+        partitions: u8,
+        transport: T,
+    ) -> Result<Self, DatabaseError> {
+        Database::create(name.clone(), path.clone(), preamble, partitions)?.close()?;
+        Self::open(name, path, transport)
+    }
+
+    /// Opens an existing replica, which continues from its latest sequence number.
+    /// The database of the replica is read only, only records of the primary are written.
+    pub fn open(name: String, path: String, transport: T) -> Result<Self, DatabaseError> {
+        let mut db = Database::open_replica(name, path)?;
+        let primary_sequence = db.sequence()?;
+        Ok(Self {
+            db,
+            transport,
+            primary_sequence,
+            synced_on: 0,
+            base: 0,
+        })
+    }
+
+    /// Pulls a single frame of records from the primary and applies them in order.
+    /// Returns the amount of records applied.
+    /// Fails with `DatabaseError::ResyncRequired` if the primary compacted records the
+    /// replica did not pull yet.
+    pub fn sync(&mut self) -> Result<usize, DatabaseError> {
+        let sequence = self.db.sequence()?;
+        let mut pull = vec![PULL];
+        pull.write_u64::<BE>(sequence)?;
+        pull.write_u64::<BE>(self.base)?;
+        self.transport.send(pull)?;
+
+        let frame = self.transport.receive()?.ok_or_else(|| {
+            DatabaseError::IoError(std::io::Error::from(ErrorKind::ConnectionAborted))
+        })?;
+        let mut reader = Cursor::new(frame);
+        match reader.read_u8()? {
+            RECORDS => {}
+            RESYNC => return Err(DatabaseError::ResyncRequired),
+            _ => {
+                return Err(DatabaseError::Implementation(
+                    "Expected records from the primary".to_string(),
+                ))
+            }
+        }
+        let primary_sequence = reader.read_u64::<BE>()?;
+        reader.read_u128::<BE>()?;
+        let compacted = reader.read_u64::<BE>()?;
+        if sequence == 0 {
+            self.base = compacted;
+        }
+        let count = reader.read_u32::<BE>()?;
+        for _ in 0..count {
+            let record = ShippedRecord::read(&mut reader)?;
+            self.db.apply_record(
+                record.kind,
+                record.sequence,
+                record.version,
+                record.name,
+                record.encoding,
+                record.stored,
+            )?;
+        }
+
+        self.primary_sequence = primary_sequence;
+        self.synced_on = timestamp();
+        Ok(count as usize)
+    }
+
+    /// Pulls from the primary until the replica has every record the primary had.
+    /// Returns the amount of records applied.
+    pub fn catch_up(&mut self) -> Result<usize, DatabaseError> {
+        let mut applied = self.sync()?;
+        while self.lag()?.sequences > 0 {
+            match self.sync()? {
+                0 => break,
+                count => applied += count,
+            }
+        }
+        Ok(applied)
+    }
+
+    /// How far the replica is behind the primary.
+    pub fn lag(&mut self) -> Result<ReplicationLag, DatabaseError> {
+        Ok(ReplicationLag {
+            sequences: self.primary_sequence.saturating_sub(self.db.sequence()?),
+            millis: match self.synced_on {
+                0 => 0,
+                synced_on => timestamp().saturating_sub(synced_on),
+            },
+        })
+    }
+
+    /// The sequence number of the latest record applied to the replica.
+    pub fn sequence(&mut self) -> Result<u64, DatabaseError> {
+        self.db.sequence()
+    }
+
+    /// Gets a key from the replica.
+    pub fn get(&mut self, key_name: String) -> Result<VirtualItem, DatabaseError> {
+        self.db.get(key_name)
+    }
+
+    /// Every key that is set on the replica.
+    pub fn fetch_keys(&mut self) -> Result<Vec<VirtualKey>, DatabaseError> {
+        self.db.fetch_keys()
+    }
+
+    /// Gets many keys at once, see `Database::get_many`.
+    pub fn get_many(
+        &mut self,
+        key_names: Vec<String>,
+    ) -> Result<Vec<Result<VirtualItem, DatabaseError>>, DatabaseError> {
+        self.db.get_many(key_names)
+    }
+
+    /// The names of every namespace, sorted.
+    pub fn namespaces(&mut self) -> Result<Vec<String>, DatabaseError> {
+        self.db.namespaces()
+    }
+
+    /// Gets a key within a namespace.
+    pub fn get_in(
+        &mut self,
+        namespace: &str,
+        key_name: String,
+    ) -> Result<VirtualItem, DatabaseError> {
+        self.db.get_in(namespace, key_name)
+    }
+
+    /// Every key within a namespace.
+    pub fn fetch_keys_in(&mut self, namespace: &str) -> Result<Vec<VirtualKey>, DatabaseError> {
+        self.db.fetch_keys_in(namespace)
+    }
+
+    /// Gets the value of a key that points at a blob.
+    pub fn get_blob(&mut self, key_name: String) -> Result<VirtualItem, DatabaseError> {
+        self.db.get_blob(key_name)
+    }
+
+    /// Closes the replica, it can be opened again with `Replica::open`.
+    pub fn close(self) -> Result<(), DatabaseError> {
+        self.db.close()
+    }
+}
@@ -0,0 +1,273 @@
+//! Secure deletion of removed values.
+//!
+//! Partitions are append only, so removing a key leaves its earlier values in the partition
+//! until it is compacted, and compaction only replaces the file, leaving the values in the
+//! blocks the filesystem freed. With secure delete enabled (see
+//! `VirtualDatabase::set_secure_delete`), removing a key overwrites every earlier record of
+//! the key in place instead:
+//!
+//! - the kind byte is set to `SCRUBBED_KIND`, so every reader skips the record,
+//! - the name, the encoding and the value are overwritten with zeroes,
+//! - the blocks of the value are punched out of the file, where the filesystem supports it.
+//!
+//! The lengths are kept, so the records after it are read as before. A record that is sealed
+//! as a whole (`EncryptionLevel::Full`) is sealed again with zeroes in its place instead,
+//! which has the same length. Records are written in place, so there is no write-ahead log
+//! that keeps another copy of them.
+//!
+//! Filesystems that copy blocks on write (or SSDs that remap them) may still keep the old
+//! blocks, which only encryption at rest protects against.
+use crate::{
+    compression::Encoding,
+    encryption,
+    preamble::EncryptionLevel,
+    virtual_db::{
+        encode_record, Partition, Record, RecordKind, VirtualDatabase, VirtualKey, SCRUBBED_KIND,
+    },
+    DatabaseError,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// The amount of zeroes written at once.
+const ZEROES_LEN: usize = 64 * 1024;
+
+/// A summary of what was overwritten by `VirtualDatabase::scrub_free_space`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScrubSummary {
+    /// The amount of records that were overwritten.
+    pub records: u64,
+    /// The amount of bytes that were overwritten.
+    pub bytes: u64,
+}
+
+/// An overwrite of part of a partition file, see `Partition::overwrites`.
+pub(crate) enum Overwrite {
+    /// Writes the bytes at the position.
+    Bytes(u64, Vec<u8>),
+    /// Writes zeroes over the given length from the position, punching out the blocks
+    /// if `punch` is set.
+    Zeroes {
+        position: u64,
+        length: u64,
+        punch: bool,
+    },
+}
+
+impl Partition {
+    /// Overwrites the records in place, see the module documentation.
+    /// Returns the amount of bytes that were overwritten.
+    pub(crate) fn scrub(&mut self, records: &[Record]) -> Result<u64, DatabaseError> {
+        if records.is_empty() {
+            return Ok(0);
+        }
+        let (overwrites, scrubbed) = self.overwrites(records)?;
+        overwrite(self.path(), &overwrites)?;
+        Ok(scrubbed)
+    }
+
+    /// Works out how the records are overwritten, without writing anything yet.
+    /// Returns the overwrites, with the amount of bytes they cover.
+    pub(crate) fn overwrites(
+        &self,
+        records: &[Record],
+    ) -> Result<(Vec<Overwrite>, u64), DatabaseError> {
+        let start = self.start() as u64;
+        let mut overwrites = Vec::new();
+        let mut scrubbed = 0;
+        for record in records {
+            let position = start + record.position;
+            match (self.encryption(), self.cipher()?) {
+                (EncryptionLevel::Full, Some(cipher)) => {
+                    let mut plain = encode_record(
+                        RecordKind::Remove,
+                        record.sequence,
+                        record.version,
+                        &vec![0; record.name.len()],
+                        Encoding::Raw,
+                        &vec![0; record.stored_length],
+                    )?;
+                    plain[0] = SCRUBBED_KIND;
+                    let sealed = encryption::seal_record(cipher, &plain)?;
+                    if sealed.len() != record.size {
+                        return Err(DatabaseError::Implementation(
+                            "Scrubbed record does not fit in place".to_string(),
+                        ));
+                    }
+                    overwrites.push(Overwrite::Bytes(position, sealed));
+                }
+                _ => {
+                    // the kind is written first, so a record that is only partly overwritten
+                    // is never read with a zeroed name.
+                    let name = position + Record::PREFIX_LEN as u64;
+                    let value = start + record.location.offset;
+                    let data = value + Record::VALUE_PREFIX_LEN as u64;
+                    let end = position + record.size as u64;
+                    overwrites.push(Overwrite::Bytes(position, vec![SCRUBBED_KIND]));
+                    overwrites.push(Overwrite::Zeroes {
+                        position: name,
+                        length: value + 1 - name,
+                        punch: false,
+                    });
+                    overwrites.push(Overwrite::Zeroes {
+                        position: data,
+                        length: end - data,
+                        punch: true,
+                    });
+                }
+            }
+            scrubbed += record.size as u64;
+        }
+        Ok((overwrites, scrubbed))
+    }
+
+    /// Overwrites whatever follows the last complete record, such as a record that was only
+    /// partially written. Returns the amount of bytes that were overwritten.
+    fn scrub_tail(&mut self) -> Result<u64, DatabaseError> {
+        let end = (self.start() + self.length) as u64;
+        let mut file = OpenOptions::new().write(true).open(self.path())?;
+        let tail = file.metadata()?.len().saturating_sub(end);
+        if tail > 0 {
+            file.seek(SeekFrom::Start(end))?;
+            write_zeroes(&mut file, tail)?;
+            punch_hole(&file, end, tail)?;
+            file.sync_all()?;
+        }
+        Ok(tail)
+    }
+}
+
+impl VirtualDatabase {
+    /// Sets whether or not removing a key overwrites its earlier values, see `crate::scrub`.
+    /// Compaction then also overwrites the records it drops before it replaces a partition,
+    /// and partitions moved to another tier are overwritten before they are removed.
+    pub fn set_secure_delete(&mut self, enabled: bool) {
+        self.secure_delete = enabled;
+    }
+
+    /// Whether or not removing a key overwrites its earlier values.
+    pub fn secure_delete(&self) -> bool {
+        self.secure_delete
+    }
+
+    /// Overwrites every record of a key before the record that removed it,
+    /// within the partition at `index`.
+    /// Snapshots taken before the key was removed no longer see it either.
+    pub(crate) fn scrub_removed(
+        &mut self,
+        index: usize,
+        removed: &VirtualKey,
+    ) -> Result<u64, DatabaseError> {
+        let partition = &mut self.parts[index];
+        let records: Vec<Record> = partition
+            .records_of(&removed.name)?
+            .into_iter()
+            .filter(|record| record.sequence < removed.sequence)
+            .collect();
+        partition.scrub(&records)
+    }
+
+    /// Overwrites every record that compaction would drop, in place, without rewriting the
+    /// partitions. Records that a snapshot may still read are kept, like they are by compaction.
+    pub fn scrub_free_space(&mut self) -> Result<ScrubSummary, DatabaseError> {
+        let horizon = self.snapshots.horizon();
+        let dropped = self.dropped_namespaces();
+        let mut reclaimable = Vec::new();
+        for partition in self.parts.iter_mut() {
+            reclaimable.push(partition.reclaimable(horizon, &dropped)?);
+        }
+        self.dropping(reclaimable.iter().flatten())?;
+        let mut summary = ScrubSummary::default();
+        for (partition, records) in self.parts.iter_mut().zip(reclaimable) {
+            summary.records += records.len() as u64;
+            summary.bytes += partition.scrub(&records)?;
+            summary.bytes += partition.scrub_tail()?;
+        }
+        Ok(summary)
+    }
+}
+
+/// Overwrites a whole file, before it is removed.
+pub(crate) fn scrub_file(path: &Path) -> Result<(), DatabaseError> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let length = file.metadata()?.len();
+    write_zeroes(&mut file, length)?;
+    punch_hole(&file, 0, length)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Writes the overwrites to the file at the path, in order, and syncs it.
+pub(crate) fn overwrite(path: &Path, overwrites: &[Overwrite]) -> Result<(), DatabaseError> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    for overwrite in overwrites {
+        match overwrite {
+            Overwrite::Bytes(position, bytes) => {
+                file.seek(SeekFrom::Start(*position))?;
+                file.write_all(bytes)?;
+            }
+            Overwrite::Zeroes {
+                position,
+                length,
+                punch,
+            } => {
+                file.seek(SeekFrom::Start(*position))?;
+                write_zeroes(&mut file, *length)?;
+                if *punch {
+                    punch_hole(&file, *position, *length)?;
+                }
+            }
+        }
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Writes `length` zeroes at the current position of the file.
+fn write_zeroes(file: &mut File, length: u64) -> Result<(), DatabaseError> {
+    let zeroes = [0; ZEROES_LEN];
+    let mut left = length;
+    while left > 0 {
+        let chunk = left.min(ZEROES_LEN as u64) as usize;
+        file.write_all(&zeroes[..chunk])?;
+        left -= chunk as u64;
+    }
+    Ok(())
+}
+
+/// Deallocates the blocks within a region of the file, which reads as zeroes afterwards.
+/// Filesystems that can not punch holes keep the zeroes that were written instead.
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: u64, length: u64) -> Result<(), DatabaseError> {
+    use std::os::unix::io::AsRawFd;
+
+    if length == 0 {
+        return Ok(());
+    }
+    // SAFETY: the file descriptor is owned by `file`, which outlives the call.
+    let result = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            length as libc::off_t,
+        )
+    };
+    if result != 0 {
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EOPNOTSUPP) {
+            return Err(error.into());
+        }
+    }
+    Ok(())
+}
+
+/// Deallocates the blocks within a region of the file.
+/// Only Linux can punch holes, elsewhere the zeroes that were written are kept.
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &File, _offset: u64, _length: u64) -> Result<(), DatabaseError> {
+    Ok(())
+}
@@ -0,0 +1,284 @@
+use crate::{
+    compression::Encoding,
+    dictionary::Dictionaries,
+    encryption::Cipher,
+    namespace,
+    preamble::EncryptionLevel,
+    virtual_db::{
+        latest_keys, read_encoded, read_raw, read_records, read_value, Record, VirtualItem,
+        VirtualKey,
+    },
+    DatabaseError,
+};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// Keeps track of the sequence numbers of the snapshots that are still alive.
+/// Compaction uses the oldest one to decide which versions it must keep.
+///
+/// Sequence numbers can also be retained without a snapshot, such as the latest sequence
+/// shipped to a replica. Those keep records from compaction as well, but nothing reads
+/// through them, so they do not count as snapshots that are alive.
+#[derive(Clone, Default)]
+pub struct SnapshotRegistry {
+    pinned: Arc<Mutex<Vec<u64>>>,
+    retained: Arc<Mutex<Vec<u64>>>,
+}
+
+impl SnapshotRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pins the given sequence number for a snapshot, until the returned pin is dropped.
+    pub fn pin(&self, sequence: u64) -> SnapshotPin {
+        self.pinned.lock().unwrap().push(sequence);
+        SnapshotPin {
+            registry: self.clone(),
+            sequence,
+            retained: false,
+        }
+    }
+
+    /// Keeps every record after the given sequence number from compaction, until the
+    /// returned pin is dropped. Unlike `pin`, this does not count as a snapshot.
+    pub fn retain(&self, sequence: u64) -> SnapshotPin {
+        self.retained.lock().unwrap().push(sequence);
+        SnapshotPin {
+            registry: self.clone(),
+            sequence,
+            retained: true,
+        }
+    }
+
+    /// The oldest sequence number that is pinned by a snapshot, or retained.
+    pub fn horizon(&self) -> Option<u64> {
+        let pinned = self.pinned.lock().unwrap().iter().min().copied();
+        let retained = self.retained.lock().unwrap().iter().min().copied();
+        pinned.into_iter().chain(retained).min()
+    }
+
+    /// The amount of snapshots that are still alive.
+    pub fn len(&self) -> usize {
+        self.pinned.lock().unwrap().len()
+    }
+
+    /// Whether or not there are no snapshots alive.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Holds a sequence number in the registry until it is dropped.
+pub struct SnapshotPin {
+    registry: SnapshotRegistry,
+    sequence: u64,
+    /// Whether the sequence number is retained, rather than pinned by a snapshot.
+    retained: bool,
+}
+
+impl Drop for SnapshotPin {
+    fn drop(&mut self) {
+        let list = match self.retained {
+            true => &self.registry.retained,
+            false => &self.registry.pinned,
+        };
+        let mut list = list.lock().unwrap();
+        if let Some(position) = list.iter().position(|s| *s == self.sequence) {
+            list.swap_remove(position);
+        }
+    }
+}
+
+/// A partition as seen by a snapshot.
+struct SnapshotPartition {
+    id: u8,
+    path: PathBuf,
+    start: u64,
+    encryption: EncryptionLevel,
+}
+
+/// A single open handle on a partition, with the records visible to the snapshot.
+/// Records and values are read through the same handle, so a compaction that
+/// replaces the partition file while it is being read does not move the values.
+pub struct SnapshotPartitionReader {
+    /// The id of the partition.
+    pub id: u8,
+    /// The records visible to the snapshot, in the order they were written.
+    pub records: Vec<Record>,
+    reader: BufReader<File>,
+    start: u64,
+    /// The length of the partition file when it was opened.
+    end: u64,
+    encryption: EncryptionLevel,
+    dictionaries: Dictionaries,
+    cipher: Option<Cipher>,
+}
+
+impl SnapshotPartitionReader {
+    /// Reads the value of one of the records.
+    pub fn value(&mut self, record: &Record) -> Result<Vec<u8>, DatabaseError> {
+        read_value(
+            &mut self.reader,
+            self.start + record.location.offset,
+            self.end,
+            &record.name,
+            &self.dictionaries,
+            self.encryption,
+            self.cipher.as_ref(),
+        )
+    }
+
+    /// Reads one of the records as it is written in the partition file.
+    /// The record of an encrypted partition stays sealed.
+    pub fn raw(&mut self, record: &Record) -> Result<Vec<u8>, DatabaseError> {
+        read_raw(&mut self.reader, self.start, record)
+    }
+
+    /// Reads the value of one of the records as it is encoded, without decompressing it.
+    /// Unlike `raw`, the value of an encrypted partition is opened.
+    pub fn encoded(&mut self, record: &Record) -> Result<(Encoding, Vec<u8>), DatabaseError> {
+        let position = self.start + record.location.offset;
+        self.reader.seek(SeekFrom::Start(position))?;
+        let (encoding, encoded, _) = read_encoded(
+            &mut self.reader,
+            self.end.saturating_sub(position),
+            &record.name,
+            self.encryption,
+            self.cipher.as_ref(),
+        )?;
+        Ok((encoding, encoded))
+    }
+
+    /// Reads the preamble and header of the partition file.
+    pub fn head(&mut self) -> Result<Vec<u8>, DatabaseError> {
+        let mut head = vec![0; self.start as usize];
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.reader.read_exact(&mut head)?;
+        Ok(head)
+    }
+}
+
+/// A read-only, point-in-time view of a virtual database.
+/// The snapshot only sees records with a sequence number up to the one it was taken at,
+/// so writers can keep using the database while the snapshot is read.
+/// Snapshots are independent of the database handle and can be sent to other threads.
+pub struct Snapshot {
+    /// The sequence number the snapshot was taken at.
+    pub sequence: u64,
+    parts: Vec<SnapshotPartition>,
+    dictionaries: Dictionaries,
+    cipher: Option<Cipher>,
+    _pin: SnapshotPin,
+}
+
+impl Snapshot {
+    pub fn new(
+        sequence: u64,
+        parts: Vec<(u8, PathBuf, u64, EncryptionLevel)>,
+        pin: SnapshotPin,
+        dictionaries: Dictionaries,
+        cipher: Option<Cipher>,
+    ) -> Self {
+        Self {
+            sequence,
+            parts: parts
+                .into_iter()
+                .map(|(id, path, start, encryption)| SnapshotPartition {
+                    id,
+                    path,
+                    start,
+                    encryption,
+                })
+                .collect(),
+            dictionaries,
+            cipher,
+            _pin: pin,
+        }
+    }
+
+    /// The ids of the partitions within the snapshot.
+    pub fn partitions(&self) -> Vec<u8> {
+        self.parts.iter().map(|part| part.id).collect()
+    }
+
+    /// Opens a partition, reading the records that are visible to this snapshot.
+    pub fn open_partition(&self, id: u8) -> Result<SnapshotPartitionReader, DatabaseError> {
+        let part = self
+            .parts
+            .iter()
+            .find(|part| part.id == id)
+            .ok_or_else(|| DatabaseError::Implementation(format!("Partition {} is missing", id)))?;
+        let mut file = File::open(&part.path)?;
+        let end = file.metadata()?.len();
+        let length = end.saturating_sub(part.start);
+        file.seek(SeekFrom::Start(part.start))?;
+        let mut reader = BufReader::new(file);
+        let (records, _) = read_records(
+            &mut reader,
+            part.id,
+            length,
+            part.encryption,
+            self.cipher.as_ref(),
+        )?;
+        Ok(SnapshotPartitionReader {
+            id,
+            records: records
+                .into_iter()
+                .filter(|record| record.sequence <= self.sequence)
+                .collect(),
+            reader,
+            start: part.start,
+            end,
+            encryption: part.encryption,
+            dictionaries: self.dictionaries.clone(),
+            cipher: self.cipher.clone(),
+        })
+    }
+
+    /// Gets a key as it was when the snapshot was taken.
+    pub fn get(&self, key_name: String) -> Result<VirtualItem, DatabaseError> {
+        if namespace::is_reserved(&key_name) {
+            return Err(DatabaseError::KeyNotFound(key_name));
+        }
+        for id in self.partitions() {
+            let mut partition = self.open_partition(id)?;
+            let found = latest_keys(
+                partition
+                    .records
+                    .iter()
+                    .filter(|record| record.name == key_name)
+                    .cloned(),
+            );
+            if let Some(key) = found.into_iter().next() {
+                let data = partition.value(&Record::from_key(&key))?;
+                return Ok(VirtualItem {
+                    key: key_name,
+                    location: key.location,
+                    length: data.len(),
+                    version: key.version,
+                    data,
+                });
+            }
+        }
+        Err(DatabaseError::KeyNotFound(key_name))
+    }
+
+    /// Every key that was set when the snapshot was taken.
+    /// Like `InternalApi::fetch_keys`, keys within namespaces are not included.
+    pub fn fetch_keys(&self) -> Result<Vec<VirtualKey>, DatabaseError> {
+        let mut keys = Vec::new();
+        for id in self.partitions() {
+            keys.extend(
+                latest_keys(self.open_partition(id)?.records)
+                    .into_iter()
+                    .filter(|key| !namespace::is_reserved(&key.name)),
+            );
+        }
+        Ok(keys)
+    }
+}
@@ -0,0 +1,157 @@
+//! Statistics about what is stored within a database.
+//! These are read from the partitions, so gathering them costs a scan of every partition.
+use crate::{
+    blob,
+    db::DbDeviceOs,
+    namespace,
+    virtual_db::{kept_records, latest_keys, Partition, Record, VirtualDatabase, VirtualKey},
+    DatabaseError,
+};
+
+/// Statistics about a single partition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartitionStats {
+    /// The id of the partition.
+    pub id: u8,
+    /// The amount of records within the partition.
+    pub records: u64,
+    /// The amount of records that hold the current value of a key.
+    pub live_records: u64,
+    /// The bytes taken by the records that hold the current value of a key.
+    pub live_bytes: u64,
+    /// The bytes taken by overwritten values and removals, which compaction reclaims.
+    pub dead_bytes: u64,
+    /// The ratio of dead bytes to every record byte, between `0.0` and `1.0`.
+    pub fragmentation: f64,
+    /// The length of the current values, once decompressed.
+    pub value_bytes: u64,
+    /// The bytes the current values take up, as they are stored.
+    pub stored_bytes: u64,
+    /// The ratio of bits set in the bloom filter of the partition.
+    pub bloom_saturation: f64,
+}
+
+impl PartitionStats {
+    /// Reads the statistics of the partition, with the keys that are set within it.
+    /// Records that compaction would drop (such as those of dropped namespaces) are dead.
+    fn read(
+        partition: &mut Partition,
+        discard: &dyn Fn(&Record) -> bool,
+    ) -> Result<(Self, Vec<VirtualKey>), DatabaseError> {
+        let records = partition.records()?;
+        let keys = latest_keys(records.iter().cloned());
+        let live = kept_records(&records, None, discard);
+
+        let mut stats = PartitionStats {
+            id: partition.id,
+            records: records.len() as u64,
+            live_records: live.iter().filter(|live| **live).count() as u64,
+            live_bytes: 0,
+            dead_bytes: 0,
+            fragmentation: 0.0,
+            value_bytes: 0,
+            stored_bytes: 0,
+            bloom_saturation: partition.bloom.saturation(),
+        };
+        for (record, live) in records.iter().zip(live) {
+            match live {
+                true => {
+                    stats.live_bytes += record.byte_len() as u64;
+                    stats.value_bytes += record.length as u64;
+                    stats.stored_bytes += record.stored_length as u64;
+                }
+                false => stats.dead_bytes += record.byte_len() as u64,
+            }
+        }
+        stats.fragmentation = ratio(stats.dead_bytes, stats.live_bytes + stats.dead_bytes);
+        Ok((stats, keys))
+    }
+}
+
+/// Statistics about a database.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatabaseStats {
+    /// The sequence number of the latest mutation.
+    pub sequence: u64,
+    /// The amount of keys that are set, including keys within namespaces and keys
+    /// pointing at blobs, but not the entries the database keeps for itself.
+    pub keys: u64,
+    /// The amount of namespaces.
+    pub namespaces: u64,
+    /// The statistics of every partition.
+    pub partitions: Vec<PartitionStats>,
+    /// The bytes taken by records that hold the current value of a key.
+    pub live_bytes: u64,
+    /// The bytes that compaction would reclaim (if no snapshot is alive).
+    pub dead_bytes: u64,
+    /// The ratio of dead bytes to every record byte, between `0.0` and `1.0`.
+    pub fragmentation: f64,
+    /// The size of the current values as they are stored, divided by their size once
+    /// decompressed. This is `1.0` when nothing is compressed.
+    pub compression_ratio: f64,
+    /// The ratio of key lookups answered by the key cache since the database was opened.
+    pub cache_hit_rate: f64,
+    /// The Operating System that created the database.
+    pub created_on: DbDeviceOs,
+    /// The time of the last "open" operation.
+    pub last_open: u128,
+    /// The time of the last "close" operation.
+    pub last_close: u128,
+    /// The time of the last "write" operation.
+    pub last_write: u128,
+}
+
+impl VirtualDatabase {
+    /// Gathers the statistics of the virtual database.
+    /// The header fields are left at their defaults, see `Database::stats`.
+    pub fn stats(&mut self) -> Result<DatabaseStats, DatabaseError> {
+        let mut partitions = Vec::new();
+        let mut keys = 0;
+        let dropped = self.dropped_namespaces();
+        for partition in self.parts.iter_mut() {
+            let (stats, set) = PartitionStats::read(partition, &dropped)?;
+            partitions.push(stats);
+            keys += set
+                .iter()
+                .filter(|key| match namespace::split_key(&key.name) {
+                    Some((id, _)) => self.namespaces.values().any(|live| *live == id),
+                    None => {
+                        blob::link_name(&key.name).is_some() || !namespace::is_reserved(&key.name)
+                    }
+                })
+                .count() as u64;
+        }
+
+        let live_bytes = partitions.iter().map(|stats| stats.live_bytes).sum();
+        let dead_bytes = partitions.iter().map(|stats| stats.dead_bytes).sum();
+        let value_bytes = partitions.iter().map(|stats| stats.value_bytes).sum();
+        let stored_bytes = partitions.iter().map(|stats| stats.stored_bytes).sum();
+        let (hits, misses) = self.keys.lookups();
+        Ok(DatabaseStats {
+            sequence: self.sequence,
+            keys,
+            namespaces: self.namespaces.len() as u64,
+            partitions,
+            live_bytes,
+            dead_bytes,
+            fragmentation: ratio(dead_bytes, live_bytes + dead_bytes),
+            compression_ratio: match value_bytes {
+                0 => 1.0,
+                _ => ratio(stored_bytes, value_bytes),
+            },
+            cache_hit_rate: ratio(hits, hits + misses),
+            created_on: DbDeviceOs::current(),
+            last_open: 0,
+            last_close: 0,
+            last_write: 0,
+        })
+    }
+}
+
+/// Divides, where anything divided by `0` is `0.0`.
+fn ratio(part: u64, whole: u64) -> f64 {
+    match whole {
+        0 => 0.0,
+        whole => part as f64 / whole as f64,
+    }
+}
@@ -0,0 +1,327 @@
+//! Tiered storage of partitions across hot and cold directories.
+//!
+//! Partitions are siblings of the database file, unless the database has storage tiers.
+//! The tiers and the directory of every partition are kept in a catalog next to the
+//! database file (`{path}.tiers`), which is read when the database is opened, so
+//! `VirtualLocation`s stay the same wherever a partition is stored.
+//!
+//! A partition is moved by copying it into the directory of its new tier, then recording
+//! the new directory in the catalog, and only then removing the old file. Files that
+//! snapshots may still read are removed once no snapshot is alive.
+use crate::{
+    scrub,
+    virtual_db::{Partition, VirtualDatabase},
+    DatabaseError,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// The tier a partition is stored in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    /// Fast storage, for partitions that are used.
+    Hot,
+    /// Slow (or cheap) storage, for partitions that are rarely used.
+    Cold,
+}
+
+/// The directories of every tier.
+/// Partitions are spread over the directories of their tier by their id.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StorageTiers {
+    pub hot: Vec<PathBuf>,
+    pub cold: Vec<PathBuf>,
+}
+
+impl StorageTiers {
+    /// The directory the partition is stored in within the given tier.
+    pub fn directory(&self, tier: Tier, id: u8) -> Option<&PathBuf> {
+        let directories = match tier {
+            Tier::Hot => &self.hot,
+            Tier::Cold => &self.cold,
+        };
+        match directories.len() {
+            0 => None,
+            count => directories.get(id as usize % count),
+        }
+    }
+}
+
+/// Where a partition is stored.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    /// The id of the partition.
+    pub id: u8,
+    /// The tier the partition is stored in.
+    pub tier: Tier,
+    /// The directory the partition is stored in.
+    pub directory: PathBuf,
+    /// When a value was last read from, or written to, the partition.
+    pub last_access: u128,
+}
+
+/// Decides when partitions move between tiers, see `Database::apply_tiering`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TieringPolicy {
+    /// Hot partitions that have not been used for this many milliseconds move to the cold tier.
+    pub cold_after: u128,
+    /// Cold partitions that had this many values read since they moved go back to the hot tier.
+    pub promote_after_reads: u64,
+}
+
+/// The storage tiers of a database, with the placement of every partition.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TierCatalog {
+    pub tiers: StorageTiers,
+    pub placements: Vec<Placement>,
+}
+
+impl TierCatalog {
+    /// The path of the catalog of the database at the given path.
+    pub fn path_for(db_path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.tiers", db_path.display()))
+    }
+
+    /// Reads the catalog of the database, if it has storage tiers.
+    pub fn load(db_path: &Path) -> Result<Option<Self>, DatabaseError> {
+        let data = match fs::read(Self::path_for(db_path)) {
+            Ok(data) => data,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|error| DatabaseError::Implementation(format!("Invalid tiers: {}", error)))
+    }
+
+    /// Writes the catalog next to the database, replacing the previous one at once.
+    pub fn save(&self, db_path: &Path) -> Result<(), DatabaseError> {
+        let path = Self::path_for(db_path);
+        let temporary = PathBuf::from(format!("{}.tmp", path.display()));
+        let data = serde_json::to_vec_pretty(self)
+            .map_err(|error| DatabaseError::Implementation(error.to_string()))?;
+        fs::write(&temporary, data)?;
+        File::open(&temporary)?.sync_all()?;
+        fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+
+    /// The placement of the partition with the given id.
+    pub fn placement(&self, id: u8) -> Option<&Placement> {
+        self.placements.iter().find(|placement| placement.id == id)
+    }
+
+    /// The path of the partition with the given id, if the catalog places it.
+    pub fn partition_path(&self, db_path: &Path, name: &str, id: u8) -> Option<PathBuf> {
+        let file_name = Partition::path_for(db_path, name, id)
+            .file_name()?
+            .to_owned();
+        self.placement(id)
+            .map(|placement| placement.directory.join(file_name))
+    }
+}
+
+impl VirtualDatabase {
+    /// Configures the storage tiers of the database.
+    /// Partitions stay where they are (as hot partitions) until they are moved.
+    pub fn set_tiers(&mut self, tiers: StorageTiers) -> Result<(), DatabaseError> {
+        if !self.partitioned() {
+            return Err(DatabaseError::Implementation(
+                "Tiered storage needs a partitioned database".to_string(),
+            ));
+        }
+        let previous = self.tiers.take().unwrap_or_default();
+        let placements = self
+            .parts
+            .iter()
+            .map(|partition| Placement {
+                id: partition.id,
+                tier: previous
+                    .placement(partition.id)
+                    .map_or(Tier::Hot, |placement| placement.tier),
+                directory: directory_of(partition.path()),
+                last_access: partition.last_access,
+            })
+            .collect();
+        let catalog = TierCatalog { tiers, placements };
+        catalog.save(&self.path)?;
+        self.tiers = Some(catalog);
+        Ok(())
+    }
+
+    /// Where every partition is stored.
+    pub fn placements(&self) -> Vec<Placement> {
+        self.parts
+            .iter()
+            .map(|partition| Placement {
+                id: partition.id,
+                tier: self
+                    .tiers
+                    .as_ref()
+                    .and_then(|catalog| catalog.placement(partition.id))
+                    .map_or(Tier::Hot, |placement| placement.tier),
+                directory: directory_of(partition.path()),
+                last_access: partition.last_access,
+            })
+            .collect()
+    }
+
+    /// Moves a partition into the given tier.
+    /// Returns the new path of the partition.
+    pub fn move_partition(&mut self, id: u8, tier: Tier) -> Result<PathBuf, DatabaseError> {
+        let directory = self
+            .tiers
+            .as_ref()
+            .ok_or_else(|| {
+                DatabaseError::Implementation("Tiered storage is not configured".to_string())
+            })?
+            .tiers
+            .directory(tier, id)
+            .cloned()
+            .ok_or_else(|| {
+                DatabaseError::Implementation(format!("No directories for the {:?} tier", tier))
+            })?;
+        let index = self.partition_index(id as u64)?;
+        let old_path = self.parts[index].path().to_path_buf();
+        let file_name = old_path.file_name().ok_or_else(|| {
+            DatabaseError::Implementation("Partition has no file name".to_string())
+        })?;
+        let new_path = directory.join(file_name);
+
+        if new_path != old_path {
+            fs::create_dir_all(&directory)?;
+            let temporary = PathBuf::from(format!("{}.moving", new_path.display()));
+            fs::copy(&old_path, &temporary)?;
+            File::open(&temporary)?.sync_all()?;
+            fs::rename(&temporary, &new_path)?;
+        }
+
+        let last_access = self.parts[index].last_access;
+        // the moved partition is opened before the catalog points at it, so a partition
+        // that can not be opened leaves the database as it was.
+        let moved = match new_path != old_path {
+            true => match self.open_moved(new_path.clone(), index, id) {
+                Ok(partition) => Some(partition),
+                Err(error) => {
+                    let _ = fs::remove_file(&new_path);
+                    return Err(error);
+                }
+            },
+            false => None,
+        };
+        self.placements_changed(Placement {
+            id,
+            tier,
+            directory,
+            last_access,
+        })?;
+
+        if let Some(partition) = moved {
+            self.parts[index] = partition;
+            self.retired.push(old_path);
+        }
+        self.remove_retired();
+        Ok(new_path)
+    }
+
+    /// Opens the copy of the partition at `index` that was moved to `path`.
+    /// The partition is read with the keys and settings of the partition it replaces.
+    fn open_moved(&self, path: PathBuf, index: usize, id: u8) -> Result<Partition, DatabaseError> {
+        let mut partition = Partition::open(path, id)?;
+        partition.last_access = self.parts[index].last_access;
+        partition.compression = self.parts[index].compression;
+        partition.dictionaries = self.dictionaries.clone();
+        partition.cipher = self.parts[index].cipher.clone();
+        partition.init()?;
+        Ok(partition)
+    }
+
+    /// Moves the partitions that the policy says belong in another tier.
+    /// Returns the partitions that moved, with their new tier.
+    pub fn apply_tiering(
+        &mut self,
+        policy: &TieringPolicy,
+    ) -> Result<Vec<(u8, Tier)>, DatabaseError> {
+        let now = crate::utils::timestamp();
+        let mut moved = Vec::new();
+        for placement in self.placements() {
+            let reads = self.parts[self.partition_index(placement.id as u64)?].reads;
+            let tier = match placement.tier {
+                Tier::Hot if now.saturating_sub(placement.last_access) >= policy.cold_after => {
+                    Tier::Cold
+                }
+                Tier::Cold if reads >= policy.promote_after_reads => Tier::Hot,
+                _ => continue,
+            };
+            self.move_partition(placement.id, tier)?;
+            moved.push((placement.id, tier));
+        }
+        Ok(moved)
+    }
+
+    /// Records when every partition was last used, so it is remembered when reopened.
+    pub(crate) fn save_tiers(&mut self) -> Result<(), DatabaseError> {
+        let accessed: Vec<(u8, u128)> = self
+            .parts
+            .iter()
+            .map(|partition| (partition.id, partition.last_access))
+            .collect();
+        if let Some(catalog) = self.tiers.as_mut() {
+            for placement in catalog.placements.iter_mut() {
+                if let Some((_, last_access)) = accessed.iter().find(|(id, _)| *id == placement.id)
+                {
+                    placement.last_access = *last_access;
+                }
+            }
+            catalog.save(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the placement of a partition in the catalog, and saves the catalog.
+    fn placements_changed(&mut self, placement: Placement) -> Result<(), DatabaseError> {
+        if let Some(catalog) = self.tiers.as_mut() {
+            catalog
+                .placements
+                .retain(|existing| existing.id != placement.id);
+            catalog.placements.push(placement);
+            catalog.placements.sort_by_key(|placement| placement.id);
+            catalog.save(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the files of moved partitions, once no snapshot can read them.
+    fn remove_retired(&mut self) {
+        if !self.snapshots.is_empty() {
+            return;
+        }
+        for path in std::mem::take(&mut self.retired) {
+            if self.secure_delete {
+                let _ = scrub::scrub_file(&path);
+            }
+            // a file that is already gone does not need to be removed.
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Whether or not the partitions are stored apart from the database file.
+    fn partitioned(&self) -> bool {
+        self.parts
+            .iter()
+            .all(|partition| partition.path() != self.path.as_path())
+    }
+}
+
+/// The directory of a file, where a bare file name is within the current directory.
+fn directory_of(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
@@ -1,4 +1,5 @@
 use crate::DatabaseError;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// A utility trait to get the amount of bytes of a certain database struct.
 pub trait GetByteLength {
@@ -6,6 +7,15 @@ pub trait GetByteLength {
     fn byte_len(&self) -> usize;
 }
 
+/// Gets the current unix epoch time stamp in milliseconds.
+/// This is the format used by the timestamps in the database header.
+pub fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0)
+}
+
 pub trait InternalApi {
     /// The key type used by the internal adapter for this database.
     type KeyKind;
@@ -15,6 +25,15 @@ pub trait InternalApi {
     /// Get a key from the database.
     fn get(&mut self, key_name: String) -> Result<Self::ValueKind, DatabaseError>;
 
+    /// Get `length` bytes of a key's value, starting at `offset`.
+    /// Only the requested bytes are read. The range is cut short at the end of the value.
+    fn get_range(
+        &mut self,
+        key_name: String,
+        offset: u64,
+        length: u64,
+    ) -> Result<Self::ValueKind, DatabaseError>;
+
     /// Set a key in the database.
     /// This will overwrite any existing key.
     /// If you want to add a new key, use `add_key`.
@@ -31,3 +50,65 @@ pub trait InternalApi {
     /// Returns a vector of keys.
     fn fetch_keys(&mut self) -> Result<Vec<Self::KeyKind>, DatabaseError>;
 }
+
+/// The async variant of `InternalApi`, for use within tokio servers.
+/// File operations use non-blocking I/O, so the runtime can keep serving other
+/// requests while one is waiting on the disk.
+#[cfg(feature = "async")]
+pub trait AsyncInternalApi {
+    /// The key type used by the internal adapter for this database.
+    type KeyKind;
+    /// The value type used by the internal adapter for this database.
+    type ValueKind;
+
+    /// Get a key from the database.
+    fn get(
+        &mut self,
+        key_name: String,
+    ) -> impl std::future::Future<Output = Result<Self::ValueKind, DatabaseError>> + Send;
+
+    /// Set a key in the database.
+    /// This will overwrite any existing key.
+    fn set(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> impl std::future::Future<Output = Result<Self::KeyKind, DatabaseError>> + Send;
+
+    /// Similar to `set`, but will only create the key if it does not already exist.
+    fn add(
+        &mut self,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> impl std::future::Future<Output = Result<Self::KeyKind, DatabaseError>> + Send;
+
+    /// Remove a key from the database.
+    /// Returns whether or not the operation succeeded.
+    fn remove(
+        &mut self,
+        key_name: String,
+    ) -> impl std::future::Future<Output = Result<bool, DatabaseError>> + Send;
+
+    /// Fetch all keys from the database.
+    /// Returns a vector of keys.
+    fn fetch_keys(
+        &mut self,
+    ) -> impl std::future::Future<Output = Result<Vec<Self::KeyKind>, DatabaseError>> + Send;
+}
+
+/// The reads of `AsyncInternalApi` that only need a shared reference to the database,
+/// so many of them can run at the same time, see the implementation for `Arc<RwLock<T>>`.
+/// Unlike `AsyncInternalApi::get`, keys found in the partitions are not cached.
+#[cfg(feature = "async")]
+pub trait AsyncSharedApi: AsyncInternalApi {
+    /// Get a key from the database.
+    fn get_shared(
+        &self,
+        key_name: String,
+    ) -> impl std::future::Future<Output = Result<Self::ValueKind, DatabaseError>> + Send;
+
+    /// Fetch all keys from the database.
+    fn fetch_keys_shared(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Vec<Self::KeyKind>, DatabaseError>> + Send;
+}
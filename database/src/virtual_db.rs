@@ -1,14 +1,28 @@
 use crate::{
-    db::{Header, Key},
-    preamble::Preamble,
-    utils::{GetByteLength, InternalApi},
+    bloom::BloomFilter,
+    cache::KeyCache,
+    changes::{Change, ChangeKind, Subscribers},
+    compression::{self, Compression, Encoding},
+    db::Header,
+    dictionary::{self, Dictionaries},
+    encryption::{self, Cipher, TOKEN_LEN},
+    namespace,
+    preamble::{CompressionMode, EncryptionLevel, Preamble},
+    replication,
+    snapshot::{Snapshot, SnapshotPin, SnapshotRegistry},
+    tiering::TierCatalog,
+    utils::{timestamp, GetByteLength, InternalApi},
     DatabaseError,
 };
 use byteorder::{ReadBytesExt, WriteBytesExt, BE};
 use std::{
-    fs::File,
-    io::{BufReader, Cursor, Read, Seek, Write},
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{BufReader, Cursor, Read, Seek, SeekFrom, Write},
+    ops::Range,
     path::{Path, PathBuf},
+    sync::mpsc::Receiver,
 };
 
 /// A virtual Item is the "Value" to a key in a One-Link Database.
@@ -20,11 +34,15 @@ pub struct VirtualItem {
     /// The location of the part in the database.
     pub location: VirtualLocation,
     /// The length of the part in bytes.
+    /// For a ranged read, this is the length of the whole value, not of the range.
     pub length: usize,
+    /// The version of the key when the item was read, see `VirtualKey::version`.
+    pub version: u64,
     /// The data of the item.
     pub data: Vec<u8>,
 }
 
+#[derive(Clone, Debug)]
 pub struct VirtualKey {
     /// The name of the key.
     pub name: String,
@@ -32,9 +50,14 @@ pub struct VirtualKey {
     pub location: VirtualLocation,
     /// The length of the part in bytes.
     pub length: usize,
+    /// The sequence number of the mutation that last wrote this key.
+    pub sequence: u64,
+    /// The amount of times the key has been written since it was created.
+    /// A new key starts at `1`, this is used for compare-and-set.
+    pub version: u64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct VirtualLocation {
     /// The partition index of the virtual location
     pub id: u64,
@@ -65,6 +88,563 @@ impl VirtualLocation {
     }
 }
 
+/// The kind of a record stored within a partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RecordKind {
+    /// The record holds a new value for the key.
+    Set,
+    /// The record marks the key as removed.
+    Remove,
+}
+
+/// The kind byte of a record that was overwritten by a secure delete, see `crate::scrub`.
+/// Scrubbed records keep their length, so the records after them are read as before,
+/// but they are skipped by every reader.
+pub const SCRUBBED_KIND: u8 = 0xff;
+
+impl RecordKind {
+    pub fn from_u8(kind: u8) -> Result<Self, DatabaseError> {
+        match kind {
+            0 => Ok(RecordKind::Set),
+            1 => Ok(RecordKind::Remove),
+            _ => Err(DatabaseError::Implementation(format!(
+                "Unknown record kind: {}",
+                kind
+            ))),
+        }
+    }
+}
+
+/// A record is a single mutation appended to a partition.
+/// Partitions are append only, the latest record for a key is its current state.
+///
+/// | Name     | Type   | Byte Length |
+/// | -------- | ------ | ----------- |
+/// | kind     | `u8`   | 1           |
+/// | sequence | `u64`  | 8           |
+/// | version  | `u64`  | 8           |
+/// | name_len | `u16`  | 2           |
+/// | name     | `str`  | name_len    |
+/// | encoding | `u8`   | 1           |
+/// | length   | `u128` | 16          |
+/// | data     | `[u8]` | length      |
+///
+/// The location of a record points at its encoding, and the data is the value as it is
+/// stored, see `Encoding`. How much of the record is sealed depends on the encryption
+/// level of the partition, see `crate::encryption`. A record that is sealed as a whole
+/// is pointed at by its location instead.
+///
+/// Records are kept in memory with their key name and the lengths of their value opened.
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// What the record does to the key.
+    pub kind: RecordKind,
+    /// The sequence number of the mutation.
+    pub sequence: u64,
+    /// The version of the key after the mutation.
+    pub version: u64,
+    /// The name of the key.
+    pub name: String,
+    /// The location of the record's value.
+    pub location: VirtualLocation,
+    /// The length of the record's value.
+    pub length: usize,
+    /// How the record's value is stored.
+    pub encoding: Encoding,
+    /// The amount of bytes the record's value takes up within the partition.
+    pub stored_length: usize,
+    /// Where the record starts, from the start of the partition's data.
+    pub position: u64,
+    /// The amount of bytes the whole record takes up within the partition.
+    pub size: usize,
+}
+
+impl Record {
+    /// The amount of bytes before the name of the key.
+    pub const PREFIX_LEN: usize = 1 + 8 + 8 + 2;
+
+    /// The amount of bytes between the name of the key and the stored value.
+    pub const VALUE_PREFIX_LEN: usize = 1 + 16;
+
+    /// Converts the record into the key it describes.
+    pub fn to_key(&self) -> VirtualKey {
+        VirtualKey {
+            name: self.name.clone(),
+            location: self.location,
+            length: self.length,
+            sequence: self.sequence,
+            version: self.version,
+        }
+    }
+
+    /// The record that holds the value of the key.
+    /// The encoding is not known from the key, it is read with the value.
+    pub fn from_key(key: &VirtualKey) -> Self {
+        Record {
+            kind: RecordKind::Set,
+            sequence: key.sequence,
+            version: key.version,
+            name: key.name.clone(),
+            location: key.location,
+            length: key.length,
+            encoding: Encoding::Raw,
+            stored_length: key.length,
+            position: 0,
+            size: 0,
+        }
+    }
+
+    /// The amount of bytes the record takes up within a partition.
+    pub fn byte_len(&self) -> usize {
+        self.size
+    }
+
+    /// Converts the record into a change for the change feed.
+    pub fn to_change(&self) -> Change {
+        Change {
+            sequence: self.sequence,
+            kind: match self.kind {
+                RecordKind::Set => ChangeKind::Set,
+                RecordKind::Remove => ChangeKind::Remove,
+            },
+            key: self.name.clone(),
+            length: self.length,
+        }
+    }
+}
+
+/// Reads the records from a reader positioned at the start of a partition's data.
+/// Reading stops at `length` bytes, or at a record that was only partially written.
+/// Records are opened with the `cipher` as far as the partition's `level` seals them.
+/// Returns the records, and the amount of bytes the complete records take up.
+pub fn read_records<R: Read + Seek>(
+    buffer: &mut BufReader<R>,
+    id: u8,
+    length: u64,
+    level: EncryptionLevel,
+    cipher: Option<&Cipher>,
+) -> Result<(Vec<Record>, u64), DatabaseError> {
+    read_records_with(buffer, id, length, level, cipher, None)
+}
+
+/// Reads every record, like `read_records`.
+/// If `tokens` are given, sealed names are only opened if they have one of the tokens,
+/// and the records of other keys are returned without a name.
+fn read_records_with<R: Read + Seek>(
+    buffer: &mut BufReader<R>,
+    id: u8,
+    length: u64,
+    level: EncryptionLevel,
+    cipher: Option<&Cipher>,
+    tokens: Option<&[[u8; TOKEN_LEN]]>,
+) -> Result<(Vec<Record>, u64), DatabaseError> {
+    let cipher = match (level, cipher) {
+        (EncryptionLevel::None | EncryptionLevel::Values, _) => None,
+        (_, Some(cipher)) => Some(cipher),
+        (_, None) => return Err(DatabaseError::IncorrectPassword),
+    };
+    let mut records = Vec::new();
+    let mut position: u64 = 0;
+
+    loop {
+        let entry = match (level, cipher) {
+            (EncryptionLevel::Full, Some(cipher)) => {
+                read_sealed_record(buffer, id, position, length, cipher)?
+            }
+            _ => read_record(buffer, id, position, length, level, cipher, tokens)?,
+        };
+        match entry {
+            Some(Entry::Record(mut record)) => {
+                record.location.index = records.len() as u64;
+                position += record.size as u64;
+                records.push(record);
+            }
+            Some(Entry::Scrubbed(size)) => position += size,
+            None => break,
+        }
+    }
+
+    Ok((records, position))
+}
+
+/// A record read from a partition file.
+enum Entry {
+    Record(Record),
+    /// A record that was overwritten by a secure delete, with the amount of bytes it takes up.
+    Scrubbed(u64),
+}
+
+/// Reads a single record at `position`, in the layout described by [`Record`].
+/// Returns `None` if the record does not fit within `length` bytes.
+fn read_record<R: Read + Seek>(
+    buffer: &mut BufReader<R>,
+    id: u8,
+    position: u64,
+    length: u64,
+    level: EncryptionLevel,
+    cipher: Option<&Cipher>,
+    tokens: Option<&[[u8; TOKEN_LEN]]>,
+) -> Result<Option<Entry>, DatabaseError> {
+    if position + Record::PREFIX_LEN as u64 > length {
+        return Ok(None);
+    }
+    let kind = buffer.read_u8()?;
+    let sequence = buffer.read_u64::<BE>()?;
+    let version = buffer.read_u64::<BE>()?;
+    let name_len = buffer.read_u16::<BE>()?;
+    let offset = position + (Record::PREFIX_LEN + name_len as usize) as u64;
+    if offset + Record::VALUE_PREFIX_LEN as u64 > length {
+        return Ok(None);
+    }
+    if kind == SCRUBBED_KIND {
+        buffer.seek_relative(name_len as i64 + 1)?;
+        let stored_length = buffer.read_u128::<BE>()? as u64;
+        let end = offset + Record::VALUE_PREFIX_LEN as u64 + stored_length;
+        if end > length {
+            return Ok(None);
+        }
+        buffer.seek_relative(stored_length as i64)?;
+        return Ok(Some(Entry::Scrubbed(end - position)));
+    }
+    let kind = RecordKind::from_u8(kind)?;
+    let mut name = vec![0; name_len as usize];
+    buffer.read_exact(&mut name)?;
+    let wanted = tokens.is_none_or(|tokens| {
+        tokens
+            .iter()
+            .any(|token| token.as_slice() == encryption::sealed_token(&name))
+    });
+    let name = match cipher {
+        Some(cipher) if wanted => encryption::open_name(cipher, &name)?,
+        Some(_) => String::new(),
+        None => String::from_utf8(name)
+            .map_err(|_| DatabaseError::Implementation("Key is not valid UTF-8".to_string()))?,
+    };
+    let encoding = Encoding::from_u8(buffer.read_u8()?)?;
+    let stored_length = buffer.read_u128::<BE>()? as u64;
+    let end = offset + Record::VALUE_PREFIX_LEN as u64 + stored_length;
+    if end > length {
+        return Ok(None);
+    }
+    // compressed and sealed values start with their length once decoded.
+    let sealed = level != EncryptionLevel::None;
+    let value_length = match encoding.is_compressed() || sealed {
+        true if stored_length < compression::SIZE_LEN as u64 => {
+            return Err(DatabaseError::Implementation(
+                "Compressed value is corrupted".to_string(),
+            ));
+        }
+        true => {
+            let value_length = buffer.read_u64::<BE>()?;
+            buffer.seek_relative(stored_length as i64 - compression::SIZE_LEN as i64)?;
+            value_length
+        }
+        false => {
+            buffer.seek_relative(stored_length as i64)?;
+            stored_length
+        }
+    };
+
+    Ok(Some(Entry::Record(Record {
+        kind,
+        sequence,
+        version,
+        name,
+        location: VirtualLocation {
+            id: id as u64,
+            offset,
+            index: 0,
+        },
+        length: value_length as usize,
+        encoding,
+        stored_length: stored_length as usize,
+        position,
+        size: (end - position) as usize,
+    })))
+}
+
+/// Reads a single record at `position` that was sealed as a whole.
+/// Returns `None` if the record does not fit within `length` bytes.
+fn read_sealed_record<R: Read + Seek>(
+    buffer: &mut BufReader<R>,
+    id: u8,
+    position: u64,
+    length: u64,
+    cipher: &Cipher,
+) -> Result<Option<Entry>, DatabaseError> {
+    if position + encryption::RECORD_PREFIX_LEN as u64 > length {
+        return Ok(None);
+    }
+    let sealed_len = buffer.read_u64::<BE>()?;
+    let size = encryption::RECORD_PREFIX_LEN as u64 + sealed_len;
+    if position + size > length {
+        return Ok(None);
+    }
+    let mut sealed = vec![0; sealed_len as usize];
+    buffer.read_exact(&mut sealed)?;
+    Ok(Some(
+        match open_sealed_record(cipher, id, position, &sealed)? {
+            Some(record) => Entry::Record(record),
+            None => Entry::Scrubbed(size),
+        },
+    ))
+}
+
+/// Opens a record that was sealed as a whole, found at `position` within the partition.
+/// The location of the record points at the start of the sealed record.
+/// Returns `None` if the record was overwritten by a secure delete.
+pub fn open_sealed_record(
+    cipher: &Cipher,
+    id: u8,
+    position: u64,
+    sealed: &[u8],
+) -> Result<Option<Record>, DatabaseError> {
+    let plain = encryption::open_record(cipher, sealed)?;
+    let length = plain.len() as u64;
+    let entry = read_record(
+        &mut BufReader::new(Cursor::new(plain)),
+        id,
+        0,
+        length,
+        EncryptionLevel::None,
+        None,
+        None,
+    )?;
+    let mut record = match entry {
+        Some(Entry::Record(record)) => record,
+        Some(Entry::Scrubbed(_)) => return Ok(None),
+        None => {
+            return Err(DatabaseError::Implementation(
+                "Sealed record is corrupted".to_string(),
+            ))
+        }
+    };
+    record.location.offset = position;
+    record.position = position;
+    record.size = encryption::RECORD_PREFIX_LEN + sealed.len();
+    Ok(Some(record))
+}
+
+/// Reads the value of a key at the given position of a partition file, as it was written.
+/// The value is opened with the `cipher` first, if the partition is encrypted.
+/// The value has to end before `end`, the length of the partition file.
+pub fn read_value<R: Read + Seek>(
+    reader: &mut R,
+    position: u64,
+    end: u64,
+    key_name: &str,
+    dictionaries: &Dictionaries,
+    level: EncryptionLevel,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<u8>, DatabaseError> {
+    reader.seek(SeekFrom::Start(position))?;
+    let remaining = end.saturating_sub(position);
+    let (encoding, encoded, _) = read_encoded(reader, remaining, key_name, level, cipher)?;
+    compression::decode(encoding, encoded, dictionaries)
+}
+
+/// Reads the value of a key at the current position of a partition file, as it is encoded.
+/// The value is opened with the `cipher` first, as far as the partition's `level` seals it,
+/// which fails if it was sealed for another key.
+/// Fails if the value is longer than the `remaining` bytes of the partition file, so a
+/// corrupted length is not allocated.
+/// Returns the encoding, the value, and the amount of bytes read.
+pub fn read_encoded<R: Read>(
+    reader: &mut R,
+    remaining: u64,
+    key_name: &str,
+    level: EncryptionLevel,
+    cipher: Option<&Cipher>,
+) -> Result<(Encoding, Vec<u8>, u64), DatabaseError> {
+    let cipher = || cipher.ok_or(DatabaseError::IncorrectPassword);
+    if level == EncryptionLevel::Full {
+        let sealed_len = reader.read_u64::<BE>()?;
+        let remaining = remaining.saturating_sub(encryption::RECORD_PREFIX_LEN as u64);
+        let mut sealed = vec![0; fits(sealed_len, remaining)?];
+        reader.read_exact(&mut sealed)?;
+        let plain = encryption::open_record(cipher()?, &sealed)?;
+        let mut cursor = Cursor::new(&plain);
+        cursor.set_position((Record::PREFIX_LEN - 2) as u64);
+        let mut name = vec![0; cursor.read_u16::<BE>()? as usize];
+        cursor.read_exact(&mut name)?;
+        if name != key_name.as_bytes() {
+            return Err(DatabaseError::Implementation(
+                "Sealed record belongs to another key".to_string(),
+            ));
+        }
+        let left = plain.len() as u64 - cursor.position();
+        let (encoding, stored) = read_stored_here(&mut cursor, left)?;
+        return Ok((
+            encoding,
+            stored,
+            (encryption::RECORD_PREFIX_LEN + sealed.len()) as u64,
+        ));
+    }
+    let (encoding, stored) = read_stored_here(reader, remaining)?;
+    let read = (Record::VALUE_PREFIX_LEN + stored.len()) as u64;
+    let encoded = match level {
+        EncryptionLevel::None => stored,
+        _ => encryption::open_value(cipher()?, key_name, encoding, &stored)?,
+    };
+    Ok((encoding, encoded, read))
+}
+
+/// Reads the value at the current position, as it is stored.
+/// The value and its prefix have to fit within the `remaining` bytes.
+fn read_stored_here<R: Read>(
+    reader: &mut R,
+    remaining: u64,
+) -> Result<(Encoding, Vec<u8>), DatabaseError> {
+    let encoding = Encoding::from_u8(reader.read_u8()?)?;
+    let size = reader.read_u128::<BE>()?;
+    let remaining = remaining.saturating_sub(Record::VALUE_PREFIX_LEN as u64);
+    let mut data: Vec<u8> = vec![0; fits(size.try_into().unwrap_or(u64::MAX), remaining)?];
+    reader.read_exact(&mut data)?;
+    Ok((encoding, data))
+}
+
+/// Checks a length read from a partition file against the bytes that are left in it,
+/// before anything is allocated for it.
+pub(crate) fn fits(length: u64, remaining: u64) -> Result<usize, DatabaseError> {
+    match length <= remaining {
+        true => Ok(length as usize),
+        false => Err(DatabaseError::Implementation(format!(
+            "Stored length {} is longer than the {} bytes left in the partition",
+            length, remaining
+        ))),
+    }
+}
+
+/// Reads a whole record of a partition file, exactly as it is stored.
+pub fn read_raw<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    record: &Record,
+) -> Result<Vec<u8>, DatabaseError> {
+    reader.seek(SeekFrom::Start(start + record.position))?;
+    let mut raw = vec![0; record.size];
+    reader.read_exact(&mut raw)?;
+    Ok(raw)
+}
+
+/// Reads the preamble, header and bloom filter at the start of a partition file.
+/// The header is only skipped, so its length is returned instead.
+/// The first record follows directly after them.
+pub fn read_partition_head(
+    reader: &mut dyn Read,
+) -> Result<(Preamble, usize, BloomFilter), DatabaseError> {
+    let mut preamble_bytes = vec![0; Preamble::new().byte_len()];
+    reader.read_exact(&mut preamble_bytes)?;
+    let preamble = Preamble::create(&preamble_bytes)?;
+    let header_len = skip_header(reader, &preamble)?;
+    let bloom = BloomFilter::read(reader)?;
+    Ok((preamble, header_len, bloom))
+}
+
+/// Skips the header after the preamble of a partition file, which is sealed if the
+/// partition is encrypted. Returns the amount of bytes skipped.
+pub fn skip_header(reader: &mut dyn Read, preamble: &Preamble) -> Result<usize, DatabaseError> {
+    match preamble.is_encrypted() {
+        true => encryption::skip_head(reader),
+        false => Ok(Header::read(reader)?.byte_len()),
+    }
+}
+
+/// Encodes a record in the layout described by [`Record`].
+/// The name is given as it is stored, and the value with its encoding.
+pub fn encode_record(
+    kind: RecordKind,
+    sequence: u64,
+    version: u64,
+    key_name: &[u8],
+    encoding: Encoding,
+    stored: &[u8],
+) -> Result<Vec<u8>, DatabaseError> {
+    let mut record: Vec<u8> = Vec::with_capacity(
+        Record::PREFIX_LEN + key_name.len() + Record::VALUE_PREFIX_LEN + stored.len(),
+    );
+    record.write_u8(kind as u8)?;
+    record.write_u64::<BE>(sequence)?;
+    record.write_u64::<BE>(version)?;
+    record.write_u16::<BE>(key_name.len() as u16)?;
+    record.write_all(key_name)?;
+    record.write_u8(encoding as u8)?;
+    record.write_u128::<BE>(stored.len() as u128)?;
+    record.write_all(stored)?;
+    Ok(record)
+}
+
+/// The version of the next write to a key, where `existing` is its current state.
+pub fn next_version(existing: Option<&VirtualKey>) -> u64 {
+    existing.map_or(1, |key| key.version + 1)
+}
+
+/// Folds records (in the order they were written) into the keys that are still set.
+pub fn latest_keys(records: impl IntoIterator<Item = Record>) -> Vec<VirtualKey> {
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut keys: Vec<Option<VirtualKey>> = Vec::new();
+
+    for record in records {
+        match record.kind {
+            RecordKind::Set => match positions.get(&record.name) {
+                Some(position) => keys[*position] = Some(record.to_key()),
+                None => {
+                    positions.insert(record.name.clone(), keys.len());
+                    keys.push(Some(record.to_key()));
+                }
+            },
+            RecordKind::Remove => {
+                if let Some(position) = positions.remove(&record.name) {
+                    keys[position] = None;
+                }
+            }
+        }
+    }
+
+    keys.into_iter().flatten().collect()
+}
+
+/// Whether or not compaction keeps each of the records, in the order they were written.
+/// Records newer than the `horizon` are kept, and older ones only if they are the visible
+/// value of their key, unless they match `discard`.
+pub(crate) fn kept_records(
+    records: &[Record],
+    horizon: Option<u64>,
+    discard: &dyn Fn(&Record) -> bool,
+) -> Vec<bool> {
+    let horizon = horizon.unwrap_or(u64::MAX);
+    // the last record of every key that is visible at the horizon.
+    let mut visible: HashMap<&str, usize> = HashMap::new();
+    for (index, record) in records.iter().enumerate() {
+        if record.sequence <= horizon {
+            visible.insert(&record.name, index);
+        }
+    }
+    records
+        .iter()
+        .enumerate()
+        .map(|(index, record)| {
+            !discard(record)
+                && (record.sequence > horizon
+                    || (record.kind == RecordKind::Set
+                        && visible.get(record.name.as_str()) == Some(&index)))
+        })
+        .collect()
+}
+
+/// A record that is ready to be appended to a partition.
+pub struct PendingAppend {
+    /// The bytes of the bloom filter that changed, with their position in the partition file.
+    pub bloom_writes: Vec<(u64, u8)>,
+    /// The position of the record in the partition file.
+    pub position: u64,
+    /// The encoded record.
+    pub record: Vec<u8>,
+    /// The key, once the record has been written.
+    pub key: VirtualKey,
+}
+
 /// This represents a real part.
 /// A part is a collection of data in different files.
 /// Each part has a magic header and a preamble.
@@ -80,55 +660,708 @@ pub struct Partition {
     pub length: usize,
     /// Whether or not the partition has been initialized.
     pub initialized: bool,
+    /// The amount of records within the partition.
+    pub records: u64,
+    /// The highest sequence number written to the partition.
+    pub sequence: u64,
     /// The path to the partition.
     path: PathBuf,
+    /// The bloom filter over the keys within the partition.
+    pub bloom: BloomFilter,
     /// File handle to the partition.
     file: File,
+    /// The offset of the bloom filter within the partition file.
+    bloom_start: usize,
     /// The start of the partition.
     start: usize,
+    /// When a value was last read from, or written to, the partition.
+    pub last_access: u128,
+    /// The amount of values read from the partition since it was opened or moved.
+    pub reads: u64,
+    /// How values written to the partition are compressed.
+    /// The mode is read from the preamble of the partition.
+    pub compression: Compression,
+    /// The dictionaries of the database the partition belongs to.
+    pub(crate) dictionaries: Dictionaries,
+    /// How much of the partition is encrypted, from its preamble.
+    encryption: EncryptionLevel,
+    /// The master key of the database the partition belongs to, if it is encrypted.
+    pub(crate) cipher: Option<Cipher>,
 }
 
 impl Partition {
-    pub fn new(base_path: &Path, name: String, id: u8) -> Self {
-        let file = File::open(base_path.with_file_name(format!("{}-{}.bin", name, id))).unwrap();
-        Self {
-            id: id,
+    pub fn new(base_path: &Path, name: String, id: u8) -> Result<Self, DatabaseError> {
+        Self::open(Self::path_for(base_path, &name, id), id)
+    }
+
+    /// Opens the partition stored at the given path.
+    /// Partitions are usually siblings of the database, however an unpartitioned
+    /// database stores its data within the database file itself.
+    pub fn open(path: PathBuf, id: u8) -> Result<Self, DatabaseError> {
+        Self::open_with(path, id, true)
+    }
+
+    /// Opens the partition stored at the given path, only for reading if not `writable`.
+    /// Appending to a partition that is not writable fails.
+    pub fn open_with(path: PathBuf, id: u8, writable: bool) -> Result<Self, DatabaseError> {
+        let file = OpenOptions::new().read(true).write(writable).open(&path)?;
+        Ok(Self {
+            id,
             length: 0,
             initialized: false,
-            path: base_path.with_file_name(format!("{}-{}.bin", name, id)),
+            records: 0,
+            sequence: 0,
+            path,
+            bloom: BloomFilter::default(),
             file,
+            bloom_start: 0,
             start: 0,
+            last_access: timestamp(),
+            reads: 0,
+            compression: Compression::default(),
+            dictionaries: Dictionaries::default(),
+            encryption: EncryptionLevel::None,
+            cipher: None,
+        })
+    }
+
+    /// Creates a new, empty partition at the given path and initializes it.
+    /// An encrypted partition seals its header with the `cipher`, and has no key block.
+    pub fn create(
+        path: PathBuf,
+        id: u8,
+        preamble: &Preamble,
+        header: &Header,
+        cipher: Option<&Cipher>,
+    ) -> Result<Self, DatabaseError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        preamble.write(&mut file)?;
+        match cipher {
+            Some(cipher) => {
+                encryption::KeyBlock::write(None, &mut file)?;
+                encryption::write_header(header, cipher, &mut file)?;
+            }
+            None => header.write(&mut file)?,
         }
+        BloomFilter::default().write(&mut file)?;
+        file.sync_all()?;
+        drop(file);
+
+        let mut partition = Self::open(path, id)?;
+        partition.cipher = cipher.cloned();
+        partition.init()?;
+        Ok(partition)
+    }
+
+    /// The path of the partition with the given id.
+    pub fn path_for(base_path: &Path, name: &str, id: u8) -> PathBuf {
+        base_path.with_file_name(format!("{}-{}.bin", name, id))
     }
 
     /// Initializes a Partition
     /// This will read the preamble and the magic header and store the starting data offset in memory.
     pub fn init(&mut self) -> Result<(), DatabaseError> {
+        self.file.seek(SeekFrom::Start(0))?;
         let mut buffer = BufReader::new(self.file.try_clone()?);
-        let preamble = Preamble::create(&mut buffer.buffer())?;
-
-        buffer.seek_relative(preamble.byte_len() as i64)?;
-
-        if preamble.encryption != 0 {
-            // The database is NOT encrypted.
-            let header = Header::create(&mut buffer.buffer())?;
-            // no need to check virtualization here, we know the header is virtual.
-            // we store this start offset incase the db is closed later.
-            // however we are going to consume these bytes so they don't take up space.
-            self.start = header.byte_len() + preamble.byte_len();
-            buffer.take(self.start as u64);
-        } else {
-            return Err(DatabaseError::Implementation(
-                "Non-encrypted databases are not supported yet".to_string(),
-            ));
-        }
+        let mut preamble_bytes = vec![0; Preamble::new().byte_len()];
+        buffer.read_exact(&mut preamble_bytes)?;
+        let preamble = Preamble::create(&preamble_bytes)?;
+
+        // no need to check virtualization here, we know the header is virtual.
+        // we store this start offset incase the db is closed later.
+        self.bloom_start = skip_header(&mut buffer, &preamble)? + preamble.byte_len();
+        self.bloom = BloomFilter::read(&mut buffer)?;
+        self.start = self.bloom_start + self.bloom.byte_len();
+        self.compression.mode = preamble.compression;
+        self.compression.level = preamble.level;
+        self.encryption = preamble.encryption;
+
+        // a record that was only partially written (for example, when the process crashed)
+        // is ignored, and overwritten by the next write.
+        let length = self
+            .file
+            .metadata()?
+            .len()
+            .saturating_sub(self.start as u64);
+        self.file.seek(SeekFrom::Start(self.start as u64))?;
+        let mut buffer = BufReader::new(self.file.try_clone()?);
+        let (records, valid_length) = read_records(
+            &mut buffer,
+            self.id,
+            length,
+            self.encryption,
+            self.cipher()?,
+        )?;
+        self.length = valid_length as usize;
         self.initialized = true;
+
+        self.records = records.len() as u64;
+        self.sequence = records.iter().map(|r| r.sequence).max().unwrap_or(0);
         Ok(())
     }
 
     pub fn get_path(&self) -> String {
         self.path.to_str().unwrap_or("").to_string()
     }
+
+    /// Reads every record within the partition, in the order they were written.
+    pub fn records(&mut self) -> Result<Vec<Record>, DatabaseError> {
+        self.file.seek(SeekFrom::Start(self.start as u64))?;
+        let mut buffer = BufReader::new(self.file.try_clone()?);
+        let (records, _) = read_records(
+            &mut buffer,
+            self.id,
+            self.length as u64,
+            self.encryption,
+            self.cipher()?,
+        )?;
+        Ok(records)
+    }
+
+    /// The master key the partition is encrypted with, or `None` if it is not encrypted.
+    /// Fails if the partition is encrypted, but the master key is unknown.
+    pub(crate) fn cipher(&self) -> Result<Option<&Cipher>, DatabaseError> {
+        match (self.is_encrypted(), self.cipher.as_ref()) {
+            (true, None) => Err(DatabaseError::IncorrectPassword),
+            (true, cipher) => Ok(cipher),
+            (false, _) => Ok(None),
+        }
+    }
+
+    /// Whether or not the name of the key fits in a record, where sealed names
+    /// (`EncryptionLevel::Names`) are longer than the name itself.
+    pub(crate) fn name_fits(&self, key_name: &str) -> bool {
+        let stored = match self.encryption {
+            EncryptionLevel::Names => key_name.len() + encryption::SEAL_OVERHEAD,
+            _ => key_name.len(),
+        };
+        stored <= u16::MAX as usize
+    }
+
+    /// Whether or not the partition is encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption != EncryptionLevel::None
+    }
+
+    /// How much of the partition is encrypted.
+    pub fn encryption(&self) -> EncryptionLevel {
+        self.encryption
+    }
+
+    /// The byte offset of the first record within the partition file.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The length of the partition file, up to the end of its last record.
+    pub fn end(&self) -> u64 {
+        (self.start + self.length) as u64
+    }
+
+    /// The path of the partition file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the value of the key, stored at its location.
+    pub fn read_at(&mut self, key: &VirtualKey) -> Result<Vec<u8>, DatabaseError> {
+        self.accessed(1);
+        let (cipher, end) = (self.cipher()?.cloned(), self.end());
+        read_value(
+            &mut self.file,
+            self.start as u64 + key.location.offset,
+            end,
+            &key.name,
+            &self.dictionaries,
+            self.encryption,
+            cipher.as_ref(),
+        )
+    }
+
+    /// Reads `length` bytes of the value of the key, starting at `offset`.
+    /// Returns the bytes, and the length of the whole value.
+    pub fn read_range(
+        &mut self,
+        key: &VirtualKey,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, u64), DatabaseError> {
+        self.accessed(1);
+        let position = self.start as u64 + key.location.offset;
+        if !self.is_encrypted() {
+            self.file.seek(SeekFrom::Start(position))?;
+            let encoding = Encoding::from_u8(self.file.read_u8()?)?;
+            if encoding == Encoding::ZstdSeekable {
+                // the stored length is not needed, the seek table locates the frames.
+                self.file.read_u128::<BE>()?;
+                return compression::read_seekable_range(&mut self.file, offset, length);
+            }
+            if !encoding.is_compressed() {
+                let size = self.file.read_u128::<BE>()?.try_into().unwrap_or(u64::MAX);
+                let remaining = self.end().saturating_sub(self.file.stream_position()?);
+                fits(size, remaining)?;
+                let start = offset.min(size);
+                let end = start.saturating_add(length).min(size);
+                self.file.seek(SeekFrom::Current(start as i64))?;
+                let mut data = vec![0; (end - start) as usize];
+                self.file.read_exact(&mut data)?;
+                return Ok((data, size));
+            }
+        }
+        // other compressed values, and sealed values, are read as a whole.
+        let (cipher, end) = (self.cipher()?.cloned(), self.end());
+        let value = read_value(
+            &mut self.file,
+            position,
+            end,
+            &key.name,
+            &self.dictionaries,
+            self.encryption,
+            cipher.as_ref(),
+        )?;
+        let size = value.len() as u64;
+        let start = offset.min(size);
+        let end = start.saturating_add(length).min(size);
+        Ok((value[start as usize..end as usize].to_vec(), size))
+    }
+
+    /// Reads the values of the given keys, in the order they were given.
+    /// The values are read in the order they are stored, so the file is read sequentially.
+    pub fn read_many(&mut self, keys: &[VirtualKey]) -> Result<Vec<Vec<u8>>, DatabaseError> {
+        self.accessed(keys.len() as u64);
+        let cipher = self.cipher()?.cloned();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by_key(|index| keys[*index].location.offset);
+
+        self.file.seek(SeekFrom::Start(self.start as u64))?;
+        let mut buffer = BufReader::new(self.file.try_clone()?);
+        let mut position = self.start as u64;
+        let mut values: Vec<Vec<u8>> = vec![Vec::new(); keys.len()];
+        for index in order {
+            let target = self.start as u64 + keys[index].location.offset;
+            // seeking relative keeps the buffer when the value is close by.
+            buffer.seek_relative(target as i64 - position as i64)?;
+            let (encoding, data, read) = read_encoded(
+                &mut buffer,
+                self.end().saturating_sub(target),
+                &keys[index].name,
+                self.encryption,
+                cipher.as_ref(),
+            )?;
+            position = target + read;
+            values[index] = compression::decode(encoding, data, &self.dictionaries)?;
+        }
+        Ok(values)
+    }
+
+    /// Appends many records to the end of the partition with a single write.
+    /// If the write fails, the partition is read again so it matches the file.
+    pub fn append_many(
+        &mut self,
+        records: Vec<(RecordKind, u64, u64, String, &[u8])>,
+    ) -> Result<Vec<VirtualKey>, DatabaseError> {
+        let position = (self.start + self.length) as u64;
+        let mut bloom_writes = Vec::new();
+        let mut data = Vec::new();
+        let mut keys = Vec::with_capacity(records.len());
+        for (kind, sequence, version, key_name, value) in records {
+            let pending = self.prepare_append(kind, sequence, version, key_name, value)?;
+            bloom_writes.extend(pending.bloom_writes.iter().copied());
+            data.extend_from_slice(&pending.record);
+            keys.push(self.finish_append(pending));
+        }
+
+        let written = (|| -> Result<(), DatabaseError> {
+            for (position, byte) in bloom_writes.iter() {
+                self.file.seek(SeekFrom::Start(*position))?;
+                self.file.write_all(&[*byte])?;
+            }
+            self.file.seek(SeekFrom::Start(position))?;
+            self.file.write_all(&data)?;
+            Ok(())
+        })();
+        if let Err(error) = written {
+            self.init()?;
+            return Err(error);
+        }
+        Ok(keys)
+    }
+
+    /// Appends a record to the end of the partition.
+    /// This is the only way data is written to a partition.
+    pub fn append(
+        &mut self,
+        kind: RecordKind,
+        sequence: u64,
+        version: u64,
+        key_name: String,
+        value: &[u8],
+    ) -> Result<VirtualKey, DatabaseError> {
+        let pending = self.prepare_append(kind, sequence, version, key_name, value)?;
+
+        // the filter is written before the record, so it never misses a key that was written.
+        for (position, byte) in pending.bloom_writes.iter() {
+            self.file.seek(SeekFrom::Start(*position))?;
+            self.file.write_all(&[*byte])?;
+        }
+        self.file.seek(SeekFrom::Start(pending.position))?;
+        self.file.write_all(&pending.record)?;
+
+        Ok(self.finish_append(pending))
+    }
+
+    /// Encodes a record and works out where it, and the bloom filter changes, are written.
+    /// Nothing is written to the partition file until the caller writes the pending append
+    /// and passes it to `finish_append`.
+    pub fn prepare_append(
+        &mut self,
+        kind: RecordKind,
+        sequence: u64,
+        version: u64,
+        key_name: String,
+        value: &[u8],
+    ) -> Result<PendingAppend, DatabaseError> {
+        let (encoding, stored) = self.encode_value(&key_name, value)?;
+        let (record, value_offset) = self.encode_stored(
+            kind,
+            sequence,
+            version,
+            &key_name,
+            encoding,
+            value.len(),
+            &stored,
+        )?;
+        let mut bloom_writes = Vec::new();
+        if kind == RecordKind::Set {
+            let bloom_key = self.bloom_key(&key_name)?;
+            for byte in self.bloom.insert_bytes(&bloom_key) {
+                bloom_writes.push((
+                    (self.bloom_start + BloomFilter::PREFIX_LEN + byte) as u64,
+                    self.bloom.byte(byte),
+                ));
+            }
+        }
+
+        let location = VirtualLocation {
+            id: self.id as u64,
+            offset: (self.length + value_offset) as u64,
+            index: self.records,
+        };
+        Ok(PendingAppend {
+            bloom_writes,
+            position: (self.start + self.length) as u64,
+            record,
+            key: VirtualKey {
+                name: key_name,
+                location,
+                length: value.len(),
+                sequence,
+                version,
+            },
+        })
+    }
+
+    /// Records that a pending append has been written to the partition file.
+    pub fn finish_append(&mut self, pending: PendingAppend) -> VirtualKey {
+        self.length += pending.record.len();
+        self.records += 1;
+        self.sequence = self.sequence.max(pending.key.sequence);
+        self.accessed(0);
+        pending.key
+    }
+
+    /// Compresses the value of a key the way the partition compresses new values.
+    fn encode_value<'a>(
+        &self,
+        key_name: &str,
+        value: &'a [u8],
+    ) -> Result<(Encoding, Cow<'a, [u8]>), DatabaseError> {
+        // dictionaries are never compressed with a dictionary, so they can always be read.
+        let dictionaries = match dictionary::dictionary_version(key_name) {
+            Some(_) => None,
+            None => Some(&self.dictionaries),
+        };
+        self.compression.encode(value, dictionaries)
+    }
+
+    /// Encodes a record with an encoded value of `size` bytes once decoded, and seals it as
+    /// far as the partition's encryption level requires.
+    /// Returns the record, and the offset its location points at within the record.
+    #[allow(clippy::too_many_arguments)]
+    fn encode_stored(
+        &self,
+        kind: RecordKind,
+        sequence: u64,
+        version: u64,
+        key_name: &str,
+        encoding: Encoding,
+        size: usize,
+        stored: &[u8],
+    ) -> Result<(Vec<u8>, usize), DatabaseError> {
+        if !self.name_fits(key_name) {
+            return Err(DatabaseError::InvalidKey(key_name.to_string()));
+        }
+        let (name, stored) = match (self.encryption, self.cipher()?) {
+            (EncryptionLevel::Full, Some(cipher)) => {
+                let record = encode_record(
+                    kind,
+                    sequence,
+                    version,
+                    key_name.as_bytes(),
+                    encoding,
+                    stored,
+                )?;
+                return Ok((encryption::seal_record(cipher, &record)?, 0));
+            }
+            (EncryptionLevel::Names, Some(cipher)) => (
+                Cow::Owned(encryption::seal_name(cipher, key_name)?),
+                Cow::Owned(encryption::seal_value(
+                    cipher,
+                    key_name,
+                    encoding,
+                    size as u64,
+                    stored,
+                )?),
+            ),
+            (_, Some(cipher)) => (
+                Cow::Borrowed(key_name.as_bytes()),
+                Cow::Owned(encryption::seal_value(
+                    cipher,
+                    key_name,
+                    encoding,
+                    size as u64,
+                    stored,
+                )?),
+            ),
+            (_, None) => (Cow::Borrowed(key_name.as_bytes()), Cow::Borrowed(stored)),
+        };
+        let record = encode_record(kind, sequence, version, &name, encoding, &stored)?;
+        Ok((record, Record::PREFIX_LEN + name.len()))
+    }
+
+    /// Sets the mode new values are compressed with, and records it in the preamble.
+    pub fn set_compression_mode(&mut self, mode: CompressionMode) -> Result<(), DatabaseError> {
+        self.file
+            .seek(SeekFrom::Start(Preamble::COMPRESSION_OFFSET))?;
+        self.file.write_u8(mode as u8)?;
+        self.file.sync_data()?;
+        self.compression.mode = mode;
+        Ok(())
+    }
+
+    /// Sets the zstd level new values are compressed at, and records it in the preamble.
+    pub fn set_compression_level(&mut self, level: i32) -> Result<(), DatabaseError> {
+        self.file.seek(SeekFrom::Start(Preamble::LEVEL_OFFSET))?;
+        self.file.write_i32::<BE>(level)?;
+        self.file.sync_data()?;
+        self.compression.level = level;
+        Ok(())
+    }
+
+    /// Records that values were read from, or written to, the partition.
+    fn accessed(&mut self, reads: u64) {
+        self.last_access = timestamp();
+        self.reads += reads;
+    }
+
+    /// Rewrites the partition without the records that are no longer visible.
+    /// Records newer than `horizon` (the oldest snapshot that is still alive) are kept,
+    /// so snapshots keep seeing the partition as it was when they were taken.
+    /// Records matching `discard` are dropped regardless of the horizon.
+    /// Values compressed by another mode than the partition's are recompressed.
+    /// Returns the amount of bytes reclaimed.
+    pub fn compact(
+        &mut self,
+        horizon: Option<u64>,
+        discard: &dyn Fn(&Record) -> bool,
+    ) -> Result<usize, DatabaseError> {
+        self.rewrite(horizon, discard, false)
+    }
+
+    /// Compacts the partition like `compact`, sealing the header and every value that is
+    /// kept again with the current master key, so nothing is left sealed with the previous key.
+    pub fn rotate(
+        &mut self,
+        horizon: Option<u64>,
+        discard: &dyn Fn(&Record) -> bool,
+    ) -> Result<usize, DatabaseError> {
+        self.rewrite(horizon, discard, true)
+    }
+
+    /// The records `compact` would drop, which nothing can read anymore.
+    pub(crate) fn reclaimable(
+        &mut self,
+        horizon: Option<u64>,
+        discard: &dyn Fn(&Record) -> bool,
+    ) -> Result<Vec<Record>, DatabaseError> {
+        let records = self.records()?;
+        let kept = kept_records(&records, horizon, discard);
+        Ok(records
+            .into_iter()
+            .zip(kept)
+            .filter(|(_, kept)| !kept)
+            .map(|(record, _)| record)
+            .collect())
+    }
+
+    fn rewrite(
+        &mut self,
+        horizon: Option<u64>,
+        discard: &dyn Fn(&Record) -> bool,
+        reseal: bool,
+    ) -> Result<usize, DatabaseError> {
+        let records = self.records()?;
+        let kept: Vec<&Record> = records
+            .iter()
+            .zip(kept_records(&records, horizon, discard))
+            .filter(|(_, kept)| *kept)
+            .map(|(record, _)| record)
+            .collect();
+
+        let compact_path = PathBuf::from(format!("{}.compact", self.get_path()));
+        let mut compacted = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&compact_path)?;
+
+        // the preamble and header are kept as is, the bloom filter is rebuilt
+        // and sized for the keys that are left.
+        let mut head = vec![0; self.bloom_start];
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_exact(&mut head)?;
+        match (reseal, self.cipher()?) {
+            (true, Some(cipher)) => {
+                let (preamble, sealed) = head.split_at(Preamble::new().byte_len());
+                compacted.write_all(preamble)?;
+                encryption::reseal_head(&mut Cursor::new(sealed), cipher, &mut compacted)?;
+            }
+            _ => compacted.write_all(&head)?,
+        }
+        let set: Vec<&&Record> = kept
+            .iter()
+            .filter(|record| record.kind == RecordKind::Set)
+            .collect();
+        let mut bloom = BloomFilter::with_capacity(set.len());
+        for record in set {
+            bloom.insert_bytes(&self.bloom_key(&record.name)?);
+        }
+        bloom.write(&mut compacted)?;
+
+        // sealed names are sealed again, so their tokens match the rebuilt bloom filter.
+        let copies_raw = !reseal && self.encryption < EncryptionLevel::Names;
+        let cipher = self.cipher()?.cloned();
+        for record in kept {
+            // compaction does not count as an access to the partition.
+            if copies_raw && self.compression.keeps(record.encoding) {
+                compacted.write_all(&read_raw(&mut self.file, self.start as u64, record)?)?;
+                continue;
+            }
+            self.file
+                .seek(SeekFrom::Start(self.start as u64 + record.location.offset))?;
+            let (mut encoding, mut encoded, _) = read_encoded(
+                &mut self.file,
+                self.length.saturating_sub(record.location.offset as usize) as u64,
+                &record.name,
+                self.encryption,
+                cipher.as_ref(),
+            )?;
+            if !self.compression.keeps(encoding) {
+                let value = compression::decode(encoding, encoded, &self.dictionaries)?;
+                let (recompressed, data) = self.encode_value(&record.name, &value)?;
+                encoding = recompressed;
+                encoded = data.into_owned();
+            }
+            let (data, _) = self.encode_stored(
+                record.kind,
+                record.sequence,
+                record.version,
+                &record.name,
+                encoding,
+                record.length,
+                &encoded,
+            )?;
+            compacted.write_all(&data)?;
+        }
+        compacted.sync_all()?;
+        drop(compacted);
+
+        let before = self.length;
+        std::fs::rename(&compact_path, &self.path)?;
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.init()?;
+        Ok(before.saturating_sub(self.length))
+    }
+
+    /// Whether or not the key may be within the partition, according to the bloom filter.
+    /// `false` means the key is certainly not in the partition.
+    /// If key names are encrypted, the filter holds their tokens, which are only known with
+    /// the master key, so every key may be within the partition without it.
+    pub fn may_contain(&self, key_name: &str) -> bool {
+        match (
+            self.encryption >= EncryptionLevel::Names,
+            self.cipher.as_ref(),
+        ) {
+            (true, Some(cipher)) => cipher
+                .tokens(key_name)
+                .iter()
+                .any(|token| self.bloom.contains_bytes(token)),
+            (true, None) => true,
+            (false, _) => self.bloom.contains(key_name),
+        }
+    }
+
+    /// The bytes a key name is added to the bloom filter as.
+    fn bloom_key(&self, key_name: &str) -> Result<Vec<u8>, DatabaseError> {
+        match (self.encryption >= EncryptionLevel::Names, self.cipher()?) {
+            (true, Some(cipher)) => Ok(cipher.token(key_name).to_vec()),
+            _ => Ok(key_name.as_bytes().to_vec()),
+        }
+    }
+
+    /// Reads the records of a single key, in the order they were written.
+    /// Sealed names are matched by their token, so only the names of the key are opened.
+    pub(crate) fn records_of(&mut self, key_name: &str) -> Result<Vec<Record>, DatabaseError> {
+        let tokens = match (self.encryption, self.cipher()?) {
+            (EncryptionLevel::Names, Some(cipher)) => Some(cipher.tokens(key_name)),
+            _ => None,
+        };
+        self.file.seek(SeekFrom::Start(self.start as u64))?;
+        let mut buffer = BufReader::new(self.file.try_clone()?);
+        let (records, _) = read_records_with(
+            &mut buffer,
+            self.id,
+            self.length as u64,
+            self.encryption,
+            self.cipher()?,
+            tokens.as_deref(),
+        )?;
+        Ok(records
+            .into_iter()
+            .filter(|record| record.name == key_name)
+            .collect())
+    }
+
+    /// Finds the current state of a key within the partition.
+    pub fn find(&mut self, key_name: &str) -> Result<Option<VirtualKey>, DatabaseError> {
+        if !self.may_contain(key_name) {
+            return Ok(None);
+        }
+        let mut found: Option<VirtualKey> = None;
+        for record in self.records_of(key_name)? {
+            if record.name == key_name {
+                found = match record.kind {
+                    RecordKind::Set => Some(record.to_key()),
+                    RecordKind::Remove => None,
+                };
+            }
+        }
+        Ok(found)
+    }
 }
 
 impl InternalApi for Partition {
@@ -139,43 +1372,69 @@ impl InternalApi for Partition {
         // get the keys from the partition
         // we're assuming that the virtual database hasn't cached the address of this key.
         // we're also assuming that the virtual database hasn't cached the data of this key.
-        let keys = self.fetch_keys()?;
-        let key = keys
-            .iter()
-            .find(|key| key.name == key_name)
-            .ok_or(DatabaseError::KeyNotFound(key_name))?;
+        let key = self
+            .find(&key_name)?
+            .ok_or_else(|| DatabaseError::KeyNotFound(key_name.clone()))?;
         let location = key.location;
 
-        drop(keys);
         // we have the location now we need to get it's data
         // we're going to rely on offset for this.
-        let mut buffer = BufReader::new(self.file.try_clone()?);
-        buffer.seek_relative(location.offset as i64)?;
-        let size = buffer.read_u128::<BE>()?;
-        let mut data: Vec<u8> = Vec::with_capacity(size as usize);
-        buffer.read_exact(&mut data)?;
+        let data = self.read_at(&key)?;
         Ok(VirtualItem {
             key: key_name,
             location,
+            length: data.len(),
+            version: key.version,
+            data,
+        })
+    }
+
+    fn get_range(
+        &mut self,
+        key_name: String,
+        offset: u64,
+        length: u64,
+    ) -> Result<Self::ValueKind, DatabaseError> {
+        let key = self
+            .find(&key_name)?
+            .ok_or_else(|| DatabaseError::KeyNotFound(key_name.clone()))?;
+        let (data, size) = self.read_range(&key, offset, length)?;
+        Ok(VirtualItem {
+            key: key_name,
+            location: key.location,
             length: size as usize,
+            version: key.version,
             data,
         })
     }
 
     fn set(&mut self, key_name: String, value: Vec<u8>) -> Result<Self::KeyKind, DatabaseError> {
-
+        let sequence = self.sequence + 1;
+        let version = next_version(self.find(&key_name)?.as_ref());
+        self.append(RecordKind::Set, sequence, version, key_name, &value)
     }
 
-    fn add(&mut self, key_name: String, value: Vec<u8>) -> Result<Self::KeyKind, DatabaseError> {}
+    fn add(&mut self, key_name: String, value: Vec<u8>) -> Result<Self::KeyKind, DatabaseError> {
+        if self.find(&key_name)?.is_some() {
+            return Err(DatabaseError::KeyAlreadyExists(key_name));
+        }
+        self.set(key_name, value)
+    }
 
-    fn remove(&mut self, key_name: String) -> Result<bool, DatabaseError> {}
+    fn remove(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        let existing = match self.find(&key_name)? {
+            Some(existing) => existing,
+            None => return Ok(false),
+        };
+        let sequence = self.sequence + 1;
+        let version = next_version(Some(&existing));
+        self.append(RecordKind::Remove, sequence, version, key_name, &[])?;
+        Ok(true)
+    }
 
     /// This operation will reset the handle held on the partition.
     fn fetch_keys(&mut self) -> Result<Vec<Self::KeyKind>, DatabaseError> {
-        let mut buffer = BufReader::new(self.file.try_clone()?);
-
-        // we are now reading the length of keys.
-        let len = buffer.read_u128::<BE>()?;
+        Ok(latest_keys(self.records()?))
     }
 }
 
@@ -187,25 +1446,713 @@ pub struct VirtualDatabase {
     /// The parts to the virtual database.
     pub parts: Vec<Partition>,
     /// The keys to the virtual database.
-    pub keys: Vec<VirtualKey>,
+    /// This is a cache of the keys that have been used since the database was opened.
+    pub keys: KeyCache,
+    /// The highest sequence number given to a mutation.
+    pub sequence: u64,
+    /// The subscribers to the change feed.
+    subscribers: Subscribers,
+    /// The snapshots that are still alive.
+    pub(crate) snapshots: SnapshotRegistry,
+    /// The namespaces within the database, by name, with their ids.
+    pub(crate) namespaces: HashMap<String, u64>,
+    /// The ids of dropped namespaces whose drop is still stored, with the sequence number
+    /// of the drop. See `VirtualDatabase::dropped_namespaces`.
+    pub(crate) dropped: HashMap<u64, u64>,
+    /// The path of the database file.
+    pub(crate) path: PathBuf,
+    /// The storage tiers of the partitions, if configured.
+    pub(crate) tiers: Option<TierCatalog>,
+    /// The files of partitions that moved to another tier, but may still be read by snapshots.
+    pub(crate) retired: Vec<PathBuf>,
+    /// The zstd dictionaries small values are compressed with.
+    pub(crate) dictionaries: Dictionaries,
+    /// The master key, if the database is encrypted.
+    pub(crate) cipher: Option<Cipher>,
+    /// Whether or not removing a key overwrites its earlier values, see `crate::scrub`.
+    pub(crate) secure_delete: bool,
+    /// The highest sequence number of a record that compaction dropped, see `crate::replication`.
+    pub(crate) compacted: u64,
 }
 
 impl VirtualDatabase {
     /// Create a new virtual database.
-    pub fn new(header: Header, name: String, path: &Path) -> Self {
+    pub fn new(header: Header, name: String, path: &Path) -> Result<Self, DatabaseError> {
+        Self::open(header, name, path, true, None)
+    }
+
+    /// Opens the virtual database, with its partitions only opened for reading if not `writable`.
+    /// The `cipher` is the master key of an encrypted database.
+    pub fn open(
+        header: Header,
+        name: String,
+        path: &Path,
+        writable: bool,
+        cipher: Option<Cipher>,
+    ) -> Result<Self, DatabaseError> {
         let mut partitions: Vec<Partition> = Vec::new();
-        let mut keys: Vec<VirtualKey> = Vec::new();
+        let tiers = TierCatalog::load(path)?;
 
         // Load the partitions if any.
         if header.partitioned {
-            for i in 0..header.partitions.unwrap() {
-                partitions.push(Partition::new(path, name.clone(), i as u8));
+            for i in 0..header.partitions.unwrap_or(0) {
+                let partition_path = tiers
+                    .as_ref()
+                    .and_then(|catalog| catalog.partition_path(path, &name, i))
+                    .unwrap_or_else(|| Partition::path_for(path, &name, i));
+                partitions.push(Partition::open_with(partition_path, i, writable)?);
             }
+        } else {
+            // The database file itself holds the data.
+            partitions.push(Partition::open_with(path.to_path_buf(), 0, writable)?);
+        }
+
+        for partition in partitions.iter_mut() {
+            partition.cipher = cipher.clone();
+            partition.init()?;
         }
 
-        Self {
+        let sequence = partitions.iter().map(|p| p.sequence).max().unwrap_or(0);
+
+        let mut db = Self {
             parts: partitions,
-            keys: keys,
+            keys: KeyCache::default(),
+            sequence,
+            subscribers: Subscribers::new(),
+            snapshots: SnapshotRegistry::new(),
+            namespaces: HashMap::new(),
+            dropped: HashMap::new(),
+            path: path.to_path_buf(),
+            tiers,
+            retired: Vec::new(),
+            dictionaries: Dictionaries::default(),
+            cipher,
+            secure_delete: header.secure_delete,
+            compacted: replication::load_compacted(path)?,
+        };
+        for partition in db.parts.iter_mut() {
+            partition.dictionaries = db.dictionaries.clone();
+        }
+        db.load_dictionaries()?;
+        db.load_namespaces()?;
+
+        // Reading the namespaces is not a use of the partitions.
+        if let Some(catalog) = db.tiers.as_ref() {
+            for partition in db.parts.iter_mut() {
+                if let Some(placement) = catalog.placement(partition.id) {
+                    partition.last_access = placement.last_access;
+                }
+            }
+        }
+        Ok(db)
+    }
+
+    /// Reads the namespace catalog from the partitions, with the namespaces that were dropped.
+    fn load_namespaces(&mut self) -> Result<(), DatabaseError> {
+        self.namespaces.clear();
+        self.dropped.clear();
+        let mut catalog = Vec::new();
+        for (index, partition) in self.parts.iter_mut().enumerate() {
+            for record in partition.records()? {
+                if namespace::catalog_name(&record.name).is_some() {
+                    catalog.push((index, record));
+                }
+            }
+        }
+        catalog.sort_by_key(|(_, record)| record.sequence);
+        for (index, record) in catalog {
+            let name = namespace::catalog_name(&record.name)
+                .unwrap_or_default()
+                .to_string();
+            match record.kind {
+                RecordKind::Set => {
+                    let id = self.parts[index].read_at(&record.to_key())?;
+                    let id = Cursor::new(id).read_u64::<BE>()?;
+                    self.namespaces.insert(name, id);
+                }
+                RecordKind::Remove => {
+                    if let Some(id) = self.namespaces.remove(&name) {
+                        self.dropped.insert(id, record.sequence);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the key in the cache.
+    pub(crate) fn cached(&self, key_name: &str) -> Option<VirtualKey> {
+        self.keys.get(key_name)
+    }
+
+    /// Finds many keys at once, reading every partition at most once.
+    /// Keys that are not set are left out of the result.
+    pub(crate) fn locate_many(
+        &mut self,
+        key_names: &[String],
+    ) -> Result<HashMap<String, VirtualKey>, DatabaseError> {
+        let mut found: HashMap<String, VirtualKey> = HashMap::new();
+        let mut missing: HashSet<&str> = HashSet::new();
+        for key_name in key_names {
+            match self.cached(key_name) {
+                Some(key) => {
+                    found.insert(key_name.clone(), key);
+                }
+                None => {
+                    missing.insert(key_name);
+                }
+            }
+        }
+
+        for partition in self.parts.iter_mut() {
+            if !missing.iter().any(|name| partition.may_contain(name)) {
+                continue;
+            }
+            for key in partition.fetch_keys()? {
+                if missing.remove(key.name.as_str()) {
+                    self.keys.insert(key.clone());
+                    found.insert(key.name.clone(), key);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Finds the key in the cache, or in the partitions if it has not been used yet.
+    fn locate(&mut self, key_name: &str) -> Result<Option<VirtualKey>, DatabaseError> {
+        if let Some(key) = self.cached(key_name) {
+            return Ok(Some(key));
+        }
+
+        for partition in self.parts.iter_mut() {
+            if let Some(key) = partition.find(key_name)? {
+                self.keys.insert(key.clone());
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The partition that new keys are written to.
+    /// This is the smallest partition, to keep the partitions balanced.
+    fn active_partition(&self) -> Result<usize, DatabaseError> {
+        self.parts
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, partition)| partition.length)
+            .map(|(index, _)| index)
+            .ok_or_else(|| DatabaseError::Implementation("Database has no partitions".to_string()))
+    }
+
+    /// The position of the partition with the given id.
+    pub(crate) fn partition_index(&self, id: u64) -> Result<usize, DatabaseError> {
+        self.parts
+            .iter()
+            .position(|partition| partition.id as u64 == id)
+            .ok_or_else(|| DatabaseError::Implementation(format!("Partition {} is missing", id)))
+    }
+
+    /// The partition a record for the key is written to.
+    /// Keys stay in the partition they were created in.
+    pub(crate) fn target_partition(
+        &self,
+        existing: Option<&VirtualKey>,
+    ) -> Result<usize, DatabaseError> {
+        match existing {
+            Some(key) => self.partition_index(key.location.id),
+            None => self.active_partition(),
+        }
+    }
+
+    /// Writes a record for the key and notifies the change feed.
+    fn write(
+        &mut self,
+        kind: RecordKind,
+        key_name: String,
+        value: &[u8],
+        existing: Option<&VirtualKey>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        let index = self.target_partition(existing)?;
+        let key = self.parts[index].append(
+            kind,
+            self.sequence + 1,
+            next_version(existing),
+            key_name,
+            value,
+        )?;
+        self.finish_write(kind, &key);
+        if kind == RecordKind::Remove && self.secure_delete {
+            self.scrub_removed(index, &key)?;
+        }
+        Ok(key)
+    }
+
+    /// Updates the key cache and notifies the change feed once a record has been written.
+    pub(crate) fn finish_write(&mut self, kind: RecordKind, key: &VirtualKey) {
+        self.sequence = self.sequence.max(key.sequence);
+
+        match kind {
+            RecordKind::Set => self.keys.insert(key.clone()),
+            RecordKind::Remove => self.keys.remove(&key.name),
+        }
+
+        self.subscribers.notify(&Change {
+            sequence: key.sequence,
+            kind: match kind {
+                RecordKind::Set => ChangeKind::Set,
+                RecordKind::Remove => ChangeKind::Remove,
+            },
+            key: key.name.clone(),
+            length: key.length,
+        });
+    }
+
+    /// Every change with a sequence number greater than `sequence`, in order.
+    /// Passing `0` returns the full history that is still stored in the partitions.
+    pub fn changes_since(
+        &mut self,
+        sequence: u64,
+    ) -> Result<impl Iterator<Item = Change>, DatabaseError> {
+        let mut changes: Vec<Change> = Vec::new();
+        for partition in self.parts.iter_mut() {
+            changes.extend(
+                partition
+                    .records()?
+                    .iter()
+                    .filter(|record| record.sequence > sequence)
+                    .map(Record::to_change),
+            );
+        }
+        changes.sort_by_key(|change| change.sequence);
+        Ok(changes.into_iter())
+    }
+
+    /// Sets the master key of the database and every partition.
+    pub(crate) fn set_cipher(&mut self, cipher: Option<Cipher>) {
+        for partition in self.parts.iter_mut() {
+            partition.cipher = cipher.clone();
+        }
+        self.cipher = cipher;
+    }
+
+    /// Takes a read-only view of the database as it is right now.
+    /// Writes made after the snapshot was taken are not visible through it,
+    /// and compaction keeps the versions it needs until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(
+            self.sequence,
+            self.parts
+                .iter()
+                .map(|partition| {
+                    (
+                        partition.id,
+                        partition.path.clone(),
+                        partition.start as u64,
+                        partition.encryption,
+                    )
+                })
+                .collect(),
+            self.snapshots.pin(self.sequence),
+            self.dictionaries.clone(),
+            self.cipher.clone(),
+        )
+    }
+
+    /// Retains a sequence number, so compaction keeps every record after it until the pin is
+    /// dropped. See `SnapshotRegistry::retain`.
+    pub(crate) fn retain(&self, sequence: u64) -> SnapshotPin {
+        self.snapshots.retain(sequence)
+    }
+
+    /// Appends a record that was written by another database, keeping its sequence and version.
+    /// The value is given as it was stored by the other database.
+    /// Records must be applied in order, older records are rejected.
+    pub(crate) fn apply_record(
+        &mut self,
+        kind: RecordKind,
+        sequence: u64,
+        version: u64,
+        key_name: String,
+        encoding: Encoding,
+        stored: Vec<u8>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        if sequence <= self.sequence {
+            return Err(DatabaseError::Implementation(format!(
+                "Record {} is not newer than the database",
+                sequence
+            )));
+        }
+        let value = compression::decode(encoding, stored, &self.dictionaries)?;
+        let existing = self.locate(&key_name)?;
+        let index = self.target_partition(existing.as_ref())?;
+        let key = self.parts[index].append(kind, sequence, version, key_name, &value)?;
+        if let (Some(version), RecordKind::Set) = (dictionary::dictionary_version(&key.name), kind)
+        {
+            self.dictionaries.insert(version, value.clone());
         }
+        if let Some(name) = namespace::catalog_name(&key.name) {
+            match kind {
+                RecordKind::Set => {
+                    let id = Cursor::new(value).read_u64::<BE>()?;
+                    self.namespaces.insert(name.to_string(), id);
+                }
+                RecordKind::Remove => {
+                    if let Some(id) = self.namespaces.remove(name) {
+                        self.dropped.insert(id, key.sequence);
+                        self.keys.remove_prefix(&namespace::key_prefix(id));
+                    }
+                }
+            }
+        }
+        self.finish_write(kind, &key);
+        if kind == RecordKind::Remove && self.secure_delete {
+            self.scrub_removed(index, &key)?;
+        }
+        Ok(key)
+    }
+
+    /// Compacts every partition, dropping overwritten and removed values
+    /// that are not visible to any snapshot.
+    /// Returns the amount of bytes reclaimed.
+    pub fn compact(&mut self) -> Result<usize, DatabaseError> {
+        self.rewrite(0..self.parts.len(), false)
+    }
+
+    /// Compacts a single partition, by its index, sealing everything that is kept again with
+    /// the current master key. Returns the amount of bytes reclaimed.
+    pub fn rotate_partition(&mut self, index: usize) -> Result<usize, DatabaseError> {
+        self.rewrite(index..index + 1, true)
+    }
+
+    fn rewrite(&mut self, range: Range<usize>, reseal: bool) -> Result<usize, DatabaseError> {
+        let horizon = self.snapshots.horizon();
+        let dropped = self.dropped_namespaces();
+        let mut reclaimable = Vec::new();
+        for partition in self.parts[range.clone()].iter_mut() {
+            reclaimable.push(partition.reclaimable(horizon, &dropped)?);
+        }
+        self.dropping(reclaimable.iter().flatten())?;
+        let mut reclaimed = 0;
+        for (partition, records) in self.parts[range].iter_mut().zip(reclaimable) {
+            // the replaced file would otherwise keep the records that are dropped.
+            if self.secure_delete {
+                partition.scrub(&records)?;
+            }
+            reclaimed += match reseal {
+                true => partition.rotate(horizon, &dropped)?,
+                false => partition.compact(horizon, &dropped)?,
+            };
+        }
+        // the locations of the cached keys have moved.
+        self.keys.clear();
+        Ok(reclaimed)
+    }
+
+    /// Records the highest sequence number of the records that are about to be dropped,
+    /// before they are, so replicas that have not pulled them are told to resync.
+    pub(crate) fn dropping<'a>(
+        &mut self,
+        records: impl Iterator<Item = &'a Record>,
+    ) -> Result<(), DatabaseError> {
+        let highest = records.map(|record| record.sequence).max().unwrap_or(0);
+        if highest > self.compacted {
+            replication::save_compacted(&self.path, highest)?;
+            self.compacted = highest;
+        }
+        Ok(())
+    }
+
+    /// Whether or not a record belongs to a dropped namespace, that no snapshot can see.
+    /// Like a removed key, the keys of a dropped namespace are reclaimed once the horizon is
+    /// at or past the drop. A namespace whose drop was already reclaimed is not known at
+    /// all, and was dropped before the horizon of that compaction.
+    pub(crate) fn dropped_namespaces(&self) -> impl Fn(&Record) -> bool {
+        let horizon = self.snapshots.horizon().unwrap_or(u64::MAX);
+        let live: HashSet<u64> = self.namespaces.values().copied().collect();
+        let dropped = self.dropped.clone();
+        move |record: &Record| match namespace::split_key(&record.name) {
+            Some((id, _)) if !live.contains(&id) => {
+                dropped.get(&id).is_none_or(|sequence| *sequence <= horizon)
+            }
+            _ => false,
+        }
+    }
+
+    /// Sets the key only if it is at `expected_version`, where `0` expects it to not be set.
+    /// Otherwise nothing is written and `DatabaseError::VersionConflict` is returned.
+    pub fn compare_and_set(
+        &mut self,
+        key_name: String,
+        expected_version: u64,
+        value: Vec<u8>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        if namespace::is_reserved(&key_name) {
+            return Err(DatabaseError::InvalidKey(key_name));
+        }
+        let existing = self.locate(&key_name)?;
+        let version = existing.as_ref().map_or(0, |key| key.version);
+        if version != expected_version {
+            return Err(DatabaseError::VersionConflict(key_name, version));
+        }
+        self.write(RecordKind::Set, key_name, &value, existing.as_ref())
+    }
+
+    /// Removes the key only if it is at `expected_version`.
+    /// Returns whether the key was removed, a key that is not set is only removed
+    /// when `0` is expected (which removes nothing).
+    pub fn compare_and_remove(
+        &mut self,
+        key_name: String,
+        expected_version: u64,
+    ) -> Result<bool, DatabaseError> {
+        let existing = match namespace::is_reserved(&key_name) {
+            true => None,
+            false => self.locate(&key_name)?,
+        };
+        let version = existing.as_ref().map_or(0, |key| key.version);
+        if version != expected_version {
+            return Err(DatabaseError::VersionConflict(key_name, version));
+        }
+        match existing {
+            Some(existing) => {
+                self.write(RecordKind::Remove, key_name, &[], Some(&existing))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Gets a key by the name it is stored under, including reserved names.
+    pub(crate) fn get_raw(&mut self, key_name: String) -> Result<VirtualItem, DatabaseError> {
+        let key = self
+            .locate(&key_name)?
+            .ok_or_else(|| DatabaseError::KeyNotFound(key_name.clone()))?;
+        let index = self.partition_index(key.location.id)?;
+        let data = self.parts[index].read_at(&key)?;
+        Ok(VirtualItem {
+            key: key_name,
+            location: key.location,
+            length: data.len(),
+            version: key.version,
+            data,
+        })
+    }
+
+    pub(crate) fn set_raw(
+        &mut self,
+        key_name: String,
+        value: &[u8],
+    ) -> Result<VirtualKey, DatabaseError> {
+        let existing = self.locate(&key_name)?;
+        self.write(RecordKind::Set, key_name, value, existing.as_ref())
+    }
+
+    fn add_raw(&mut self, key_name: String, value: &[u8]) -> Result<VirtualKey, DatabaseError> {
+        if self.locate(&key_name)?.is_some() {
+            return Err(DatabaseError::KeyAlreadyExists(key_name));
+        }
+        self.write(RecordKind::Set, key_name, value, None)
+    }
+
+    pub(crate) fn remove_raw(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        match self.locate(&key_name)? {
+            Some(existing) => {
+                self.write(RecordKind::Remove, key_name, &[], Some(&existing))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Every key that is set, by the name it is stored under.
+    pub(crate) fn fetch_raw_keys(&mut self) -> Result<Vec<VirtualKey>, DatabaseError> {
+        let mut keys: Vec<VirtualKey> = Vec::new();
+        for partition in self.parts.iter_mut() {
+            keys.extend(partition.fetch_keys()?);
+        }
+        Ok(keys)
+    }
+
+    /// Creates a namespace, returning its id.
+    pub fn create_namespace(&mut self, name: String) -> Result<u64, DatabaseError> {
+        if !namespace::is_valid_name(&name) {
+            return Err(DatabaseError::InvalidKey(name));
+        }
+        if self.namespaces.contains_key(&name) {
+            return Err(DatabaseError::KeyAlreadyExists(name));
+        }
+        // the id is the sequence number of the catalog entry, so it is never reused.
+        let id = self.sequence + 1;
+        self.add_raw(namespace::catalog_key(&name), &id.to_be_bytes())?;
+        self.namespaces.insert(name, id);
+        Ok(id)
+    }
+
+    /// Drops a namespace and every key within it.
+    /// This only writes a single record, the keys are reclaimed by compaction.
+    /// Returns whether or not the namespace existed.
+    pub fn drop_namespace(&mut self, name: String) -> Result<bool, DatabaseError> {
+        let id = match self.namespaces.remove(&name) {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        self.remove_raw(namespace::catalog_key(&name))?;
+        self.dropped.insert(id, self.sequence);
+        self.keys.remove_prefix(&namespace::key_prefix(id));
+        Ok(true)
+    }
+
+    /// The names of every namespace, sorted.
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.namespaces.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The id of the namespace with the given name.
+    fn namespace_id(&self, name: &str) -> Result<u64, DatabaseError> {
+        self.namespaces
+            .get(name)
+            .copied()
+            .ok_or_else(|| DatabaseError::NamespaceNotFound(name.to_string()))
+    }
+
+    /// Gets a key within a namespace.
+    pub fn get_in(
+        &mut self,
+        namespace: &str,
+        key_name: String,
+    ) -> Result<VirtualItem, DatabaseError> {
+        let id = self.namespace_id(namespace)?;
+        let mut item = self
+            .get_raw(namespace::namespaced_key(id, &key_name))
+            .map_err(|error| match error {
+                DatabaseError::KeyNotFound(_) => DatabaseError::KeyNotFound(key_name.clone()),
+                error => error,
+            })?;
+        item.key = key_name;
+        Ok(item)
+    }
+
+    /// Sets a key within a namespace.
+    pub fn set_in(
+        &mut self,
+        namespace: &str,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        let id = self.namespace_id(namespace)?;
+        let mut key = self.set_raw(namespace::namespaced_key(id, &key_name), &value)?;
+        key.name = key_name;
+        Ok(key)
+    }
+
+    /// Adds a key within a namespace, if it does not already exist.
+    pub fn add_in(
+        &mut self,
+        namespace: &str,
+        key_name: String,
+        value: Vec<u8>,
+    ) -> Result<VirtualKey, DatabaseError> {
+        let id = self.namespace_id(namespace)?;
+        let mut key = self
+            .add_raw(namespace::namespaced_key(id, &key_name), &value)
+            .map_err(|error| match error {
+                DatabaseError::KeyAlreadyExists(_) => {
+                    DatabaseError::KeyAlreadyExists(key_name.clone())
+                }
+                error => error,
+            })?;
+        key.name = key_name;
+        Ok(key)
+    }
+
+    /// Removes a key within a namespace.
+    pub fn remove_in(&mut self, namespace: &str, key_name: String) -> Result<bool, DatabaseError> {
+        let id = self.namespace_id(namespace)?;
+        self.remove_raw(namespace::namespaced_key(id, &key_name))
+    }
+
+    /// Every key within a namespace, by the name it was set with.
+    pub fn fetch_keys_in(&mut self, namespace: &str) -> Result<Vec<VirtualKey>, DatabaseError> {
+        let prefix = namespace::key_prefix(self.namespace_id(namespace)?);
+        Ok(self
+            .fetch_raw_keys()?
+            .into_iter()
+            .filter_map(|mut key| {
+                key.name = key.name.strip_prefix(&prefix)?.to_string();
+                Some(key)
+            })
+            .collect())
+    }
+
+    /// Subscribes to every future change on a key starting with `prefix`.
+    /// The channel is closed when the database is dropped.
+    pub fn subscribe(&mut self, prefix: String) -> Receiver<Change> {
+        self.subscribers.subscribe(prefix)
+    }
+}
+
+impl InternalApi for VirtualDatabase {
+    type KeyKind = VirtualKey;
+    type ValueKind = VirtualItem;
+
+    fn get(&mut self, key_name: String) -> Result<Self::ValueKind, DatabaseError> {
+        if namespace::is_reserved(&key_name) {
+            return Err(DatabaseError::KeyNotFound(key_name));
+        }
+        self.get_raw(key_name)
+    }
+
+    fn get_range(
+        &mut self,
+        key_name: String,
+        offset: u64,
+        length: u64,
+    ) -> Result<Self::ValueKind, DatabaseError> {
+        let key = match namespace::is_reserved(&key_name) {
+            true => None,
+            false => self.locate(&key_name)?,
+        }
+        .ok_or_else(|| DatabaseError::KeyNotFound(key_name.clone()))?;
+        let index = self.partition_index(key.location.id)?;
+        let (data, size) = self.parts[index].read_range(&key, offset, length)?;
+        Ok(VirtualItem {
+            key: key_name,
+            location: key.location,
+            length: size as usize,
+            version: key.version,
+            data,
+        })
+    }
+
+    fn set(&mut self, key_name: String, value: Vec<u8>) -> Result<Self::KeyKind, DatabaseError> {
+        if namespace::is_reserved(&key_name) {
+            return Err(DatabaseError::InvalidKey(key_name));
+        }
+        self.set_raw(key_name, &value)
+    }
+
+    fn add(&mut self, key_name: String, value: Vec<u8>) -> Result<Self::KeyKind, DatabaseError> {
+        if namespace::is_reserved(&key_name) {
+            return Err(DatabaseError::InvalidKey(key_name));
+        }
+        self.add_raw(key_name, &value)
+    }
+
+    fn remove(&mut self, key_name: String) -> Result<bool, DatabaseError> {
+        if namespace::is_reserved(&key_name) {
+            return Ok(false);
+        }
+        self.remove_raw(key_name)
+    }
+
+    /// Keys within namespaces are not included, see `fetch_keys_in`.
+    fn fetch_keys(&mut self) -> Result<Vec<Self::KeyKind>, DatabaseError> {
+        Ok(self
+            .fetch_raw_keys()?
+            .into_iter()
+            .filter(|key| !namespace::is_reserved(&key.name))
+            .collect())
     }
 }
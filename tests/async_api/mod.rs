@@ -0,0 +1,146 @@
+use onelink_database::db::Database;
+use onelink_database::preamble::{EncryptionLevel, Preamble};
+use onelink_database::utils::{AsyncInternalApi, AsyncSharedApi};
+use onelink_database::DatabaseError;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::common::{create_db, noise, test_dir};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+pub async fn test_concurrent_requests_share_one_database() {
+    let (db, _dir) = create_db("async_shared", 2);
+    let shared = Arc::new(RwLock::new(db));
+
+    let mut tasks = Vec::new();
+    for i in 0..16u8 {
+        let mut db = shared.clone();
+        tasks.push(tokio::spawn(async move {
+            db.set(format!("key-{}", i), vec![i; 32]).await.unwrap();
+            assert_eq!(
+                db.get(format!("key-{}", i)).await.unwrap().data,
+                vec![i; 32]
+            );
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut db = shared.clone();
+    assert_eq!(db.fetch_keys().await.unwrap().len(), 16);
+    assert!(db.add("key-3".to_string(), vec![]).await.is_err());
+    assert!(db.remove("key-3".to_string()).await.unwrap());
+    assert!(!db.remove("key-3".to_string()).await.unwrap());
+    assert_eq!(shared.write().await.sequence().unwrap(), 17);
+
+    // reads share the lock, so a read is served while another one holds it.
+    let held = shared.read().await;
+    let mut reader = shared.clone();
+    let read = tokio::time::timeout(Duration::from_secs(5), reader.get("key-4".to_string()))
+        .await
+        .expect("a read waited for another read");
+    assert_eq!(read.unwrap().data, vec![4; 32]);
+    assert_eq!(
+        held.get_shared("key-5".to_string()).await.unwrap().data,
+        vec![5; 32]
+    );
+}
+
+#[tokio::test]
+pub async fn test_async_access_checks() {
+    let (mut db, _dir) = create_db("async_read_only", 0);
+    AsyncInternalApi::set(&mut db, "a".to_string(), vec![1])
+        .await
+        .unwrap();
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+
+    let mut db = Database::open_read_only(name.clone(), path.clone()).unwrap();
+    assert_eq!(
+        AsyncInternalApi::get(&mut db, "a".to_string())
+            .await
+            .unwrap()
+            .data,
+        vec![1]
+    );
+    assert!(matches!(
+        AsyncInternalApi::set(&mut db, "b".to_string(), vec![2]).await,
+        Err(DatabaseError::ReadOnly)
+    ));
+    // the read-only handle still holds the lock of the database.
+    assert!(matches!(
+        Database::open(name, path),
+        Err(DatabaseError::Locked)
+    ));
+}
+
+#[tokio::test]
+pub async fn test_async_encrypted_database() {
+    let dir = test_dir("async_encrypted");
+    let path = dir.path().join("async_encrypted.onelink");
+    let path = path.to_str().unwrap().to_string();
+    let mut preamble = Preamble::new();
+    preamble.encryption = EncryptionLevel::Full;
+    let db = Database::create_with_password(
+        "async_encrypted".to_string(),
+        path.clone(),
+        preamble,
+        2,
+        "hunter2",
+    )
+    .unwrap();
+
+    let mut shared = Arc::new(RwLock::new(db));
+    shared
+        .set("a.txt".to_string(), b"sealed".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(
+        shared.get("a.txt".to_string()).await.unwrap().data,
+        b"sealed"
+    );
+    assert_eq!(shared.fetch_keys().await.unwrap().len(), 1);
+    drop(shared);
+
+    let mut db =
+        Database::open_with_password("async_encrypted".to_string(), path, "hunter2").unwrap();
+    assert_eq!(
+        AsyncInternalApi::get(&mut db, "a.txt".to_string())
+            .await
+            .unwrap()
+            .data,
+        b"sealed"
+    );
+}
+
+#[tokio::test]
+pub async fn test_secure_remove_without_blocking() {
+    let (mut db, _dir) = create_db("async_secure_remove", 0);
+    let path = db.get_path();
+    db.set_secure_delete(true).unwrap();
+    let secret = noise(2048);
+    AsyncInternalApi::set(&mut db, "secret".to_string(), secret.clone())
+        .await
+        .unwrap();
+    AsyncInternalApi::set(&mut db, "kept".to_string(), vec![7; 16])
+        .await
+        .unwrap();
+    assert!(AsyncInternalApi::remove(&mut db, "secret".to_string())
+        .await
+        .unwrap());
+
+    let data = std::fs::read(&path).unwrap();
+    assert!(!data.windows(secret.len()).any(|window| window == secret));
+    assert!(AsyncInternalApi::get(&mut db, "secret".to_string())
+        .await
+        .is_err());
+    assert_eq!(
+        AsyncInternalApi::get(&mut db, "kept".to_string())
+            .await
+            .unwrap()
+            .data,
+        vec![7; 16]
+    );
+}
@@ -0,0 +1,95 @@
+use onelink_database::backup;
+use onelink_database::db::Database;
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::common::{create_db, noise, test_dir};
+
+#[test]
+pub fn test_backup_and_restore() {
+    let (mut db, _dir) = create_db("backup_restore", 3);
+    for i in 0..20 {
+        db.set(format!("file-{}", i), vec![i as u8; 100 + i])
+            .unwrap();
+    }
+    db.remove("file-3".to_string()).unwrap();
+
+    let dir = test_dir("backup_restore_out");
+    let archive = dir.path().join("backup.olb").to_str().unwrap().to_string();
+    let summary = db.backup_to(archive.clone()).unwrap();
+    assert_eq!(summary.keys, 19);
+    assert_eq!(summary.sequence, db.sequence().unwrap());
+
+    // writes after the backup are not in the archive.
+    db.set("file-99".to_string(), vec![1]).unwrap();
+
+    let restored_path = dir
+        .path()
+        .join("restored.onelink")
+        .to_str()
+        .unwrap()
+        .to_string();
+    let mut restored = Database::restore_from(archive, restored_path).unwrap();
+    assert_eq!(restored.get_name(), "backup_restore");
+    assert_eq!(restored.fetch_keys().unwrap().len(), 19);
+    assert_eq!(
+        restored.get("file-7".to_string()).unwrap().data,
+        vec![7; 107]
+    );
+    assert!(restored.get("file-99".to_string()).is_err());
+    assert_eq!(restored.sequence().unwrap(), summary.sequence);
+}
+
+#[test]
+pub fn test_restore_rejects_corrupt_archive() {
+    let (mut db, _dir) = create_db("backup_corrupt", 0);
+    db.set("a".to_string(), vec![5; 64]).unwrap();
+
+    let dir = test_dir("backup_corrupt_out");
+    let archive = dir.path().join("backup.olb");
+    db.backup_to(archive.to_str().unwrap().to_string()).unwrap();
+
+    let mut file = OpenOptions::new().write(true).open(&archive).unwrap();
+    file.seek(SeekFrom::Start(120)).unwrap();
+    file.write_all(&[0xff]).unwrap();
+    drop(file);
+
+    let restored = dir.path().join("restored.onelink");
+    assert!(matches!(
+        Database::restore_from(
+            archive.to_str().unwrap().to_string(),
+            restored.to_str().unwrap().to_string()
+        ),
+        Err(DatabaseError::BackupInvalid(_))
+    ));
+    assert!(!restored.exists());
+}
+
+#[test]
+pub fn test_restore_verifies_every_key() {
+    let (mut db, _dir) = create_db("backup_verify", 0);
+    let value = noise(64);
+    db.set("a".to_string(), vec![5; 64]).unwrap();
+    db.set("b".to_string(), value.clone()).unwrap();
+
+    let dir = test_dir("backup_verify_out");
+    let archive = dir.path().join("backup.olb");
+    db.backup_to(archive.to_str().unwrap().to_string()).unwrap();
+
+    let restored = dir.path().join("restored.onelink");
+    let (name, header, _, summary, digests) =
+        backup::restore_archive(&archive, &restored, None).unwrap();
+    backup::verify_restore(&restored, &name, &header, None, &summary, &digests).unwrap();
+
+    // a value that changed after it was restored has the same counts, but not the same hash.
+    let mut data = fs::read(&restored).unwrap();
+    let at = data.windows(64).position(|w| w == value).unwrap();
+    data[at] ^= 0xff;
+    fs::write(&restored, data).unwrap();
+    assert!(matches!(
+        backup::verify_restore(&restored, &name, &header, None, &summary, &digests),
+        Err(DatabaseError::BackupInvalid(_))
+    ));
+}
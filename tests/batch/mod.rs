@@ -0,0 +1,83 @@
+use onelink_database::db::Database;
+use onelink_database::preamble::{EncryptionLevel, Preamble};
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+
+use crate::common::{create_db, test_dir};
+
+#[test]
+pub fn test_set_many_get_many() {
+    let (mut db, _dir) = create_db("batch_set_get", 3);
+    db.set("existing.txt".to_string(), b"old".to_vec()).unwrap();
+
+    let pairs: Vec<(String, Vec<u8>)> = (0..50)
+        .map(|i| (format!("key-{}", i), format!("value-{}", i).into_bytes()))
+        .chain(vec![
+            ("existing.txt".to_string(), b"new".to_vec()),
+            ("\0reserved".to_string(), b"nope".to_vec()),
+            ("key-0".to_string(), b"twice".to_vec()),
+        ])
+        .collect();
+    let results = db.set_many(pairs).unwrap();
+    assert_eq!(results.len(), 53);
+    assert!(matches!(results[51], Err(DatabaseError::InvalidKey(_))));
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 52);
+    assert_eq!(db.sequence().unwrap(), 53);
+
+    let mut names: Vec<String> = (0..50).rev().map(|i| format!("key-{}", i)).collect();
+    names.push("missing.txt".to_string());
+    names.push("existing.txt".to_string());
+    let items = db.get_many(names).unwrap();
+    assert_eq!(items.len(), 52);
+    assert_eq!(items[0].as_ref().unwrap().data, b"value-49");
+    assert_eq!(items[49].as_ref().unwrap().data, b"twice");
+    assert!(matches!(items[50], Err(DatabaseError::KeyNotFound(_))));
+    assert_eq!(items[51].as_ref().unwrap().data, b"new");
+}
+
+#[test]
+pub fn test_set_many_survives_reopen() {
+    let (mut db, _dir) = create_db("batch_reopen", 2);
+    let pairs: Vec<(String, Vec<u8>)> = (0..20)
+        .map(|i| (format!("key-{}", i), vec![i as u8; 64]))
+        .collect();
+    db.set_many(pairs).unwrap();
+    let path = db.get_path();
+    db.close().unwrap();
+
+    let mut db = Database::open("batch_reopen".to_string(), path).unwrap();
+    assert_eq!(db.fetch_keys().unwrap().len(), 20);
+    let names: Vec<String> = (0..20).map(|i| format!("key-{}", i)).collect();
+    for (i, item) in db.get_many(names).unwrap().into_iter().enumerate() {
+        assert_eq!(item.unwrap().data, vec![i as u8; 64]);
+    }
+}
+
+#[test]
+pub fn test_set_many_rejects_names_too_long_once_sealed() {
+    let dir = test_dir("batch_sealed_names");
+    let path = dir.path().join("batch_sealed_names.onelink");
+    let mut preamble = Preamble::new();
+    preamble.encryption = EncryptionLevel::Names;
+    let mut db = Database::create_with_password(
+        "batch_sealed_names".to_string(),
+        path.to_str().unwrap().to_string(),
+        preamble,
+        0,
+        "hunter2",
+    )
+    .unwrap();
+
+    // the name fits as it is, but not once it is sealed.
+    let long = "k".repeat(u16::MAX as usize - 8);
+    let results = db
+        .set_many(vec![
+            ("short".to_string(), b"a".to_vec()),
+            (long.clone(), b"b".to_vec()),
+        ])
+        .unwrap();
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1], Err(DatabaseError::InvalidKey(_))));
+    assert_eq!(db.get("short".to_string()).unwrap().data, b"a");
+    assert!(db.get(long).is_err());
+}
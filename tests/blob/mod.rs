@@ -0,0 +1,93 @@
+use onelink_database::blob;
+use onelink_database::db::Database;
+use onelink_database::preamble::Preamble;
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+use std::fs;
+use std::io::{BufReader, Cursor};
+
+use crate::common::{create_db, noise, test_dir};
+
+#[test]
+pub fn test_blobs_are_deduplicated() {
+    let (mut db, _dir) = create_db("blob_dedup", 0);
+    let path = db.get_path();
+    let content = noise(64 * 1024);
+
+    let first = db.set_blob("a.bin".to_string(), content.clone()).unwrap();
+    assert_eq!(first.hash, blob::hash(&content));
+    assert_eq!(first.references, 1);
+    let size = fs::metadata(&path).unwrap().len();
+
+    let second = db.set_blob("b.bin".to_string(), content.clone()).unwrap();
+    assert_eq!(second.references, 2);
+    // only the link and the reference count are written for the second key.
+    assert!(fs::metadata(&path).unwrap().len() - size < 1024);
+    assert_eq!(db.get_blob("b.bin".to_string()).unwrap().data, content);
+    assert_eq!(
+        db.fetch_blob_keys().unwrap(),
+        vec!["a.bin".to_string(), "b.bin".to_string()]
+    );
+    // blob keys are not part of the flat key space.
+    assert!(db.fetch_keys().unwrap().is_empty());
+
+    assert!(db.remove_blob("a.bin".to_string()).unwrap());
+    assert_eq!(db.blob_references(&first.hash).unwrap(), 1);
+    assert_eq!(db.get_blob("b.bin".to_string()).unwrap().data, content);
+
+    assert!(db.remove_blob("b.bin".to_string()).unwrap());
+    assert_eq!(db.blob_references(&first.hash).unwrap(), 0);
+    assert!(matches!(
+        db.get_blob("b.bin".to_string()),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+    // the blob is reclaimed with its last reference.
+    assert!(db.compact().unwrap() > content.len());
+}
+
+#[test]
+pub fn test_blob_overwrite_releases_previous() {
+    let (mut db, _dir) = create_db("blob_overwrite", 2);
+    let old = db.set_blob("a.bin".to_string(), b"old".to_vec()).unwrap();
+    db.set_blob("b.bin".to_string(), b"old".to_vec()).unwrap();
+    let new = db.set_blob("a.bin".to_string(), b"new".to_vec()).unwrap();
+
+    assert_eq!(db.blob_references(&old.hash).unwrap(), 1);
+    assert_eq!(db.blob_references(&new.hash).unwrap(), 1);
+    assert_eq!(db.get_blob("a.bin".to_string()).unwrap().data, b"new");
+    assert_eq!(db.get_blob("b.bin".to_string()).unwrap().data, b"old");
+
+    // setting the same content again does not add a reference.
+    let again = db.set_blob("a.bin".to_string(), b"new".to_vec()).unwrap();
+    assert_eq!(again.references, 1);
+}
+
+#[test]
+pub fn test_blobs_are_exported() {
+    let (mut db, _dir) = create_db("blob_export", 0);
+    db.set_blob("a.bin".to_string(), b"shared".to_vec())
+        .unwrap();
+    db.set_blob("b.bin".to_string(), b"shared".to_vec())
+        .unwrap();
+    db.set("plain".to_string(), b"plain".to_vec()).unwrap();
+
+    let mut exported = Vec::new();
+    assert_eq!(db.export_to(&mut exported).unwrap(), 3);
+
+    let dir = test_dir("blob_export_out");
+    let path = dir.path().join("imported.onelink");
+    let mut imported = Database::import_from(
+        &mut BufReader::new(Cursor::new(exported)),
+        "imported".to_string(),
+        path.to_str().unwrap().to_string(),
+        Preamble::new_unsafe(),
+        0,
+    )
+    .unwrap();
+    assert_eq!(
+        imported.get_blob("a.bin".to_string()).unwrap().data,
+        b"shared"
+    );
+    assert_eq!(imported.blob_references(&blob::hash(b"shared")).unwrap(), 2);
+    assert_eq!(imported.get("plain".to_string()).unwrap().data, b"plain");
+}
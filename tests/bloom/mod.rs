@@ -0,0 +1,68 @@
+use onelink_database::bloom::BloomFilter;
+use onelink_database::db::Database;
+use onelink_database::utils::InternalApi;
+use onelink_database::virtual_db::Partition;
+use std::path::Path;
+
+use crate::common::create_db;
+
+#[test]
+pub fn test_bloom_filter_has_no_false_negatives() {
+    let mut bloom = BloomFilter::with_capacity(1000);
+    for i in 0..1000 {
+        bloom.insert(&format!("key-{}", i));
+    }
+    assert!((0..1000).all(|i| bloom.contains(&format!("key-{}", i))));
+    let false_positives = (0..1000)
+        .filter(|i| bloom.contains(&format!("missing-{}", i)))
+        .count();
+    assert!(false_positives < 50);
+}
+
+#[test]
+pub fn test_partition_bloom_is_persisted() {
+    let (mut db, _dir) = create_db("bloom_persisted", 2);
+    for i in 0..10 {
+        db.set(format!("key-{}", i), vec![i]).unwrap();
+    }
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+
+    // every key is in the filter of the partition that holds it, and only there.
+    for id in 0..2 {
+        let mut partition = Partition::new(Path::new(&path), name.clone(), id).unwrap();
+        partition.init().unwrap();
+        for key in partition.fetch_keys().unwrap() {
+            assert!(partition.may_contain(&key.name));
+        }
+        assert!(!partition.may_contain("not-a-key"));
+    }
+
+    let mut db = Database::open(name, path).unwrap();
+    assert_eq!(db.get("key-4".to_string()).unwrap().data, vec![4]);
+    assert!(db.get("not-a-key".to_string()).is_err());
+}
+
+#[test]
+pub fn test_bloom_is_rebuilt_on_compaction() {
+    let (mut db, _dir) = create_db("bloom_compaction", 0);
+    for i in 0..1000 {
+        db.set(format!("key-{}", i), vec![]).unwrap();
+    }
+    for i in 0..1000 {
+        if i % 10 != 0 {
+            db.remove(format!("key-{}", i)).unwrap();
+        }
+    }
+    db.compact().unwrap();
+
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    let mut partition = Partition::open(Path::new(&path).to_path_buf(), 0).unwrap();
+    partition.init().unwrap();
+    assert!(!partition.may_contain("key-1"));
+    assert!(partition.may_contain("key-10"));
+
+    let mut db = Database::open(name, path).unwrap();
+    assert_eq!(db.fetch_keys().unwrap().len(), 100);
+}
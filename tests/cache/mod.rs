@@ -0,0 +1,51 @@
+use onelink_database::cache::KeyCache;
+use onelink_database::utils::InternalApi;
+use onelink_database::virtual_db::{VirtualKey, VirtualLocation};
+
+use crate::common::create_db;
+
+fn key(name: &str) -> VirtualKey {
+    VirtualKey {
+        name: name.to_string(),
+        location: VirtualLocation {
+            id: 0,
+            offset: 0,
+            index: 0,
+        },
+        length: 0,
+        version: 1,
+        sequence: 1,
+    }
+}
+
+#[test]
+pub fn test_key_cache_is_bounded() {
+    let mut cache = KeyCache::with_capacity(4);
+    for i in 0..10 {
+        cache.insert(key(&format!("key-{}", i)));
+    }
+    assert_eq!(cache.len(), 4);
+    // the keys that were cached first are forgotten first.
+    assert!(cache.get("key-5").is_none());
+    assert!(cache.get("key-6").is_some());
+
+    cache.remove("key-6");
+    cache.insert(key("key-6"));
+    cache.insert(key("key-6"));
+    assert_eq!(cache.len(), 4);
+    assert!(cache.get("key-9").is_some());
+    assert_eq!(cache.lookups(), (2, 1));
+}
+
+#[test]
+pub fn test_cached_keys_follow_writes() {
+    let (mut db, _dir) = create_db("key_cache", 2);
+    for i in 0..100 {
+        db.set(format!("key-{}", i), vec![i as u8; 8]).unwrap();
+    }
+    db.remove("key-7".to_string()).unwrap();
+    db.set("key-8".to_string(), vec![1]).unwrap();
+    assert!(db.get("key-7".to_string()).is_err());
+    assert_eq!(db.get("key-8".to_string()).unwrap().data, vec![1]);
+    assert_eq!(db.get("key-9".to_string()).unwrap().data, vec![9; 8]);
+}
@@ -0,0 +1,45 @@
+use onelink_database::changes::ChangeKind;
+use onelink_database::db::Database;
+use onelink_database::utils::InternalApi;
+
+use crate::common::create_db;
+
+#[test]
+pub fn test_changes_since() {
+    let (mut db, _dir) = create_db("changes_since", 2);
+    db.set("a".to_string(), b"1".to_vec()).unwrap();
+    db.set("b".to_string(), b"2".to_vec()).unwrap();
+    let seen = db.sequence().unwrap();
+    db.remove("a".to_string()).unwrap();
+    db.set("c".to_string(), b"33".to_vec()).unwrap();
+
+    let changes: Vec<_> = db.changes_since(seen).unwrap().collect();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].key, "a");
+    assert_eq!(changes[0].kind, ChangeKind::Remove);
+    assert_eq!(changes[1].key, "c");
+    assert_eq!(changes[1].length, 2);
+    assert!(changes[0].sequence < changes[1].sequence);
+
+    // sequence numbers survive a reopen.
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    let mut db = Database::open(name, path).unwrap();
+    assert_eq!(db.sequence().unwrap(), seen + 2);
+    assert_eq!(db.changes_since(0).unwrap().count(), 4);
+}
+
+#[test]
+pub fn test_subscribe_prefix() {
+    let (mut db, _dir) = create_db("subscribe_prefix", 0);
+    let photos = db.subscribe("photos/".to_string()).unwrap();
+    db.set("photos/cat.png".to_string(), vec![1, 2, 3]).unwrap();
+    db.set("docs/cv.pdf".to_string(), vec![4]).unwrap();
+    db.remove("photos/cat.png".to_string()).unwrap();
+
+    let events: Vec<_> = photos.try_iter().collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].kind, ChangeKind::Set);
+    assert_eq!(events[1].kind, ChangeKind::Remove);
+    assert!(events.iter().all(|e| e.key == "photos/cat.png"));
+}
@@ -0,0 +1,40 @@
+use onelink_database::db::Database;
+use onelink_database::preamble::Preamble;
+use tempfile::TempDir;
+
+/// Creates an empty directory for a test to put its databases in.
+/// The directory is removed once the returned guard is dropped.
+pub fn test_dir(name: &str) -> TempDir {
+    tempfile::Builder::new()
+        .prefix(&format!("onelink-{}-", name))
+        .tempdir()
+        .unwrap()
+}
+
+/// Creates a fresh database in its own directory.
+/// The directory is removed once the returned guard is dropped, so keep it for the whole test.
+pub fn create_db(name: &str, partitions: u8) -> (Database, TempDir) {
+    let dir = test_dir(name);
+    let path = dir.path().join(format!("{}.onelink", name));
+    let db = Database::create(
+        name.to_string(),
+        path.to_str().unwrap().to_string(),
+        Preamble::new_unsafe(),
+        partitions,
+    )
+    .unwrap();
+    (db, dir)
+}
+
+/// Bytes that do not compress, for tests that measure how much space values take.
+pub fn noise(len: usize) -> Vec<u8> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
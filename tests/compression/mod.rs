@@ -0,0 +1,261 @@
+use onelink_database::compression::{decode, Compression, DEFAULT_LEVEL};
+use onelink_database::db::Database;
+use onelink_database::dictionary::Dictionaries;
+use onelink_database::preamble::{CompressionMode, Preamble};
+use onelink_database::utils::InternalApi;
+use std::fs;
+
+use crate::common::{create_db, noise, test_dir};
+
+#[test]
+pub fn test_values_are_compressed() {
+    let (mut db, _dir) = create_db("compressed", 2);
+    let text = "the quick brown fox jumps over the lazy dog. ".repeat(200);
+    db.set("fox.txt".to_string(), text.clone().into_bytes())
+        .unwrap();
+    // bytes that do not compress are stored raw.
+    let noise = noise(4096);
+    db.set("noise.bin".to_string(), noise.clone()).unwrap();
+
+    let stats = db.stats().unwrap();
+    assert!(stats.compression_ratio < 0.5);
+    assert!(stats.live_bytes < (text.len() + noise.len()) as u64);
+    let key = db
+        .fetch_keys()
+        .unwrap()
+        .into_iter()
+        .find(|key| key.name == "fox.txt")
+        .unwrap();
+    assert_eq!(key.length, text.len());
+
+    assert_eq!(db.get("fox.txt".to_string()).unwrap().data, text.as_bytes());
+    assert_eq!(db.get("noise.bin".to_string()).unwrap().data, noise);
+    let range = db.get_range("fox.txt".to_string(), 4, 5).unwrap();
+    assert_eq!(range.data, b"quick");
+    assert_eq!(range.length, text.len());
+
+    // compressed values survive compaction and a reopen.
+    db.set_compression_level(19).unwrap();
+    db.set("fox.txt".to_string(), text.clone().into_bytes())
+        .unwrap();
+    db.compact().unwrap();
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    let mut db = Database::open(name, path).unwrap();
+    assert_eq!(db.compression_level(), 19);
+    assert_eq!(db.get("fox.txt".to_string()).unwrap().data, text.as_bytes());
+    assert_eq!(
+        db.get_many(vec!["noise.bin".to_string(), "fox.txt".to_string()])
+            .unwrap()
+            .into_iter()
+            .map(|item| item.unwrap().data.len())
+            .collect::<Vec<_>>(),
+        vec![noise.len(), text.len()]
+    );
+    assert!(db.set_compression_level(1000).is_err());
+}
+
+#[test]
+pub fn test_compression_none() {
+    let dir = test_dir("uncompressed");
+    let path = dir.path().join("uncompressed.onelink");
+    let preamble = Preamble {
+        compression: CompressionMode::None,
+        ..Preamble::new_unsafe()
+    };
+    let mut db = Database::create(
+        "uncompressed".to_string(),
+        path.to_str().unwrap().to_string(),
+        preamble,
+        1,
+    )
+    .unwrap();
+    db.set("a.txt".to_string(), vec![7; 1000]).unwrap();
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.compression_ratio, 1.0);
+    assert_eq!(db.get("a.txt".to_string()).unwrap().data, vec![7; 1000]);
+}
+
+#[test]
+pub fn test_dictionary_compresses_small_values() {
+    let (mut db, _dir) = create_db("dictionary", 2);
+    let metadata = |i: usize| {
+        format!(
+            r#"{{"id":{},"owner":"user-{}","kind":"photo","tags":["holiday","beach"],"public":{}}}"#,
+            i,
+            i % 7,
+            i.is_multiple_of(2)
+        )
+        .into_bytes()
+    };
+    // training needs values to sample.
+    assert!(db.train_dictionary().is_err());
+    for i in 0..500 {
+        db.set(format!("meta-{}.json", i), metadata(i)).unwrap();
+    }
+    let before = db.stats().unwrap();
+
+    assert_eq!(db.train_dictionary().unwrap(), 1);
+    for i in 0..500 {
+        db.set(format!("meta-{}.json", i), metadata(i)).unwrap();
+    }
+    let after = db.stats().unwrap();
+    assert!(after.compression_ratio < before.compression_ratio);
+    assert_eq!(
+        db.get("meta-42.json".to_string()).unwrap().data,
+        metadata(42)
+    );
+
+    // values keep the dictionary version they were compressed with.
+    assert_eq!(db.train_dictionary().unwrap(), 2);
+    db.set("meta-new.json".to_string(), metadata(1000)).unwrap();
+    let snapshot = db.snapshot().unwrap();
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    assert_eq!(
+        snapshot.get("meta-7.json".to_string()).unwrap().data,
+        metadata(7)
+    );
+
+    let mut db = Database::open(name, path).unwrap();
+    assert_eq!(db.dictionary_versions().unwrap(), vec![1, 2]);
+    assert_eq!(db.get("meta-7.json".to_string()).unwrap().data, metadata(7));
+    assert_eq!(
+        db.get("meta-new.json".to_string()).unwrap().data,
+        metadata(1000)
+    );
+    // dictionaries are not part of the key space.
+    assert_eq!(db.fetch_keys().unwrap().len(), 501);
+}
+
+#[test]
+pub fn test_compression_modes() {
+    let dir = test_dir("modes");
+    let path = dir.path().join("modes.onelink");
+    let preamble = Preamble {
+        compression: CompressionMode::Lz4,
+        ..Preamble::new_unsafe()
+    };
+    let mut db = Database::create(
+        "modes".to_string(),
+        path.to_str().unwrap().to_string(),
+        preamble,
+        1,
+    )
+    .unwrap();
+    // words in a random order, which high ratio modes compress better than lz4.
+    let words = [
+        "the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog",
+    ];
+    let text = noise(8000)
+        .into_iter()
+        .map(|byte| words[byte as usize % words.len()])
+        .collect::<Vec<_>>()
+        .join(" ");
+    db.set("fox.txt".to_string(), text.clone().into_bytes())
+        .unwrap();
+    let lz4 = db.stats().unwrap().partitions[0].stored_bytes;
+    assert!(lz4 < text.len() as u64);
+
+    // compaction recompresses the values with the new mode of the partition.
+    db.set_partition_compression(0, CompressionMode::Xz)
+        .unwrap();
+    db.compact().unwrap();
+    let xz = db.stats().unwrap().partitions[0].stored_bytes;
+    assert!(xz < lz4);
+    assert_eq!(db.get("fox.txt".to_string()).unwrap().data, text.as_bytes());
+
+    db.set_compression(CompressionMode::Brotli).unwrap();
+    db.set("dog.txt".to_string(), text.clone().into_bytes())
+        .unwrap();
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    let mut db = Database::open(name, path).unwrap();
+    assert_eq!(db.preamble.compression, CompressionMode::Brotli);
+    db.compact().unwrap();
+    for key in ["fox.txt", "dog.txt"] {
+        assert_eq!(db.get(key.to_string()).unwrap().data, text.as_bytes());
+    }
+    assert_eq!(
+        db.get_range("dog.txt".to_string(), 0, 100).unwrap().data,
+        &text.as_bytes()[..100]
+    );
+}
+
+#[test]
+pub fn test_large_values_are_seekable() {
+    let (mut db, _dir) = create_db("seekable", 2);
+    let words = [
+        "the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog",
+    ];
+    let value = noise(700_000)
+        .into_iter()
+        .map(|byte| words[byte as usize % words.len()])
+        .collect::<Vec<_>>()
+        .join(" ")
+        .into_bytes();
+    assert!(value.len() > 3 * 1024 * 1024);
+    db.set("large.txt".to_string(), value.clone()).unwrap();
+    assert!(db.stats().unwrap().compression_ratio < 0.5);
+
+    // a range across the boundary of two frames.
+    let offset = 1024 * 1024 - 10;
+    let range = db.get_range("large.txt".to_string(), offset, 20).unwrap();
+    assert_eq!(range.data, &value[offset as usize..offset as usize + 20]);
+    assert_eq!(range.length, value.len());
+    let tail = db
+        .get_range("large.txt".to_string(), value.len() as u64 - 5, 100)
+        .unwrap();
+    assert_eq!(tail.data, &value[value.len() - 5..]);
+
+    db.compact().unwrap();
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    let mut db = Database::open(name, path).unwrap();
+    assert_eq!(db.get("large.txt".to_string()).unwrap().data, value);
+}
+
+#[test]
+pub fn test_corrupted_sizes_are_rejected() {
+    let value = "the quick brown fox jumps over the lazy dog. ".repeat(200);
+    let dictionaries = Dictionaries::default();
+    for mode in [
+        CompressionMode::Zstd,
+        CompressionMode::Lz4,
+        CompressionMode::Brotli,
+        CompressionMode::Xz,
+    ] {
+        let compression = Compression {
+            mode,
+            level: DEFAULT_LEVEL,
+        };
+        let (encoding, stored) = compression.encode(value.as_bytes(), None).unwrap();
+        assert_eq!(
+            decode(encoding, stored.to_vec(), &dictionaries).unwrap(),
+            value.as_bytes()
+        );
+
+        // a size that can not be allocated fails to decode, instead of aborting.
+        let mut corrupted = stored.to_vec();
+        corrupted[..8].copy_from_slice(&(u64::MAX / 2).to_be_bytes());
+        assert!(decode(encoding, corrupted, &dictionaries).is_err());
+    }
+}
+
+#[test]
+pub fn test_corrupted_stored_lengths_are_rejected() {
+    let (mut db, _dir) = create_db("corrupted_lengths", 0);
+    let value = noise(256);
+    db.set("noise.bin".to_string(), value.clone()).unwrap();
+    assert_eq!(db.get("noise.bin".to_string()).unwrap().data, value);
+
+    // the stored length is the `u128` right before the value.
+    let path = db.get_path();
+    let mut data = fs::read(&path).unwrap();
+    let at = data.windows(value.len()).position(|w| w == value).unwrap();
+    data[at - 16..at].copy_from_slice(&(u128::MAX / 2).to_be_bytes());
+    fs::write(&path, data).unwrap();
+    // a length longer than the partition fails, instead of being allocated.
+    assert!(db.get("noise.bin".to_string()).is_err());
+    assert!(db.get_range("noise.bin".to_string(), 0, 16).is_err());
+}
@@ -1,7 +1,71 @@
-use onelink_database::db;
+use onelink_database::db::Database;
+use onelink_database::preamble::Preamble;
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+
+use crate::common::{create_db, test_dir};
 
 #[test]
 pub fn test_open_db() {
-    let db = Database::open("./tests/db/test.db").unwrap();
-    assert_eq!(db.get_path(), "./tests/db/test.db");
-}
\ No newline at end of file
+    let dir = test_dir("open");
+    let path = dir.path().join("test.db").to_str().unwrap().to_string();
+    Database::create("test".to_string(), path.clone(), Preamble::new_unsafe(), 0)
+        .unwrap()
+        .close()
+        .unwrap();
+
+    let db = Database::open("test".to_string(), path.clone()).unwrap();
+    assert_eq!(db.get_path(), path);
+}
+
+#[test]
+pub fn test_set_get_remove() {
+    let (mut db, _dir) = create_db("set_get_remove", 2);
+    db.set("foo".to_string(), b"bar".to_vec()).unwrap();
+    db.set("baz".to_string(), b"qux".to_vec()).unwrap();
+    db.set("foo".to_string(), b"bar2".to_vec()).unwrap();
+
+    assert_eq!(db.get("foo".to_string()).unwrap().data, b"bar2");
+    assert!(matches!(
+        db.add("baz".to_string(), vec![]),
+        Err(DatabaseError::KeyAlreadyExists(_))
+    ));
+    assert!(db.remove("foo".to_string()).unwrap());
+    assert!(!db.remove("foo".to_string()).unwrap());
+    assert!(matches!(
+        db.get("foo".to_string()),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+
+    // reopening reads everything back from the partitions.
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    let mut db = Database::open(name, path).unwrap();
+    let keys = db.fetch_keys().unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(db.get("baz".to_string()).unwrap().data, b"qux");
+}
+
+#[test]
+pub fn test_get_range() {
+    let (mut db, _dir) = create_db("get_range", 2);
+    let value: Vec<u8> = (0..=255).collect();
+    db.set("video.mp4".to_string(), value.clone()).unwrap();
+
+    let item = db.get_range("video.mp4".to_string(), 16, 32).unwrap();
+    assert_eq!(item.data, value[16..48]);
+    assert_eq!(item.length, value.len());
+
+    // ranges are cut short at the end of the value.
+    let tail = db.get_range("video.mp4".to_string(), 250, 100).unwrap();
+    assert_eq!(tail.data, value[250..]);
+    assert!(db
+        .get_range("video.mp4".to_string(), 1000, 10)
+        .unwrap()
+        .data
+        .is_empty());
+    assert!(matches!(
+        db.get_range("missing".to_string(), 0, 10),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+}
@@ -0,0 +1,422 @@
+use onelink_database::db::Database;
+use onelink_database::encryption::{rotate_in_background, SlotKind, KEY_BLOCK_LEN};
+use onelink_database::preamble::{EncryptionLevel, Preamble};
+use onelink_database::utils::{GetByteLength, InternalApi};
+use onelink_database::DatabaseError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+
+use crate::common::test_dir;
+
+/// Creates a fresh encrypted database in its own directory.
+fn create_encrypted(name: &str, partitions: u8, password: &str) -> (Database, PathBuf, TempDir) {
+    create_at_level(name, partitions, password, EncryptionLevel::Values)
+}
+
+/// Creates a fresh database in its own directory, encrypted at the given level.
+fn create_at_level(
+    name: &str,
+    partitions: u8,
+    password: &str,
+    level: EncryptionLevel,
+) -> (Database, PathBuf, TempDir) {
+    let dir = test_dir(name);
+    let path = dir.path().join(format!("{}.onelink", name));
+    let mut preamble = Preamble::new();
+    preamble.encryption = level;
+    let db = Database::create_with_password(
+        name.to_string(),
+        path.to_str().unwrap().to_string(),
+        preamble,
+        partitions,
+        password,
+    )
+    .unwrap();
+    (db, path, dir)
+}
+
+/// Whether or not any file in the directory of the database contains the bytes.
+fn stored_in_plaintext(path: &Path, bytes: &[u8]) -> bool {
+    fs::read_dir(path.parent().unwrap()).unwrap().any(|entry| {
+        let data = fs::read(entry.unwrap().path()).unwrap();
+        data.windows(bytes.len()).any(|window| window == bytes)
+    })
+}
+
+#[test]
+pub fn test_encrypted_database_reopens_with_password() {
+    let (mut db, path, _dir) = create_encrypted("encrypted_reopen", 2, "hunter2");
+    assert!(db.is_encrypted());
+    for i in 0..10 {
+        db.set(
+            format!("secret-{}", i),
+            format!("plaintext value {}", i).into_bytes(),
+        )
+        .unwrap();
+    }
+    drop(db);
+
+    let path = path.to_str().unwrap().to_string();
+    assert!(matches!(
+        Database::open_with_password("encrypted_reopen".to_string(), path.clone(), "hunter3"),
+        Err(DatabaseError::IncorrectPassword)
+    ));
+    assert!(matches!(
+        Database::open("encrypted_reopen".to_string(), path.clone()),
+        Err(DatabaseError::IncorrectPassword)
+    ));
+
+    let mut db =
+        Database::open_with_password("encrypted_reopen".to_string(), path, "hunter2").unwrap();
+    assert_eq!(db.fetch_keys().unwrap().len(), 10);
+    assert_eq!(
+        db.get("secret-4".to_string()).unwrap().data,
+        b"plaintext value 4".to_vec()
+    );
+}
+
+#[test]
+pub fn test_values_are_not_stored_in_plaintext() {
+    let (mut db, path, _dir) = create_encrypted("encrypted_plaintext", 0, "hunter2");
+    let value = b"a very recognisable plaintext value".to_vec();
+    db.set("a".to_string(), value.clone()).unwrap();
+    db.set("b".to_string(), vec![1; 64]).unwrap();
+    db.remove("b".to_string()).unwrap();
+    db.compact().unwrap();
+    assert_eq!(db.get("a".to_string()).unwrap().data, value);
+    drop(db);
+
+    assert!(!stored_in_plaintext(&path, &value));
+}
+
+#[test]
+pub fn test_sealed_value_can_not_move_to_another_key() {
+    let (mut db, path, _dir) = create_encrypted("encrypted_moved_value", 0, "hunter2");
+    db.set("key-a".to_string(), vec![1; 32]).unwrap();
+    db.set("key-b".to_string(), vec![2; 32]).unwrap();
+    drop(db);
+
+    // copies the sealed value of "key-a" over the one of "key-b", which has the same length.
+    let mut data = fs::read(&path).unwrap();
+    let stored = |data: &[u8], name: &[u8]| {
+        let start = data.windows(name.len()).position(|w| w == name).unwrap() + name.len();
+        let size = u128::from_be_bytes(data[start + 1..start + 17].try_into().unwrap());
+        start + 17..start + 17 + size as usize
+    };
+    let (a, b) = (stored(&data, b"key-a"), stored(&data, b"key-b"));
+    assert_eq!(a.len(), b.len());
+    let sealed = data[a].to_vec();
+    data[b].copy_from_slice(&sealed);
+    fs::write(&path, data).unwrap();
+
+    let mut db = Database::open_with_password(
+        "encrypted_moved_value".to_string(),
+        path.to_str().unwrap().to_string(),
+        "hunter2",
+    )
+    .unwrap();
+    assert_eq!(db.get("key-a".to_string()).unwrap().data, vec![1; 32]);
+    assert!(db.get("key-b".to_string()).is_err());
+}
+
+#[test]
+pub fn test_encrypted_key_names() {
+    let (mut db, path, _dir) =
+        create_at_level("encrypted_names", 0, "hunter2", EncryptionLevel::Names);
+    assert_eq!(db.encryption_level(), EncryptionLevel::Names);
+    let name = "a-recognisable-key-name";
+    db.set(name.to_string(), b"value".to_vec()).unwrap();
+    db.set("removed".to_string(), vec![1; 64]).unwrap();
+    db.remove("removed".to_string()).unwrap();
+    db.compact().unwrap();
+    assert_eq!(
+        db.snapshot().unwrap().get(name.to_string()).unwrap().data,
+        b"value".to_vec()
+    );
+    drop(db);
+    assert!(!stored_in_plaintext(&path, name.as_bytes()));
+
+    let mut db = Database::open_with_password(
+        "encrypted_names".to_string(),
+        path.to_str().unwrap().to_string(),
+        "hunter2",
+    )
+    .unwrap();
+    let keys = db.fetch_keys().unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].name, name);
+    assert_eq!(db.get(name.to_string()).unwrap().data, b"value".to_vec());
+}
+
+#[test]
+pub fn test_fully_encrypted_records() {
+    let (mut db, path, _dir) =
+        create_at_level("encrypted_full", 3, "hunter2", EncryptionLevel::Full);
+    for i in 0..20 {
+        db.set(format!("file-{}", i), vec![i as u8; 100 + i])
+            .unwrap();
+    }
+    let large: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    db.set("large".to_string(), large.clone()).unwrap();
+    db.remove("file-3".to_string()).unwrap();
+    db.compact().unwrap();
+    assert_eq!(
+        db.get_range("large".to_string(), 2 * 1024 * 1024, 16)
+            .unwrap()
+            .data,
+        large[2 * 1024 * 1024..2 * 1024 * 1024 + 16].to_vec()
+    );
+
+    let dir = test_dir("encrypted_full_out");
+    let archive = dir.path().join("backup.olb").to_str().unwrap().to_string();
+    db.backup_to(archive.clone()).unwrap();
+    drop(db);
+    assert!(!stored_in_plaintext(&path, b"file-1"));
+
+    let mut db = Database::open_with_password(
+        "encrypted_full".to_string(),
+        path.to_str().unwrap().to_string(),
+        "hunter2",
+    )
+    .unwrap();
+    assert_eq!(db.fetch_keys().unwrap().len(), 20);
+    assert_eq!(db.get("large".to_string()).unwrap().data, large);
+
+    let restored_path = dir
+        .path()
+        .join("restored.onelink")
+        .to_str()
+        .unwrap()
+        .to_string();
+    let mut restored =
+        Database::restore_from_with_password(archive, restored_path, "hunter2").unwrap();
+    assert_eq!(restored.encryption_level(), EncryptionLevel::Full);
+    assert_eq!(restored.fetch_keys().unwrap().len(), 20);
+    assert_eq!(
+        restored.get("file-7".to_string()).unwrap().data,
+        vec![7; 107]
+    );
+    assert!(restored.get("file-3".to_string()).is_err());
+}
+
+#[test]
+pub fn test_encrypted_backup_and_restore() {
+    let (mut db, _, _dir) = create_encrypted("encrypted_backup", 3, "hunter2");
+    for i in 0..20 {
+        db.set(format!("file-{}", i), vec![i as u8; 100 + i])
+            .unwrap();
+    }
+
+    let dir = test_dir("encrypted_backup_out");
+    let archive = dir.path().join("backup.olb").to_str().unwrap().to_string();
+    db.backup_to(archive.clone()).unwrap();
+
+    let restored_path = dir
+        .path()
+        .join("restored.onelink")
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(matches!(
+        Database::restore_from_with_password(archive.clone(), restored_path.clone(), "hunter3"),
+        Err(DatabaseError::IncorrectPassword)
+    ));
+    let mut restored =
+        Database::restore_from_with_password(archive, restored_path, "hunter2").unwrap();
+    assert!(restored.is_encrypted());
+    assert_eq!(restored.fetch_keys().unwrap().len(), 20);
+    assert_eq!(
+        restored.get("file-7".to_string()).unwrap().data,
+        vec![7; 107]
+    );
+}
+
+#[test]
+pub fn test_key_slots() {
+    let (mut db, path, _dir) = create_encrypted("encrypted_slots", 2, "hunter2");
+    db.set("a".to_string(), vec![1; 32]).unwrap();
+    let keyfile = path.with_extension("key");
+    fs::write(&keyfile, [7; 64]).unwrap();
+    let keyfile = keyfile.to_str().unwrap().to_string();
+
+    assert_eq!(db.add_password("correct horse").unwrap(), 1);
+    assert_eq!(db.add_keyfile(keyfile.clone()).unwrap(), 2);
+    assert_eq!(
+        db.key_slots(),
+        vec![
+            (0, SlotKind::Password),
+            (1, SlotKind::Password),
+            (2, SlotKind::Keyfile)
+        ]
+    );
+    db.remove_key_slot(0).unwrap();
+    assert!(db.remove_key_slot(0).is_err());
+    drop(db);
+
+    let name = "encrypted_slots".to_string();
+    let path = path.to_str().unwrap().to_string();
+    assert!(matches!(
+        Database::open_with_password(name.clone(), path.clone(), "hunter2"),
+        Err(DatabaseError::IncorrectPassword)
+    ));
+    let mut db = Database::open_with_keyfile(name.clone(), path.clone(), keyfile).unwrap();
+    assert_eq!(db.get("a".to_string()).unwrap().data, vec![1; 32]);
+    db.remove_key_slot(2).unwrap();
+    assert!(db.remove_key_slot(1).is_err());
+    drop(db);
+
+    let mut db = Database::open_with_password(name, path, "correct horse").unwrap();
+    assert_eq!(db.key_slots(), vec![(1, SlotKind::Password)]);
+    assert_eq!(db.get("a".to_string()).unwrap().data, vec![1; 32]);
+}
+
+#[test]
+pub fn test_rotate_key() {
+    let (mut db, path, _dir) = create_encrypted("encrypted_rotate", 0, "hunter2");
+    db.set("a".to_string(), b"before the rotation".to_vec())
+        .unwrap();
+    db.add_password("kept").unwrap();
+
+    // every slot wraps the new key.
+    let rotation = db.rotate_key().unwrap();
+    assert!(rotation.is_done());
+    assert_eq!(db.key_rotation(), None);
+    assert_eq!(
+        db.key_slots(),
+        vec![(0, SlotKind::Password), (1, SlotKind::Password)]
+    );
+    assert_eq!(
+        db.get("a".to_string()).unwrap().data,
+        b"before the rotation".to_vec()
+    );
+    drop(db);
+
+    let name = "encrypted_rotate".to_string();
+    let path = path.to_str().unwrap().to_string();
+    for password in ["hunter2", "kept"] {
+        let db = Database::open_with_password(name.clone(), path.clone(), password);
+        assert_eq!(
+            db.unwrap().get("a".to_string()).unwrap().data,
+            b"before the rotation".to_vec()
+        );
+    }
+
+    // a slot that was removed before the rotation does not unlock the new key.
+    let mut db = Database::open_with_password(name.clone(), path.clone(), "hunter2").unwrap();
+    let before = fs::read(&path).unwrap();
+    db.remove_key_slot(1).unwrap();
+    db.rotate_key().unwrap();
+    drop(db);
+    assert!(matches!(
+        Database::open_with_password(name.clone(), path.clone(), "kept"),
+        Err(DatabaseError::IncorrectPassword)
+    ));
+    // nor does the slot as it was stored before it was removed.
+    let after = fs::read(&path).unwrap();
+    let block = Preamble::new().byte_len()..Preamble::new().byte_len() + KEY_BLOCK_LEN;
+    let mut restored = after.clone();
+    restored[block.clone()].copy_from_slice(&before[block]);
+    fs::write(&path, restored).unwrap();
+    assert!(Database::open_with_password(name.clone(), path.clone(), "kept").is_err());
+    fs::write(&path, after).unwrap();
+    let mut db = Database::open_with_password(name, path, "hunter2").unwrap();
+    assert_eq!(
+        db.get("a".to_string()).unwrap().data,
+        b"before the rotation".to_vec()
+    );
+}
+
+#[test]
+pub fn test_rotate_key_resumes() {
+    let (mut db, path, _dir) = create_encrypted("encrypted_rotate_resume", 3, "hunter2");
+    for i in 0..30 {
+        db.set(format!("file-{}", i), vec![i as u8; 100 + i])
+            .unwrap();
+    }
+
+    let rotation = db.begin_key_rotation().unwrap();
+    assert_eq!(rotation.rotated, 0);
+    assert_eq!(rotation.partitions, 3);
+    db.set("during".to_string(), vec![9; 50]).unwrap();
+    assert_eq!(db.rotate_partitions(1).unwrap().rotated, 1);
+    // the database stops before the rotation is done, as if it crashed.
+    drop(db);
+
+    let mut db = Database::open_with_password(
+        "encrypted_rotate_resume".to_string(),
+        path.to_str().unwrap().to_string(),
+        "hunter2",
+    )
+    .unwrap();
+    let rotation = db.key_rotation().unwrap();
+    assert_eq!((rotation.rotated, rotation.partitions), (1, 3));
+    assert_eq!(db.get("file-20".to_string()).unwrap().data, vec![20; 120]);
+
+    assert!(db.rotate_partitions(5).unwrap().is_done());
+    assert_eq!(db.key_rotation(), None);
+    assert_eq!(db.fetch_keys().unwrap().len(), 31);
+    assert_eq!(db.get("during".to_string()).unwrap().data, vec![9; 50]);
+    assert_eq!(db.get("file-7".to_string()).unwrap().data, vec![7; 107]);
+}
+
+#[test]
+pub fn test_rotate_key_in_background() {
+    let (mut db, _path, _dir) = create_encrypted("encrypted_rotate_background", 4, "hunter2");
+    for i in 0..40 {
+        db.set(format!("file-{}", i), vec![i as u8; 64]).unwrap();
+    }
+    db.begin_key_rotation().unwrap();
+    let db = Arc::new(Mutex::new(db));
+    let rotating = rotate_in_background(Arc::clone(&db), 1, Duration::from_millis(1));
+    // the database is unlocked between partitions, so it keeps serving requests.
+    db.lock()
+        .unwrap()
+        .set("during".to_string(), vec![9; 64])
+        .unwrap();
+
+    let rotation = rotating.join().unwrap().unwrap();
+    assert_eq!((rotation.rotated, rotation.partitions), (4, 4));
+    let mut db = db.lock().unwrap();
+    assert_eq!(db.key_rotation(), None);
+    assert_eq!(db.fetch_keys().unwrap().len(), 41);
+    assert_eq!(db.get("file-33".to_string()).unwrap().data, vec![33; 64]);
+    assert_eq!(db.get("during".to_string()).unwrap().data, vec![9; 64]);
+}
+
+#[test]
+pub fn test_encrypted_key_names_are_found_by_token() {
+    let (mut db, path, _dir) =
+        create_at_level("encrypted_tokens", 3, "hunter2", EncryptionLevel::Names);
+    for i in 0..30 {
+        db.set(format!("patient-{}", i), vec![i as u8; 10]).unwrap();
+    }
+    // updating a key finds its earlier record by the token of its name.
+    db.set("patient-4".to_string(), vec![44; 10]).unwrap();
+    assert_eq!(db.fetch_keys().unwrap().len(), 30);
+    assert!(db.get("patient-30".to_string()).is_err());
+
+    // partitions that are not encrypted again yet keep the tokens of the previous key.
+    db.begin_key_rotation().unwrap();
+    db.set("patient-5".to_string(), vec![55; 10]).unwrap();
+    assert_eq!(db.get("patient-9".to_string()).unwrap().data, vec![9; 10]);
+    assert_eq!(db.get("patient-5".to_string()).unwrap().data, vec![55; 10]);
+    db.rotate_partitions(3).unwrap();
+    db.compact().unwrap();
+    drop(db);
+    assert!(!stored_in_plaintext(&path, b"patient-"));
+
+    let mut db = Database::open_with_password(
+        "encrypted_tokens".to_string(),
+        path.to_str().unwrap().to_string(),
+        "hunter2",
+    )
+    .unwrap();
+    assert_eq!(db.fetch_keys().unwrap().len(), 30);
+    assert_eq!(db.get("patient-4".to_string()).unwrap().data, vec![44; 10]);
+    assert_eq!(db.get("patient-5".to_string()).unwrap().data, vec![55; 10]);
+    assert!(db.remove("patient-6".to_string()).unwrap());
+    assert!(db.get("patient-6".to_string()).is_err());
+}
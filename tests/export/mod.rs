@@ -0,0 +1,150 @@
+use onelink_database::db::Database;
+use onelink_database::preamble::{CompressionMode, EncryptionLevel, Preamble};
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+use std::io::{BufReader, Cursor};
+
+use crate::common::{create_db, test_dir};
+
+#[test]
+pub fn test_export_and_import() {
+    let (mut db, _dir) = create_db("export_import", 2);
+    db.set("notes/a.txt".to_string(), b"hello".to_vec())
+        .unwrap();
+    db.set("bin".to_string(), vec![0, 255, 10, 13]).unwrap();
+    db.set("gone".to_string(), vec![1]).unwrap();
+    db.remove("gone".to_string()).unwrap();
+
+    let mut exported = Vec::new();
+    assert_eq!(db.export_to(&mut exported).unwrap(), 2);
+    assert_eq!(String::from_utf8_lossy(&exported).lines().count(), 2);
+
+    let dir = test_dir("export_import_out");
+    let path = dir.path().join("imported.onelink");
+    let preamble = Preamble {
+        compression: CompressionMode::None,
+        ..Preamble::new_unsafe()
+    };
+    let mut imported = Database::import_from(
+        &mut BufReader::new(Cursor::new(exported)),
+        "imported".to_string(),
+        path.to_str().unwrap().to_string(),
+        preamble,
+        0,
+    )
+    .unwrap();
+    assert_eq!(imported.preamble.compression, CompressionMode::None);
+    assert_eq!(imported.fetch_keys().unwrap().len(), 2);
+    assert_eq!(
+        imported.get("bin".to_string()).unwrap().data,
+        vec![0, 255, 10, 13]
+    );
+    assert_eq!(
+        imported.get("notes/a.txt".to_string()).unwrap().data,
+        b"hello"
+    );
+}
+
+#[test]
+pub fn test_import_rejects_invalid_lines() {
+    let dir = test_dir("import_invalid");
+    let path = dir.path().join("imported.onelink");
+    let result = Database::import_from(
+        &mut BufReader::new(Cursor::new(
+            b"{\"key\": \"a\", \"value\": \"***\"}\n".to_vec(),
+        )),
+        "imported".to_string(),
+        path.to_str().unwrap().to_string(),
+        Preamble::new_unsafe(),
+        0,
+    );
+    assert!(matches!(result, Err(DatabaseError::ImportInvalid(_))));
+}
+
+#[test]
+pub fn test_import_into_encrypted_database() {
+    let (mut db, _dir) = create_db("export_encrypted", 2);
+    db.set("a.txt".to_string(), b"secret value".to_vec())
+        .unwrap();
+    db.create_namespace("photos".to_string()).unwrap();
+    db.set_in("photos", "b.jpg".to_string(), vec![1, 2, 3])
+        .unwrap();
+    let mut exported = Vec::new();
+    db.export_to(&mut exported).unwrap();
+
+    let dir = test_dir("export_encrypted_out");
+    let path = dir.path().join("imported.onelink");
+    let path = path.to_str().unwrap().to_string();
+    let mut preamble = Preamble::new();
+    preamble.encryption = EncryptionLevel::Names;
+    let imported = Database::import_from_with_password(
+        &mut BufReader::new(Cursor::new(exported.clone())),
+        "imported".to_string(),
+        path.clone(),
+        preamble,
+        2,
+        "hunter2",
+    )
+    .unwrap();
+    assert_eq!(imported.encryption_level(), EncryptionLevel::Names);
+    drop(imported);
+
+    let mut imported =
+        Database::open_with_password("imported".to_string(), path, "hunter2").unwrap();
+    assert_eq!(
+        imported.get("a.txt".to_string()).unwrap().data,
+        b"secret value"
+    );
+    assert_eq!(
+        imported.get_in("photos", "b.jpg".to_string()).unwrap().data,
+        vec![1, 2, 3]
+    );
+    let mut reexported = Vec::new();
+    imported.export_to(&mut reexported).unwrap();
+    assert_eq!(reexported, exported);
+}
+
+#[test]
+pub fn test_import_in_chunks() {
+    let (mut db, _dir) = create_db("export_chunks", 3);
+    db.create_namespace("photos".to_string()).unwrap();
+    let pairs: Vec<(String, Vec<u8>)> = (0..2500)
+        .map(|i| (format!("key-{}", i), vec![i as u8; 16]))
+        .collect();
+    db.set_many(pairs).unwrap();
+    for i in 0..1500 {
+        db.set_in("photos", format!("photo-{}", i), vec![i as u8; 8])
+            .unwrap();
+    }
+    db.set_blob("shared.bin".to_string(), vec![9; 64]).unwrap();
+    let mut exported = Vec::new();
+    assert_eq!(db.export_to(&mut exported).unwrap(), 4001);
+
+    let dir = test_dir("export_chunks_out");
+    let path = dir.path().join("imported.onelink");
+    let mut imported = Database::import_from(
+        &mut BufReader::new(Cursor::new(exported)),
+        "imported".to_string(),
+        path.to_str().unwrap().to_string(),
+        Preamble::new_unsafe(),
+        3,
+    )
+    .unwrap();
+    assert_eq!(imported.fetch_keys().unwrap().len(), 2500);
+    assert_eq!(imported.fetch_keys_in("photos").unwrap().len(), 1500);
+    assert_eq!(
+        imported.get("key-2499".to_string()).unwrap().data,
+        vec![2499u16 as u8; 16]
+    );
+    assert_eq!(
+        imported
+            .get_in("photos", "photo-1499".to_string())
+            .unwrap()
+            .data,
+        vec![1499u16 as u8; 8]
+    );
+    assert_eq!(
+        imported.get_blob("shared.bin".to_string()).unwrap().data,
+        vec![9; 64]
+    );
+}
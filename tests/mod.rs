@@ -1 +1,20 @@
-mod db;
\ No newline at end of file
+mod async_api;
+mod backup;
+mod batch;
+mod blob;
+mod bloom;
+mod cache;
+mod changes;
+mod common;
+mod compression;
+mod db;
+mod encryption;
+mod export;
+mod namespace;
+mod read_only;
+mod replication;
+mod scrub;
+mod snapshot;
+mod stats;
+mod tiering;
+mod versions;
@@ -0,0 +1,145 @@
+use onelink_database::db::Database;
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+use std::io::{BufReader, Cursor};
+
+use crate::common::{create_db, noise, test_dir};
+
+#[test]
+pub fn test_namespaces_are_isolated() {
+    let (mut db, _dir) = create_db("namespace_isolated", 2);
+    db.create_namespace("alice".to_string()).unwrap();
+    db.create_namespace("bob".to_string()).unwrap();
+    assert!(matches!(
+        db.create_namespace("alice".to_string()),
+        Err(DatabaseError::KeyAlreadyExists(_))
+    ));
+
+    db.set("shared.txt".to_string(), b"flat".to_vec()).unwrap();
+    db.set_in("alice", "shared.txt".to_string(), b"alice".to_vec())
+        .unwrap();
+    db.set_in("bob", "shared.txt".to_string(), b"bob".to_vec())
+        .unwrap();
+    db.set_in("bob", "other.txt".to_string(), b"bob".to_vec())
+        .unwrap();
+
+    assert_eq!(db.get("shared.txt".to_string()).unwrap().data, b"flat");
+    assert_eq!(
+        db.get_in("alice", "shared.txt".to_string()).unwrap().data,
+        b"alice"
+    );
+    assert_eq!(db.fetch_keys().unwrap().len(), 1);
+    assert_eq!(db.fetch_keys_in("alice").unwrap().len(), 1);
+    let mut bob: Vec<String> = db
+        .fetch_keys_in("bob")
+        .unwrap()
+        .into_iter()
+        .map(|key| key.name)
+        .collect();
+    bob.sort();
+    assert_eq!(bob, vec!["other.txt", "shared.txt"]);
+
+    // reserved names can not be reached through the flat key space.
+    assert!(matches!(
+        db.set("\0ns\0alice".to_string(), vec![]),
+        Err(DatabaseError::InvalidKey(_))
+    ));
+
+    // namespaces are read back when the database is reopened.
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    let mut db = Database::open(name, path).unwrap();
+    assert_eq!(db.namespaces().unwrap(), vec!["alice", "bob"]);
+    assert_eq!(
+        db.get_in("bob", "other.txt".to_string()).unwrap().data,
+        b"bob"
+    );
+}
+
+#[test]
+pub fn test_drop_namespace() {
+    let (mut db, _dir) = create_db("namespace_drop", 0);
+    db.create_namespace("tmp".to_string()).unwrap();
+    for i in 0..50 {
+        db.set_in("tmp", format!("file-{}", i), noise(256)).unwrap();
+    }
+    let sequence = db.sequence().unwrap();
+    assert!(db.drop_namespace("tmp".to_string()).unwrap());
+    // dropping is a single record.
+    assert_eq!(db.sequence().unwrap(), sequence + 1);
+    assert!(matches!(
+        db.get_in("tmp", "file-1".to_string()),
+        Err(DatabaseError::NamespaceNotFound(_))
+    ));
+
+    // a namespace with the same name starts empty.
+    db.create_namespace("tmp".to_string()).unwrap();
+    assert!(db.fetch_keys_in("tmp").unwrap().is_empty());
+    assert!(db.compact().unwrap() >= 50 * 256);
+}
+
+#[test]
+pub fn test_dropped_namespace_reclaimed_past_snapshots() {
+    let (mut db, _dir) = create_db("namespace_drop_snapshot", 0);
+    for name in ["old", "new"] {
+        db.create_namespace(name.to_string()).unwrap();
+        for i in 0..50 {
+            db.set_in(name, format!("file-{}", i), noise(256)).unwrap();
+        }
+    }
+    assert!(db.drop_namespace("old".to_string()).unwrap());
+
+    // the snapshot was taken after "old" was dropped, but before "new" was.
+    let snapshot = db.snapshot().unwrap();
+    assert!(db.drop_namespace("new".to_string()).unwrap());
+    let reclaimed = db.compact().unwrap();
+    assert!(reclaimed >= 50 * 256);
+    assert!(reclaimed < 100 * 256);
+    let partition = snapshot.open_partition(0).unwrap();
+    let kept: Vec<_> = partition
+        .records
+        .iter()
+        .filter(|record| record.name.ends_with("\0file-1"))
+        .collect();
+    assert_eq!(kept.len(), 1);
+
+    // the drop is read again when the database is reopened.
+    drop(snapshot);
+    let path = db.get_path();
+    db.close().unwrap();
+    let mut db = Database::open("namespace_drop_snapshot".to_string(), path).unwrap();
+    let _snapshot = db.snapshot().unwrap();
+    assert!(db.compact().unwrap() >= 50 * 256);
+}
+
+#[test]
+pub fn test_export_keeps_namespaces() {
+    let (mut db, _dir) = create_db("namespace_export", 0);
+    db.create_namespace("photos".to_string()).unwrap();
+    db.set_in("photos", "cat.png".to_string(), vec![1, 2])
+        .unwrap();
+    db.set("readme".to_string(), vec![3]).unwrap();
+
+    let mut exported = Vec::new();
+    assert_eq!(db.export_to(&mut exported).unwrap(), 2);
+
+    let dir = test_dir("namespace_export_out");
+    let path = dir.path().join("imported.onelink");
+    let mut imported = Database::import_from(
+        &mut BufReader::new(Cursor::new(exported)),
+        "imported".to_string(),
+        path.to_str().unwrap().to_string(),
+        db.preamble.clone(),
+        0,
+    )
+    .unwrap();
+    assert_eq!(imported.namespaces().unwrap(), vec!["photos"]);
+    assert_eq!(
+        imported
+            .get_in("photos", "cat.png".to_string())
+            .unwrap()
+            .data,
+        vec![1, 2]
+    );
+    assert_eq!(imported.fetch_keys().unwrap().len(), 1);
+}
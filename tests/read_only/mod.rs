@@ -0,0 +1,81 @@
+use onelink_database::db::Database;
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+
+use crate::common::create_db;
+
+#[test]
+pub fn test_read_only_openers_share_the_database() {
+    let (mut db, _dir) = create_db("read_only", 2);
+    db.set("a.txt".to_string(), b"one".to_vec()).unwrap();
+    let path = db.get_path();
+
+    // a database opened for writing can not be shared.
+    assert!(matches!(
+        Database::open_read_only("read_only".to_string(), path.clone()),
+        Err(DatabaseError::Locked)
+    ));
+    db.close().unwrap();
+
+    let mut first = Database::open_read_only("read_only".to_string(), path.clone()).unwrap();
+    let mut second = Database::open_read_only("read_only".to_string(), path.clone()).unwrap();
+    assert!(first.is_read_only());
+    assert_eq!(first.get("a.txt".to_string()).unwrap().data, b"one");
+    assert_eq!(second.fetch_keys().unwrap().len(), 1);
+    assert!(matches!(
+        Database::open("read_only".to_string(), path.clone()),
+        Err(DatabaseError::Locked)
+    ));
+
+    let header = first.header.clone();
+    assert!(matches!(
+        first.set("b.txt".to_string(), b"two".to_vec()),
+        Err(DatabaseError::ReadOnly)
+    ));
+    assert!(matches!(
+        first.remove("a.txt".to_string()),
+        Err(DatabaseError::ReadOnly)
+    ));
+    assert!(matches!(
+        first.create_namespace("photos".to_string()),
+        Err(DatabaseError::ReadOnly)
+    ));
+    assert!(matches!(first.compact(), Err(DatabaseError::ReadOnly)));
+    first.close().unwrap();
+    second.close().unwrap();
+
+    // the header was not touched by the read-only openers.
+    let third = Database::open_read_only("read_only".to_string(), path.clone()).unwrap();
+    assert_eq!(third.header.last_open, header.last_open);
+    assert_eq!(third.header.last_close, header.last_close);
+    assert_eq!(third.header.last_write, header.last_write);
+    drop(third);
+
+    let mut db = Database::open("read_only".to_string(), path).unwrap();
+    db.set("b.txt".to_string(), b"two".to_vec()).unwrap();
+}
+
+#[test]
+pub fn test_compacted_database_stays_locked() {
+    // an unpartitioned database stores its records in the database file,
+    // which compaction replaces.
+    let (mut db, _dir) = create_db("read_only_compacted", 0);
+    db.set("a.txt".to_string(), b"one".to_vec()).unwrap();
+    db.set("a.txt".to_string(), b"two".to_vec()).unwrap();
+    assert!(db.compact().unwrap() > 0);
+
+    let path = db.get_path();
+    assert!(matches!(
+        Database::open("read_only_compacted".to_string(), path.clone()),
+        Err(DatabaseError::Locked)
+    ));
+    assert!(matches!(
+        Database::open_read_only("read_only_compacted".to_string(), path.clone()),
+        Err(DatabaseError::Locked)
+    ));
+    assert_eq!(db.get("a.txt".to_string()).unwrap().data, b"two");
+    db.close().unwrap();
+
+    let mut db = Database::open("read_only_compacted".to_string(), path).unwrap();
+    assert_eq!(db.get("a.txt".to_string()).unwrap().data, b"two");
+}
@@ -0,0 +1,277 @@
+use onelink_database::db::Database;
+use onelink_database::preamble::Preamble;
+use onelink_database::replication::{
+    ChannelTransport, Primary, Replica, StreamTransport, Transport, MAX_FRAME_LEN,
+    MAX_FRAME_RECORDS,
+};
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+use std::io::Cursor;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tempfile::TempDir;
+
+use crate::common::{create_db, test_dir};
+
+/// The path of the replica, within the given directory.
+fn replica_path(dir: &TempDir) -> String {
+    dir.path()
+        .join("replica.onelink")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+pub fn test_replica_follows_primary() {
+    let (db, _dir) = create_db("replication_primary", 2);
+    let primary = Arc::new(Mutex::new(db));
+    let replica_dir = test_dir("replication_replica");
+    let (primary_end, replica_end) = ChannelTransport::pair();
+    let serving = Arc::clone(&primary);
+    let server = thread::spawn(move || Primary::new(primary_end).serve(&serving));
+
+    let mut replica = Replica::create(
+        "replica".to_string(),
+        replica_path(&replica_dir),
+        Preamble::new_unsafe(),
+        1,
+        replica_end,
+    )
+    .unwrap();
+
+    {
+        let mut db = primary.lock().unwrap();
+        db.set("a.txt".to_string(), b"one".to_vec()).unwrap();
+        db.set("b.txt".to_string(), b"two".to_vec()).unwrap();
+        db.create_namespace("photos".to_string()).unwrap();
+        db.set_in("photos", "c.jpg".to_string(), b"three".to_vec())
+            .unwrap();
+    }
+    assert_eq!(replica.sync().unwrap(), 4);
+    assert_eq!(replica.lag().unwrap().sequences, 0);
+    assert_eq!(replica.get("a.txt".to_string()).unwrap().data, b"one");
+    assert_eq!(
+        replica.get_in("photos", "c.jpg".to_string()).unwrap().data,
+        b"three"
+    );
+
+    {
+        let mut db = primary.lock().unwrap();
+        db.remove("a.txt".to_string()).unwrap();
+        db.set("b.txt".to_string(), b"two again".to_vec()).unwrap();
+        // records the replica has not pulled yet survive compaction.
+        db.compact().unwrap();
+    }
+    assert_eq!(replica.sync().unwrap(), 2);
+    assert!(matches!(
+        replica.get("a.txt".to_string()),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+    let item = replica.get("b.txt".to_string()).unwrap();
+    assert_eq!(item.data, b"two again");
+    assert_eq!(item.version, 2);
+    assert_eq!(
+        replica.sequence().unwrap(),
+        primary.lock().unwrap().sequence().unwrap()
+    );
+
+    drop(replica);
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+pub fn test_replica_over_loopback_socket() {
+    let (db, _dir) = create_db("replication_socket", 0);
+    let primary = Arc::new(Mutex::new(db));
+    {
+        let mut db = primary.lock().unwrap();
+        let pairs: Vec<(String, Vec<u8>)> = (0..MAX_FRAME_RECORDS + 10)
+            .map(|i| (format!("key-{}", i), vec![i as u8; 8]))
+            .collect();
+        db.set_many(pairs).unwrap();
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let serving = Arc::clone(&primary);
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        Primary::new(StreamTransport::new(stream)).serve(&serving)
+    });
+
+    let replica_dir = test_dir("replication_socket_replica");
+    let path = replica_path(&replica_dir);
+    let mut replica = Replica::create(
+        "replica".to_string(),
+        path.clone(),
+        Preamble::new_unsafe(),
+        0,
+        StreamTransport::new(TcpStream::connect(address).unwrap()),
+    )
+    .unwrap();
+
+    // a single frame does not hold every record, so the replica lags behind.
+    assert_eq!(replica.sync().unwrap(), MAX_FRAME_RECORDS);
+    assert_eq!(replica.lag().unwrap().sequences, 10);
+    assert_eq!(replica.catch_up().unwrap(), 10);
+    assert_eq!(replica.lag().unwrap().sequences, 0);
+    assert_eq!(replica.fetch_keys().unwrap().len(), MAX_FRAME_RECORDS + 10);
+    replica.close().unwrap();
+    server.join().unwrap().unwrap();
+
+    // a reopened replica continues from its latest record.
+    primary
+        .lock()
+        .unwrap()
+        .set("late".to_string(), b"late".to_vec())
+        .unwrap();
+    let (primary_end, replica_end) = ChannelTransport::pair();
+    let serving = Arc::clone(&primary);
+    let server = thread::spawn(move || Primary::new(primary_end).serve(&serving));
+    let mut replica = Replica::open("replica".to_string(), path, replica_end).unwrap();
+    assert_eq!(replica.sync().unwrap(), 1);
+    assert_eq!(replica.get("late".to_string()).unwrap().data, b"late");
+    drop(replica);
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+pub fn test_replica_resyncs_after_unserved_compaction() {
+    let name = "replication_resync";
+    let (mut db, _dir) = create_db(name, 2);
+    let path = db.get_path();
+    let pairs: Vec<(String, Vec<u8>)> = (0..MAX_FRAME_RECORDS + 10)
+        .map(|i| (format!("key-{}", i), vec![i as u8; 8]))
+        .collect();
+    db.set_many(pairs).unwrap();
+    db.remove(format!("key-{}", MAX_FRAME_RECORDS + 9)).unwrap();
+    db.compact().unwrap();
+    let primary = Arc::new(Mutex::new(db));
+
+    // an empty replica is not behind what was compacted before it started.
+    let (primary_end, replica_end) = ChannelTransport::pair();
+    let serving = Arc::clone(&primary);
+    let server = thread::spawn(move || Primary::new(primary_end).serve(&serving));
+    let replica_dir = test_dir("replication_resync_replica");
+    let replica_path = replica_path(&replica_dir);
+    let mut replica = Replica::create(
+        "replica".to_string(),
+        replica_path.clone(),
+        Preamble::new_unsafe(),
+        1,
+        replica_end,
+    )
+    .unwrap();
+    assert_eq!(replica.catch_up().unwrap(), MAX_FRAME_RECORDS + 9);
+    replica.close().unwrap();
+    server.join().unwrap().unwrap();
+
+    // nothing keeps the removal from compaction while the replica is away,
+    // not even after the primary is reopened.
+    {
+        let mut db = primary.lock().unwrap();
+        db.remove("key-0".to_string()).unwrap();
+        db.compact().unwrap();
+    }
+    let db = Arc::try_unwrap(primary).ok().unwrap().into_inner().unwrap();
+    db.close().unwrap();
+    let primary = Arc::new(Mutex::new(Database::open(name.to_string(), path).unwrap()));
+
+    let (primary_end, replica_end) = ChannelTransport::pair();
+    let serving = Arc::clone(&primary);
+    let server = thread::spawn(move || Primary::new(primary_end).serve(&serving));
+    let mut replica = Replica::open("replica".to_string(), replica_path, replica_end).unwrap();
+    assert!(matches!(replica.sync(), Err(DatabaseError::ResyncRequired)));
+    drop(replica);
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+pub fn test_replication_does_not_stop_key_rotation() {
+    let dir = test_dir("replication_rotation");
+    let path = dir.path().join("primary.onelink");
+    let db = Database::create_with_password(
+        "primary".to_string(),
+        path.to_str().unwrap().to_string(),
+        Preamble::new(),
+        2,
+        "hunter2",
+    )
+    .unwrap();
+    let primary = Arc::new(Mutex::new(db));
+    let replica_dir = test_dir("replication_rotation_replica");
+    let (primary_end, replica_end) = ChannelTransport::pair();
+    let serving = Arc::clone(&primary);
+    let server = thread::spawn(move || Primary::new(primary_end).serve(&serving));
+    let mut replica = Replica::create(
+        "replica".to_string(),
+        replica_path(&replica_dir),
+        Preamble::new_unsafe(),
+        1,
+        replica_end,
+    )
+    .unwrap();
+
+    primary
+        .lock()
+        .unwrap()
+        .set("a.txt".to_string(), b"one".to_vec())
+        .unwrap();
+    assert_eq!(replica.sync().unwrap(), 1);
+
+    // the records kept for the replica are not a snapshot.
+    {
+        let mut db = primary.lock().unwrap();
+        db.rotate_key().unwrap();
+        db.set("b.txt".to_string(), b"two".to_vec()).unwrap();
+    }
+    assert_eq!(replica.sync().unwrap(), 1);
+    assert_eq!(replica.get("b.txt".to_string()).unwrap().data, b"two");
+    drop(replica);
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+pub fn test_corrupted_frames_are_rejected() {
+    // a frame longer than the maximum fails, before it is allocated.
+    let mut wire = Cursor::new((MAX_FRAME_LEN as u32 + 1).to_be_bytes().to_vec());
+    assert!(StreamTransport::new(&mut wire).receive().is_err());
+    // so does a frame that ends before its length.
+    let mut wire = Cursor::new([100u32.to_be_bytes().to_vec(), vec![1, 2, 3]].concat());
+    assert!(StreamTransport::new(&mut wire).receive().is_err());
+
+    // a shipped record longer than the rest of the frame fails.
+    let mut frame = vec![1];
+    frame.extend_from_slice(&1u64.to_be_bytes());
+    frame.extend_from_slice(&0u128.to_be_bytes());
+    frame.extend_from_slice(&0u64.to_be_bytes());
+    frame.extend_from_slice(&1u32.to_be_bytes());
+    frame.push(0);
+    frame.extend_from_slice(&1u64.to_be_bytes());
+    frame.extend_from_slice(&1u64.to_be_bytes());
+    frame.extend_from_slice(&3u16.to_be_bytes());
+    frame.extend_from_slice(b"key");
+    frame.push(0);
+    frame.extend_from_slice(&(u128::MAX / 2).to_be_bytes());
+    frame.extend_from_slice(b"value");
+
+    let (mut primary_end, replica_end) = ChannelTransport::pair();
+    primary_end.send(frame).unwrap();
+    let replica_dir = test_dir("replication_corrupted");
+    let mut replica = Replica::create(
+        "replica".to_string(),
+        replica_path(&replica_dir),
+        Preamble::new_unsafe(),
+        0,
+        replica_end,
+    )
+    .unwrap();
+    assert!(matches!(
+        replica.sync(),
+        Err(DatabaseError::Implementation(message)) if message.contains("longer than")
+    ));
+    assert!(replica.fetch_keys().unwrap().is_empty());
+}
@@ -0,0 +1,124 @@
+use onelink_database::db::Database;
+use onelink_database::preamble::{EncryptionLevel, Preamble};
+use onelink_database::utils::InternalApi;
+use std::fs;
+use std::path::Path;
+
+use crate::common::{create_db, noise, test_dir};
+
+/// Whether or not any file in the directory of the database contains the bytes.
+fn stored_anywhere(db: &Database, bytes: &[u8]) -> bool {
+    let path = db.get_path();
+    fs::read_dir(Path::new(&path).parent().unwrap())
+        .unwrap()
+        .any(|entry| {
+            let data = fs::read(entry.unwrap().path()).unwrap();
+            data.windows(bytes.len()).any(|window| window == bytes)
+        })
+}
+
+#[test]
+pub fn test_secure_remove_overwrites_values() {
+    let (mut db, _dir) = create_db("scrub_remove", 2);
+    let (first, second, kept) = (noise(4096), noise(5000)[904..].to_vec(), noise(64));
+    db.set("plain".to_string(), first.clone()).unwrap();
+    db.remove("plain".to_string()).unwrap();
+    // without secure delete, the value stays until the partition is compacted.
+    assert!(stored_anywhere(&db, &first));
+
+    db.set_secure_delete(true).unwrap();
+    db.set("secret".to_string(), first.clone()).unwrap();
+    db.set("secret".to_string(), second.clone()).unwrap();
+    db.set("kept".to_string(), kept.clone()).unwrap();
+    assert!(db.remove("secret".to_string()).unwrap());
+    assert!(!stored_anywhere(&db, &second));
+    assert!(db.get("secret".to_string()).is_err());
+
+    // the removal of `plain` is older, so it is only overwritten by compaction.
+    assert!(stored_anywhere(&db, &first));
+    db.compact().unwrap();
+    assert!(!stored_anywhere(&db, &first));
+
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    let mut db = Database::open(name, path).unwrap();
+    assert_eq!(db.fetch_keys().unwrap().len(), 1);
+    assert_eq!(db.get("kept".to_string()).unwrap().data, kept);
+    db.set("secret".to_string(), b"again".to_vec()).unwrap();
+    assert_eq!(db.get("secret".to_string()).unwrap().data, b"again");
+}
+
+#[test]
+pub fn test_secure_delete_is_kept_after_reopening() {
+    let (mut db, _dir) = create_db("scrub_reopen", 2);
+    let value = noise(4096);
+    assert!(!db.secure_delete());
+    db.set_secure_delete(true).unwrap();
+    db.set("secret".to_string(), value.clone()).unwrap();
+
+    let (name, path) = (db.get_name().to_string(), db.get_path());
+    db.close().unwrap();
+    let mut db = Database::open(name, path).unwrap();
+    assert!(db.secure_delete());
+    assert!(db.remove("secret".to_string()).unwrap());
+    assert!(!stored_anywhere(&db, &value));
+    assert!(db.get("secret".to_string()).is_err());
+}
+
+#[test]
+pub fn test_scrub_free_space() {
+    let (mut db, _dir) = create_db("scrub_free_space", 0);
+    let (old, seen, current) = (noise(3000), noise(3500)[500..].to_vec(), noise(100));
+    db.set("a".to_string(), old.clone()).unwrap();
+    db.set("a".to_string(), seen.clone()).unwrap();
+    db.set("b".to_string(), vec![1; 10]).unwrap();
+    db.remove("b".to_string()).unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+    db.set("a".to_string(), current.clone()).unwrap();
+    let summary = db.scrub_free_space().unwrap();
+    assert_eq!(summary.records, 3);
+    assert!(summary.bytes >= old.len() as u64);
+    assert!(!stored_anywhere(&db, &old));
+    // the version the snapshot sees is kept until it is dropped.
+    assert_eq!(snapshot.get("a".to_string()).unwrap().data, seen);
+    drop(snapshot);
+
+    assert_eq!(db.scrub_free_space().unwrap().records, 1);
+    assert!(!stored_anywhere(&db, &seen));
+    assert_eq!(db.scrub_free_space().unwrap().records, 0);
+    assert_eq!(db.get("a".to_string()).unwrap().data, current);
+    assert_eq!(db.changes_since(0).unwrap().count(), 1);
+    db.compact().unwrap();
+    assert_eq!(db.fetch_keys().unwrap().len(), 1);
+}
+
+#[test]
+pub fn test_secure_remove_in_encrypted_partitions() {
+    for level in [EncryptionLevel::Names, EncryptionLevel::Full] {
+        let name = format!("scrub_encrypted_{}", level as u8);
+        let dir = test_dir(&name);
+        let path = dir.path().join(format!("{}.onelink", name));
+        let path = path.to_str().unwrap().to_string();
+        let mut preamble = Preamble::new();
+        preamble.encryption = level;
+        let mut db =
+            Database::create_with_password(name.clone(), path.clone(), preamble, 2, "hunter2")
+                .unwrap();
+        db.set_secure_delete(true).unwrap();
+        for i in 0..10 {
+            db.set(format!("file-{}", i), vec![i; 100]).unwrap();
+        }
+        db.set("file-3".to_string(), vec![33; 100]).unwrap();
+        assert!(db.remove("file-3".to_string()).unwrap());
+        db.scrub_free_space().unwrap();
+        drop(db);
+
+        let mut db = Database::open_with_password(name, path, "hunter2").unwrap();
+        assert_eq!(db.fetch_keys().unwrap().len(), 9);
+        assert!(db.get("file-3".to_string()).is_err());
+        assert_eq!(db.get("file-4".to_string()).unwrap().data, vec![4; 100]);
+        db.compact().unwrap();
+        assert_eq!(db.get("file-9".to_string()).unwrap().data, vec![9; 100]);
+    }
+}
@@ -0,0 +1,45 @@
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+
+use crate::common::{create_db, noise};
+
+#[test]
+pub fn test_snapshot_is_consistent() {
+    let (mut db, _dir) = create_db("snapshot_consistent", 2);
+    db.set("a".to_string(), b"old".to_vec()).unwrap();
+    db.set("b".to_string(), b"gone".to_vec()).unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+    db.set("a".to_string(), b"new".to_vec()).unwrap();
+    db.remove("b".to_string()).unwrap();
+    db.set("c".to_string(), b"later".to_vec()).unwrap();
+
+    assert_eq!(snapshot.get("a".to_string()).unwrap().data, b"old");
+    assert_eq!(snapshot.get("b".to_string()).unwrap().data, b"gone");
+    assert!(matches!(
+        snapshot.get("c".to_string()),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+    assert_eq!(snapshot.fetch_keys().unwrap().len(), 2);
+    assert_eq!(db.get("a".to_string()).unwrap().data, b"new");
+
+    // compaction keeps the versions the snapshot can see.
+    db.compact().unwrap();
+    assert_eq!(snapshot.get("a".to_string()).unwrap().data, b"old");
+    assert_eq!(snapshot.get("b".to_string()).unwrap().data, b"gone");
+    assert_eq!(db.get("a".to_string()).unwrap().data, b"new");
+}
+
+#[test]
+pub fn test_compact_after_snapshot_dropped() {
+    let (mut db, _dir) = create_db("snapshot_dropped", 0);
+    db.set("a".to_string(), noise(1024)).unwrap();
+    let snapshot = db.snapshot().unwrap();
+    db.set("a".to_string(), vec![1; 16]).unwrap();
+
+    assert_eq!(db.compact().unwrap(), 0);
+    drop(snapshot);
+    assert!(db.compact().unwrap() >= 1024);
+    assert_eq!(db.get("a".to_string()).unwrap().data, vec![1; 16]);
+    assert_eq!(db.fetch_keys().unwrap().len(), 1);
+}
@@ -0,0 +1,67 @@
+use onelink_database::db::DbDeviceOs;
+use onelink_database::utils::InternalApi;
+
+use crate::common::{create_db, noise};
+
+#[test]
+pub fn test_stats() {
+    let (mut db, _dir) = create_db("stats", 2);
+    let empty = db.stats().unwrap();
+    assert_eq!(empty.keys, 0);
+    assert_eq!(empty.compression_ratio, 1.0);
+    assert_eq!(empty.partitions.len(), 2);
+    assert_eq!(empty.fragmentation, 0.0);
+    assert_eq!(empty.created_on, DbDeviceOs::current());
+
+    db.set("a.txt".to_string(), vec![1; 100]).unwrap();
+    db.set("b.txt".to_string(), vec![2; 100]).unwrap();
+    db.set("a.txt".to_string(), vec![3; 100]).unwrap();
+    db.remove("b.txt".to_string()).unwrap();
+    db.create_namespace("photos".to_string()).unwrap();
+    db.set_in("photos", "c.jpg".to_string(), vec![4; 10])
+        .unwrap();
+    db.set_blob("d.bin".to_string(), vec![5; 10]).unwrap();
+
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.sequence, db.sequence().unwrap());
+    assert_eq!(stats.keys, 3);
+    assert_eq!(stats.namespaces, 1);
+    assert_eq!(
+        stats.partitions.iter().map(|p| p.records).sum::<u64>(),
+        stats.sequence
+    );
+    assert!(stats.dead_bytes > 0);
+    assert!(stats.fragmentation > 0.0 && stats.fragmentation < 1.0);
+    // the repeated bytes are compressed.
+    assert!(stats.compression_ratio < 1.0);
+    assert!(stats.last_write >= stats.last_open);
+
+    db.get("a.txt".to_string()).unwrap();
+    assert!(db.stats().unwrap().cache_hit_rate > 0.0);
+
+    db.compact().unwrap();
+    let compacted = db.stats().unwrap();
+    assert_eq!(compacted.dead_bytes, 0);
+    assert_eq!(compacted.live_bytes, stats.live_bytes);
+    assert_eq!(compacted.keys, 3);
+}
+
+#[test]
+pub fn test_dropped_namespace_is_dead() {
+    let (mut db, _dir) = create_db("stats_dropped_namespace", 0);
+    db.set("kept".to_string(), vec![1; 100]).unwrap();
+    db.create_namespace("photos".to_string()).unwrap();
+    db.set_in("photos", "a.jpg".to_string(), noise(4000))
+        .unwrap();
+    let before = db.stats().unwrap();
+    db.drop_namespace("photos".to_string()).unwrap();
+
+    // the values of the dropped namespace are reclaimed by the next compaction.
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.keys, 1);
+    assert!(stats.live_bytes < before.live_bytes);
+    assert!(stats.dead_bytes > before.dead_bytes + 4000 / 2);
+    let reclaimed = db.compact().unwrap();
+    assert!(reclaimed as u64 >= stats.dead_bytes);
+    assert_eq!(db.stats().unwrap().dead_bytes, 0);
+}
@@ -0,0 +1,133 @@
+use onelink_database::db::Database;
+use onelink_database::preamble::{EncryptionLevel, Preamble};
+use onelink_database::tiering::{StorageTiers, Tier, TieringPolicy};
+use onelink_database::utils::InternalApi;
+
+use tempfile::TempDir;
+
+use crate::common::test_dir;
+
+fn create_tiered(name: &str) -> (Database, StorageTiers, TempDir) {
+    let dir = test_dir(name);
+    let tiers = StorageTiers {
+        hot: vec![dir.path().join("hot")],
+        cold: vec![dir.path().join("cold")],
+    };
+    let db = Database::create_tiered(
+        name.to_string(),
+        dir.path()
+            .join(format!("{}.onelink", name))
+            .to_str()
+            .unwrap()
+            .to_string(),
+        Preamble::new_unsafe(),
+        2,
+        tiers.clone(),
+    )
+    .unwrap();
+    (db, tiers, dir)
+}
+
+#[test]
+pub fn test_move_partition_between_tiers() {
+    let (mut db, tiers, _dir) = create_tiered("tiering_move");
+    for i in 0..10 {
+        db.set(format!("{}.txt", i), vec![i; 100]).unwrap();
+    }
+    assert!(db
+        .placements()
+        .unwrap()
+        .iter()
+        .all(|placement| placement.tier == Tier::Hot && placement.directory == tiers.hot[0]));
+
+    let old_path = tiers.hot[0].join("tiering_move-0.bin");
+    assert!(old_path.exists());
+    let new_path = db.move_partition(0, Tier::Cold).unwrap();
+    assert_eq!(new_path, tiers.cold[0].join("tiering_move-0.bin"));
+    assert!(new_path.exists());
+    assert!(!old_path.exists());
+
+    for i in 0..10 {
+        assert_eq!(db.get(format!("{}.txt", i)).unwrap().data, vec![i; 100]);
+    }
+    db.set("0.txt".to_string(), b"moved".to_vec()).unwrap();
+
+    // the catalog remembers where the partition went.
+    let path = db.get_path();
+    db.close().unwrap();
+    let mut db = Database::open("tiering_move".to_string(), path).unwrap();
+    let placements = db.placements().unwrap();
+    assert_eq!(placements[0].tier, Tier::Cold);
+    assert_eq!(placements[0].directory, tiers.cold[0]);
+    assert_eq!(placements[1].tier, Tier::Hot);
+    assert_eq!(db.get("0.txt".to_string()).unwrap().data, b"moved");
+    assert_eq!(db.fetch_keys().unwrap().len(), 10);
+}
+
+#[test]
+pub fn test_apply_tiering() {
+    let (mut db, _, _dir) = create_tiered("tiering_policy");
+    db.set("a.txt".to_string(), b"one".to_vec()).unwrap();
+
+    // every partition is older than no time at all.
+    let demote = TieringPolicy {
+        cold_after: 0,
+        promote_after_reads: u64::MAX,
+    };
+    let moved = db.apply_tiering(&demote).unwrap();
+    assert_eq!(moved, vec![(0, Tier::Cold), (1, Tier::Cold)]);
+    assert!(db.apply_tiering(&demote).unwrap().is_empty());
+
+    // reading from a cold partition brings it back.
+    assert_eq!(db.get("a.txt".to_string()).unwrap().data, b"one");
+    let promote = TieringPolicy {
+        cold_after: u128::MAX,
+        promote_after_reads: 1,
+    };
+    let moved = db.apply_tiering(&promote).unwrap();
+    assert_eq!(moved.len(), 1);
+    assert_eq!(moved[0].1, Tier::Hot);
+    assert_eq!(db.get("a.txt".to_string()).unwrap().data, b"one");
+}
+
+#[test]
+pub fn test_move_encrypted_partition() {
+    let dir = test_dir("tiering_encrypted");
+    let path = dir.path().join("tiering_encrypted.onelink");
+    let mut preamble = Preamble::new();
+    preamble.encryption = EncryptionLevel::Full;
+    let mut db = Database::create_with_password(
+        "tiering_encrypted".to_string(),
+        path.to_str().unwrap().to_string(),
+        preamble,
+        2,
+        "hunter2",
+    )
+    .unwrap();
+    for i in 0..10 {
+        db.set(format!("{}.txt", i), vec![i; 100]).unwrap();
+    }
+    let tiers = StorageTiers {
+        hot: vec![dir.path().to_path_buf()],
+        cold: vec![dir.path().join("cold")],
+    };
+    db.set_tiers(tiers.clone()).unwrap();
+
+    let new_path = db.move_partition(1, Tier::Cold).unwrap();
+    assert_eq!(new_path, tiers.cold[0].join("tiering_encrypted-1.bin"));
+    for i in 0..10 {
+        assert_eq!(db.get(format!("{}.txt", i)).unwrap().data, vec![i; 100]);
+    }
+    db.set("1.txt".to_string(), b"moved".to_vec()).unwrap();
+    db.close().unwrap();
+
+    let mut db = Database::open_with_password(
+        "tiering_encrypted".to_string(),
+        path.to_str().unwrap().to_string(),
+        "hunter2",
+    )
+    .unwrap();
+    assert_eq!(db.placements().unwrap()[1].tier, Tier::Cold);
+    assert_eq!(db.get("1.txt".to_string()).unwrap().data, b"moved");
+    assert_eq!(db.fetch_keys().unwrap().len(), 10);
+}
@@ -0,0 +1,62 @@
+use onelink_database::db::Database;
+use onelink_database::utils::InternalApi;
+use onelink_database::DatabaseError;
+
+use crate::common::create_db;
+
+#[test]
+pub fn test_compare_and_set() {
+    let (mut db, _dir) = create_db("versions_cas", 2);
+    let key = db
+        .compare_and_set("file.txt".to_string(), 0, b"one".to_vec())
+        .unwrap();
+    assert_eq!(key.version, 1);
+    assert!(matches!(
+        db.compare_and_set("file.txt".to_string(), 0, b"two".to_vec()),
+        Err(DatabaseError::VersionConflict(_, 1))
+    ));
+
+    db.set("file.txt".to_string(), b"two".to_vec()).unwrap();
+    let item = db.get("file.txt".to_string()).unwrap();
+    assert_eq!(item.version, 2);
+    assert!(matches!(
+        db.compare_and_set("file.txt".to_string(), 1, b"stale".to_vec()),
+        Err(DatabaseError::VersionConflict(_, 2))
+    ));
+    let key = db
+        .compare_and_set("file.txt".to_string(), item.version, b"three".to_vec())
+        .unwrap();
+    assert_eq!(key.version, 3);
+    assert_eq!(db.get("file.txt".to_string()).unwrap().data, b"three");
+
+    // versions are stored with the records, so they survive reopening and compaction.
+    let path = db.get_path();
+    db.compact().unwrap();
+    db.close().unwrap();
+    let mut db = Database::open("versions_cas".to_string(), path).unwrap();
+    assert_eq!(db.get("file.txt".to_string()).unwrap().version, 3);
+}
+
+#[test]
+pub fn test_compare_and_remove() {
+    let (mut db, _dir) = create_db("versions_remove", 0);
+    db.set("file.txt".to_string(), b"one".to_vec()).unwrap();
+    db.set("file.txt".to_string(), b"two".to_vec()).unwrap();
+
+    assert!(matches!(
+        db.compare_and_remove("file.txt".to_string(), 1),
+        Err(DatabaseError::VersionConflict(_, 2))
+    ));
+    assert!(db.compare_and_remove("file.txt".to_string(), 2).unwrap());
+    assert!(matches!(
+        db.get("file.txt".to_string()),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+    assert!(!db.compare_and_remove("file.txt".to_string(), 0).unwrap());
+
+    // a key created again starts over at the first version.
+    let key = db
+        .compare_and_set("file.txt".to_string(), 0, b"again".to_vec())
+        .unwrap();
+    assert_eq!(key.version, 1);
+}